@@ -0,0 +1,331 @@
+//! Optional Bluetooth connection management, through BlueZ's D-Bus API.
+//!
+//! Everything here works with an arbitrary D-Bus connection (see
+//! [`Bluez::new`]), so it can be pointed at a stand-in BlueZ service running on
+//! a private bus.
+
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use dbus::arg::{RefArg, Variant};
+use dbus::blocking::stdintf::org_freedesktop_dbus::{ObjectManager, Properties};
+use dbus::blocking::{Connection, Proxy};
+
+use crate::error::{Error, Result};
+
+const BLUEZ: &str = "org.bluez";
+const ADAPTER_IFACE: &str = "org.bluez.Adapter1";
+const DEVICE_IFACE: &str = "org.bluez.Device1";
+const BATTERY_IFACE: &str = "org.bluez.Battery1";
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+/// Pairing / connecting can take a while (e.g: if the dial has to wake up).
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Dials advertise themselves under this name.
+const DIAL_NAME: &str = "Surface Dial";
+
+/// A Bluetooth device, as seen by BlueZ.
+#[derive(Debug, Clone)]
+pub struct BluezDevice {
+    pub path: dbus::Path<'static>,
+    /// e.g: `AA:BB:CC:DD:EE:FF`
+    pub address: String,
+    pub name: Option<String>,
+    pub paired: bool,
+    pub connected: bool,
+    /// Signal strength (in dBm). Only known while (or shortly after) BlueZ
+    /// is scanning for devices.
+    pub rssi: Option<i16>,
+    /// As a percentage.
+    pub battery: Option<u8>,
+}
+
+impl BluezDevice {
+    pub fn is_dial(&self) -> bool {
+        self.name
+            .as_deref()
+            .is_some_and(|name| name.starts_with(DIAL_NAME))
+    }
+}
+
+type Props = HashMap<String, Variant<Box<dyn RefArg + 'static>>>;
+
+pub struct Bluez {
+    conn: Connection,
+}
+
+impl Bluez {
+    /// Connect to BlueZ on the system bus.
+    pub fn system() -> Result<Bluez> {
+        Ok(Bluez::new(Connection::new_system().map_err(Error::Dbus)?))
+    }
+
+    pub fn new(conn: Connection) -> Bluez {
+        Bluez { conn }
+    }
+
+    fn proxy<'a>(&'a self, path: dbus::Path<'a>, timeout: Duration) -> Proxy<'a, &'a Connection> {
+        self.conn.with_proxy(BLUEZ, path, timeout)
+    }
+
+    fn objects(&self) -> Result<HashMap<dbus::Path<'static>, HashMap<String, Props>>> {
+        self.proxy("/".into(), TIMEOUT)
+            .get_managed_objects()
+            .map_err(Error::Dbus)
+    }
+
+    /// Every device BlueZ knows about (paired or otherwise).
+    pub fn devices(&self) -> Result<Vec<BluezDevice>> {
+        let devices = self
+            .objects()?
+            .into_iter()
+            .filter_map(|(path, ifaces)| decode_device(path, &ifaces));
+        Ok(devices.collect())
+    }
+
+    /// Look up a device by its Bluetooth address (case-insensitive).
+    pub fn device(&self, address: &str) -> Result<Option<BluezDevice>> {
+        Ok(self
+            .devices()?
+            .into_iter()
+            .find(|dev| dev.address.eq_ignore_ascii_case(address)))
+    }
+
+    /// Scan for an unpaired dial (i.e: one in pairing mode), giving up after
+    /// `timeout`.
+    pub fn discover(&self, timeout: Duration) -> Result<BluezDevice> {
+        let adapters = self
+            .objects()?
+            .into_iter()
+            .filter(|(_, ifaces)| ifaces.contains_key(ADAPTER_IFACE))
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>();
+
+        for adapter in adapters.iter() {
+            let proxy = self.proxy(adapter.clone(), TIMEOUT);
+            // not fatal, since another program might already be scanning
+            if let Err(e) = proxy.method_call::<(), _, _, _>(ADAPTER_IFACE, "StartDiscovery", ()) {
+                eprintln!("could not start discovery on {}: {}", adapter, e);
+            }
+        }
+
+        let deadline = Instant::now() + timeout;
+        let res = loop {
+            match self.devices() {
+                Ok(devices) => {
+                    if let Some(dial) = devices.into_iter().find(|d| d.is_dial() && !d.paired) {
+                        break Ok(dial);
+                    }
+                }
+                Err(e) => break Err(e),
+            }
+
+            if Instant::now() >= deadline {
+                break Err(Error::MissingDial);
+            }
+            std::thread::sleep(Duration::from_millis(500));
+        };
+
+        for adapter in adapters {
+            let proxy = self.proxy(adapter, TIMEOUT);
+            let _ = proxy.method_call::<(), _, _, _>(ADAPTER_IFACE, "StopDiscovery", ());
+        }
+
+        res
+    }
+
+    /// Pair with, trust, and connect to a dial.
+    pub fn pair(&self, device: &BluezDevice) -> Result<()> {
+        let proxy = self.proxy(device.path.clone(), CONNECT_TIMEOUT);
+        if !device.paired {
+            proxy
+                .method_call::<(), _, _, _>(DEVICE_IFACE, "Pair", ())
+                .map_err(Error::Dbus)?;
+        }
+
+        // trusted devices are allowed to reconnect by themselves
+        proxy
+            .set(DEVICE_IFACE, "Trusted", true)
+            .map_err(Error::Dbus)?;
+
+        self.connect(device)
+    }
+
+    pub fn connect(&self, device: &BluezDevice) -> Result<()> {
+        if device.connected {
+            return Ok(());
+        }
+
+        self.proxy(device.path.clone(), CONNECT_TIMEOUT)
+            .method_call::<(), _, _, _>(DEVICE_IFACE, "Connect", ())
+            .map_err(Error::Dbus)
+    }
+
+    /// Reconnect every paired dial which isn't currently connected, returning
+    /// their addresses.
+    pub fn reconnect_dials(&self) -> Result<Vec<String>> {
+        let mut reconnected = Vec::new();
+        for dial in self.devices()? {
+            if !dial.is_dial() || !dial.paired || dial.connected {
+                continue;
+            }
+
+            // the dial may simply be out of range / switched off
+            match self.connect(&dial) {
+                Ok(()) => reconnected.push(dial.address),
+                Err(e) => eprintln!("could not reconnect to {}: {}", dial.address, e),
+            }
+        }
+        Ok(reconnected)
+    }
+
+    /// Reconnect paired dials on startup, and whenever a message arrives on
+    /// `resumed` (i.e: after the system resumes from suspend).
+    ///
+    /// Blocks until `resumed` is disconnected (or an error occurs).
+    pub fn reconnect_loop(&self, resumed: mpsc::Receiver<()>) -> Result<()> {
+        let reconnect = || -> Result<()> {
+            for address in self.reconnect_dials()? {
+                eprintln!("reconnected to {}", address);
+            }
+            Ok(())
+        };
+
+        reconnect()?;
+        while resumed.recv().is_ok() {
+            // give the adapter a moment to come back up
+            std::thread::sleep(Duration::from_secs(2));
+            reconnect()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Decodes a managed object's properties (keyed by interface), if it's a
+/// device.
+fn decode_device(
+    path: dbus::Path<'static>,
+    ifaces: &HashMap<String, Props>,
+) -> Option<BluezDevice> {
+    let props = ifaces.get(DEVICE_IFACE)?;
+    Some(BluezDevice {
+        path,
+        address: string_prop(props, "Address")?,
+        name: string_prop(props, "Name"),
+        paired: bool_prop(props, "Paired"),
+        connected: bool_prop(props, "Connected"),
+        rssi: props
+            .get("RSSI")
+            .and_then(|v| v.0.as_i64())
+            .map(|rssi| rssi as i16),
+        battery: ifaces
+            .get(BATTERY_IFACE)
+            .and_then(|props| props.get("Percentage"))
+            .and_then(|v| v.0.as_u64())
+            .map(|percentage| percentage.min(100) as u8),
+    })
+}
+
+fn string_prop(props: &Props, key: &str) -> Option<String> {
+    props.get(key)?.0.as_str().map(String::from)
+}
+
+fn bool_prop(props: &Props, key: &str) -> bool {
+    // booleans are stored as 0 / 1
+    props
+        .get(key)
+        .and_then(|v| v.0.as_u64())
+        .is_some_and(|v| v != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn props(entries: Vec<(&str, Box<dyn RefArg>)>) -> Props {
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), Variant(value)))
+            .collect()
+    }
+
+    fn dial_props() -> Props {
+        props(vec![
+            ("Address", Box::new("AA:BB:CC:DD:EE:FF".to_string())),
+            ("Name", Box::new("Surface Dial".to_string())),
+            ("Paired", Box::new(true)),
+            ("Connected", Box::new(false)),
+            ("RSSI", Box::new(-60i16)),
+        ])
+    }
+
+    fn decode(ifaces: Vec<(&str, Props)>) -> Option<BluezDevice> {
+        let ifaces = ifaces
+            .into_iter()
+            .map(|(iface, props)| (iface.to_string(), props))
+            .collect();
+        decode_device("/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF".into(), &ifaces)
+    }
+
+    #[test]
+    fn decode_dial() {
+        let battery = props(vec![("Percentage", Box::new(80u8))]);
+        let dev = decode(vec![(DEVICE_IFACE, dial_props()), (BATTERY_IFACE, battery)]).unwrap();
+
+        assert_eq!(&*dev.path, "/org/bluez/hci0/dev_AA_BB_CC_DD_EE_FF");
+        assert_eq!(dev.address, "AA:BB:CC:DD:EE:FF");
+        assert_eq!(dev.name.as_deref(), Some("Surface Dial"));
+        assert!(dev.paired);
+        assert!(!dev.connected);
+        assert_eq!(dev.rssi, Some(-60));
+        assert_eq!(dev.battery, Some(80));
+        assert!(dev.is_dial());
+    }
+
+    #[test]
+    fn decode_minimal_device() {
+        let props = props(vec![("Address", Box::new("11:22:33:44:55:66".to_string()))]);
+        let dev = decode(vec![(DEVICE_IFACE, props)]).unwrap();
+
+        assert_eq!(dev.name, None);
+        assert!(!dev.paired);
+        assert!(!dev.connected);
+        assert_eq!(dev.rssi, None);
+        assert_eq!(dev.battery, None);
+        assert!(!dev.is_dial());
+    }
+
+    #[test]
+    fn decode_clamps_battery() {
+        let battery = props(vec![("Percentage", Box::new(255u8))]);
+        let dev = decode(vec![(DEVICE_IFACE, dial_props()), (BATTERY_IFACE, battery)]).unwrap();
+        assert_eq!(dev.battery, Some(100));
+    }
+
+    #[test]
+    fn decode_non_devices() {
+        // e.g: an adapter
+        let adapter = props(vec![("Address", Box::new("00:11:22:33:44:55".to_string()))]);
+        assert!(decode(vec![(ADAPTER_IFACE, adapter)]).is_none());
+
+        // devices always have an address
+        let nameless = props(vec![("Name", Box::new("Surface Dial".to_string()))]);
+        assert!(decode(vec![(DEVICE_IFACE, nameless)]).is_none());
+    }
+
+    #[test]
+    fn is_dial_by_name() {
+        let named = |name: &str| {
+            let mut props = dial_props();
+            props.insert("Name".to_string(), Variant(Box::new(name.to_string())));
+            decode(vec![(DEVICE_IFACE, props)]).unwrap()
+        };
+
+        assert!(named("Surface Dial").is_dial());
+        assert!(named("Surface Dial 2").is_dial());
+        assert!(!named("Mouse").is_dial());
+    }
+}
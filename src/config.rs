@@ -1,69 +1,1319 @@
+use std::ffi::OsStr;
+use std::fmt;
 use std::fs;
-use std::io::prelude::*;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use crate::controller::controls;
+use crate::dial_device::{HapticPattern, InputBackend, PATTERNS};
 use crate::error::{Error, Result};
+use crate::feedback::FeedbackBackend;
 
-// This current implementation is incredibly barebones and brittle.
-// It literally just reads/writes the last selected mode from the file as a raw
-// index into the modes array. That's it.
+// The config file is written in a (very) small subset of TOML:
 //
-// No TOML, No JSON, just raw text.
+// - `key = value` pairs, where values are strings, integers, or booleans
+// - `[table]` headers, and `[[array-of-tables]]` headers
+// - `#` comments
 //
-// While it wouldn't be too hard to get a proper serde-based implementation up
-// and running, it'll just bump compile times up for no good reason, so I'll
-// only set it up once I need the added complexity.
+// Hand-rolling a parser for this subset keeps the dependency tree (and compile
+// times) lean, while still allowing the file to be edited with any TOML-aware
+// editor.
 
-// TODO: stop using strings for errors lol
+const CONFIG_FILE_NAME: &str = "config.toml";
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Config {
-    pub last_mode: usize,
+    /// How long the button must be held to open the meta-menu.
+    pub long_press_timeout: Duration,
+    /// Modes (in meta-menu order).
+    pub modes: Vec<ModeConfig>,
+    pub haptics: HapticsConfig,
+    /// Show a warning once the battery level drops to this percentage (0
+    /// disables the warning).
+    pub low_battery_threshold: u8,
+    /// Where to read the dial's input events from. Only read on startup.
+    pub input_backend: InputBackend,
+    /// Grab the dial's evdev nodes exclusively, hiding its raw events from
+    /// other programs (e.g: libinput). Only read on startup.
+    pub grab_input: bool,
+    /// Reconnect paired dials (via BlueZ) on startup, and after resuming from
+    /// suspend. Only read on startup.
+    pub bluetooth_reconnect: bool,
+    /// How messages / the meta-menu are shown.
+    pub feedback: FeedbackBackend,
+    /// The program run by the `command` feedback backend.
+    pub feedback_command: Option<String>,
 }
 
-fn get_cfg_file() -> Result<fs::File> {
-    let proj_dirs = directories::ProjectDirs::from("com", "prilik", "surface-dial-daemon")
-        .ok_or_else(|| Error::ConfigFile("could not open config directory".into()))?;
-    let cfg_folder = proj_dirs.config_dir();
-    let cfg_file_path = proj_dirs.config_dir().join("config.txt");
+/// Which haptic patterns to play in response to various daemon events.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HapticsConfig {
+    /// Played when the meta-menu is opened.
+    pub meta_enter: String,
+    /// Played when a mode is selected from the meta-menu.
+    pub mode_select: String,
+    /// Played when the dial is turned past the end of a bounded range (e.g:
+    /// trying to turn up the volume past 100%).
+    pub limit_reached: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModeConfig {
+    /// Unique identifier, used to key persisted state. Defaults to `kind`.
+    pub id: String,
+    /// Which built-in mode to instantiate.
+    pub kind: String,
+    /// Override the mode's default haptic feedback setting.
+    pub haptics: Option<bool>,
+    /// Override the mode's default number of dial subdivisions.
+    pub steps: Option<u16>,
+    /// Restrict the mode to a single dial (identified by its Bluetooth
+    /// address). Modes without a `dial` are available on every dial.
+    pub dial: Option<String>,
+}
+
+impl ModeConfig {
+    fn new(kind: &str) -> ModeConfig {
+        ModeConfig {
+            id: kind.to_string(),
+            kind: kind.to_string(),
+            haptics: None,
+            steps: None,
+            dial: None,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            long_press_timeout: Duration::from_millis(750),
+            modes: controls::DEFAULT_MODES
+                .iter()
+                .copied()
+                .map(ModeConfig::new)
+                .collect(),
+            haptics: HapticsConfig {
+                meta_enter: "double-tap".into(),
+                mode_select: "double-tap".into(),
+                limit_reached: "limit-reached".into(),
+            },
+            low_battery_threshold: 15,
+            input_backend: InputBackend::Evdev,
+            grab_input: false,
+            bluetooth_reconnect: false,
+            feedback: FeedbackBackend::Notification,
+            feedback_command: None,
+        }
+    }
+}
+
+/// A problem with (or while accessing) the config file.
+#[derive(Debug)]
+pub struct ConfigError {
+    /// Path to the config file, if known.
+    pub path: Option<PathBuf>,
+    /// 1-based (line, column) of the offending bit of config, if applicable.
+    pub pos: Option<(usize, usize)>,
+    pub kind: ConfigErrorKind,
+}
+
+#[derive(Debug)]
+pub enum ConfigErrorKind {
+    NoConfigDir,
+    Io(io::Error),
+    Watch(nix::Error),
+
+    // syntax errors
+    ExpectedKeyValue,
+    UnterminatedTableHeader,
+    UnterminatedString,
+    InvalidEscape(char),
+    MissingValue,
+    InvalidValue(String),
+    TrailingCharacters,
+    InvalidKey(String),
+    InvalidTableName(String),
+    DuplicateKey(String),
+    DuplicateTable(String),
+
+    // semantic errors
+    UnknownKey(String),
+    UnknownSection(String),
+    TypeMismatch {
+        key: String,
+        expected: &'static str,
+        found: &'static str,
+    },
+    OutOfRange {
+        key: String,
+        min: i64,
+        max: i64,
+    },
+    UnknownModeType(String),
+    MissingModeType,
+    InvalidModeId(String),
+    DuplicateModeId(String),
+    UnknownHapticPattern(String),
+    UnknownInputBackend(String),
+    UnknownFeedbackBackend(String),
+    MissingFeedbackCommand,
+}
+
+impl ConfigError {
+    pub fn new(pos: Option<(usize, usize)>, kind: ConfigErrorKind) -> ConfigError {
+        ConfigError {
+            path: None,
+            pos,
+            kind,
+        }
+    }
+
+    fn io(path: &Path, e: io::Error) -> ConfigError {
+        ConfigError {
+            path: Some(path.to_owned()),
+            pos: None,
+            kind: ConfigErrorKind::Io(e),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.path, self.pos) {
+            (Some(path), Some((line, col))) => write!(f, "{}:{}:{}: ", path.display(), line, col)?,
+            (Some(path), None) => write!(f, "{}: ", path.display())?,
+            (None, Some((line, col))) => write!(f, "{}:{}: ", line, col)?,
+            (None, None) => {}
+        }
+
+        use self::ConfigErrorKind::*;
+        match &self.kind {
+            NoConfigDir => write!(f, "could not find the config directory"),
+            Io(e) => write!(f, "{}", e),
+            Watch(e) => write!(f, "could not watch the config file: {}", e),
 
-    fs::create_dir_all(cfg_folder)
-        .map_err(|e| Error::ConfigFile(format!("could not create config dir: {}", e)))?;
+            ExpectedKeyValue => write!(f, "expected `key = value`"),
+            UnterminatedTableHeader => write!(f, "unterminated table header"),
+            UnterminatedString => write!(f, "unterminated string"),
+            InvalidEscape(c) => write!(f, "invalid escape sequence \\{}", c),
+            MissingValue => write!(f, "missing value"),
+            InvalidValue(val) => write!(f, "invalid value {:?}", val),
+            TrailingCharacters => write!(f, "unexpected trailing characters"),
+            InvalidKey(key) => write!(
+                f,
+                "invalid key {:?} (keys may only contain letters, numbers, '-' and '_')",
+                key
+            ),
+            InvalidTableName(name) => write!(f, "invalid table name {:?}", name),
+            DuplicateKey(key) => write!(f, "duplicate key {:?}", key),
+            DuplicateTable(name) => write!(f, "duplicate table {:?}", name),
 
-    if !cfg_file_path.exists() {
-        fs::write(&cfg_file_path, "0")
-            .map_err(|e| Error::ConfigFile(format!("could not write to config file: {}", e)))?;
+            UnknownKey(key) => write!(f, "unknown key {:?}", key),
+            UnknownSection(name) => write!(f, "unknown section {:?}", name),
+            TypeMismatch {
+                key,
+                expected,
+                found,
+            } => write!(f, "expected {:?} to be {}, found {}", key, expected, found),
+            OutOfRange { key, min, max } => {
+                write!(f, "{:?} must be between {} and {}", key, min, max)
+            }
+            UnknownModeType(kind) => write!(
+                f,
+                "unknown mode type {:?} (expected one of: {})",
+                kind,
+                controls::ALL_MODES.join(", ")
+            ),
+            MissingModeType => write!(f, "mode is missing a \"type\" key"),
+            InvalidModeId(id) => write!(
+                f,
+                "invalid mode ID {:?} (IDs may only contain letters, numbers, '-' and '_')",
+                id
+            ),
+            DuplicateModeId(id) => write!(f, "duplicate mode ID {:?}", id),
+            UnknownHapticPattern(name) => write!(
+                f,
+                "unknown haptic pattern {:?} (expected one of: {})",
+                name,
+                PATTERNS
+                    .iter()
+                    .map(|p| p.name)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            UnknownInputBackend(name) => write!(
+                f,
+                "unknown input backend {:?} (expected one of: {})",
+                name,
+                InputBackend::NAMES.join(", ")
+            ),
+            UnknownFeedbackBackend(name) => write!(
+                f,
+                "unknown feedback backend {:?} (expected one of: {})",
+                name,
+                FeedbackBackend::NAMES.join(", ")
+            ),
+            MissingFeedbackCommand => write!(
+                f,
+                "the \"command\" feedback backend requires a \"feedback_command\" key"
+            ),
+        }
     }
+}
+
+/// A config file can contain more than one mistake, and it's much nicer to
+/// report them all at once.
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, e) in self.0.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", e)?;
+        }
+        Ok(())
+    }
+}
 
-    let cfg_file = fs::OpenOptions::new()
-        .write(true)
-        .read(true)
-        .open(cfg_file_path)
-        .map_err(|e| Error::ConfigFile(format!("could not open config file: {}", e)))?;
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Error {
+        Error::Config(ConfigErrors(vec![e]))
+    }
+}
 
-    Ok(cfg_file)
+pub fn config_file_path() -> std::result::Result<PathBuf, ConfigError> {
+    let proj_dirs = directories::ProjectDirs::from("com", "prilik", "surface-dial-daemon")
+        .ok_or_else(|| ConfigError::new(None, ConfigErrorKind::NoConfigDir))?;
+    Ok(proj_dirs.config_dir().join(CONFIG_FILE_NAME))
 }
 
 impl Config {
+    /// Load the config file, falling back to the default config if it doesn't
+    /// exist.
     pub fn from_disk() -> Result<Config> {
-        let mut cfg_file = get_cfg_file()?;
+        Config::from_file_or_default(&config_file_path()?)
+    }
+
+    /// Like [`Config::from_file`], but falls back to the default config if
+    /// `path` doesn't exist.
+    fn from_file_or_default(path: &Path) -> Result<Config> {
+        if !path.exists() {
+            return Ok(Config::default());
+        }
 
-        let mut content = String::new();
-        cfg_file
-            .read_to_string(&mut content)
-            .map_err(|e| Error::ConfigFile(format!("could not read the config file: {}", e)))?;
+        Config::from_file(path)
+    }
 
-        let last_mode = content
-            .parse()
-            .map_err(|e| Error::ConfigFile(format!("could not parse the config file: {}", e)))?;
+    /// Load and validate the config file at `path`.
+    pub fn from_file(path: &Path) -> Result<Config> {
+        let content = fs::read_to_string(path).map_err(|e| ConfigError::io(path, e))?;
 
-        Ok(Config { last_mode })
+        Config::parse(&content).map_err(|mut errors| {
+            for e in errors.iter_mut() {
+                e.path = Some(path.to_owned());
+            }
+            Error::Config(ConfigErrors(errors))
+        })
     }
 
-    pub fn to_disk(&self) -> Result<()> {
-        let mut cfg_file = get_cfg_file()?;
-        cfg_file
-            .write_all(format!("{}", self.last_mode).as_bytes())
-            .map_err(|e| Error::ConfigFile(format!("could not write to the config file: {}", e)))?;
+    /// The config as seen by a particular dial, i.e: only including modes which
+    /// are available on that dial.
+    ///
+    /// Falls back to the default modes if no modes are available.
+    pub fn for_dial(&self, dial_id: &str) -> Config {
+        let modes = self
+            .modes
+            .iter()
+            .filter(|mode| mode.dial.as_deref().is_none_or(|dial| dial == dial_id))
+            .cloned()
+            .collect::<Vec<_>>();
 
-        Ok(())
+        Config {
+            modes: if modes.is_empty() {
+                Config::default().modes
+            } else {
+                modes
+            },
+            ..self.clone()
+        }
+    }
+
+    /// Returns _all_ the errors in the file, not just the first one.
+    fn parse(content: &str) -> std::result::Result<Config, Vec<ConfigError>> {
+        let (doc, mut errors) = parse_document(content);
+
+        let mut config = Config::default();
+        // for reporting a missing `feedback_command`
+        let mut feedback_pos = None;
+
+        for entry in doc.root {
+            match entry.key.as_str() {
+                "long_press_timeout_ms" => match entry.integer_in_range(0, 60_000) {
+                    Ok(ms) => config.long_press_timeout = Duration::from_millis(ms as u64),
+                    Err(e) => errors.push(e),
+                },
+                "low_battery_threshold" => match entry.integer_in_range(0, 100) {
+                    Ok(percent) => config.low_battery_threshold = percent as u8,
+                    Err(e) => errors.push(e),
+                },
+                "input_backend" => match entry.input_backend() {
+                    Ok(backend) => config.input_backend = backend,
+                    Err(e) => errors.push(e),
+                },
+                "grab_input" => match entry.boolean() {
+                    Ok(grab) => config.grab_input = grab,
+                    Err(e) => errors.push(e),
+                },
+                "bluetooth_reconnect" => match entry.boolean() {
+                    Ok(reconnect) => config.bluetooth_reconnect = reconnect,
+                    Err(e) => errors.push(e),
+                },
+                "feedback" => match entry.feedback_backend() {
+                    Ok(backend) => {
+                        config.feedback = backend;
+                        feedback_pos = Some(entry.value_pos);
+                    }
+                    Err(e) => errors.push(e),
+                },
+                "feedback_command" => match entry.string() {
+                    Ok(program) => config.feedback_command = Some(program.to_string()),
+                    Err(e) => errors.push(e),
+                },
+                _ => errors.push(entry.unknown_key()),
+            }
+        }
+
+        let mut modes = Vec::new();
+        for section in doc.sections {
+            match (section.name.as_str(), section.is_array) {
+                ("mode", true) => match ModeConfig::from_section(section) {
+                    Ok(mode) => modes.push(mode),
+                    Err(e) => errors.extend(e),
+                },
+                ("haptics", false) => {
+                    for entry in section.entries {
+                        let field = match entry.key.as_str() {
+                            "meta_enter" => &mut config.haptics.meta_enter,
+                            "mode_select" => &mut config.haptics.mode_select,
+                            "limit_reached" => &mut config.haptics.limit_reached,
+                            _ => {
+                                errors.push(entry.unknown_key());
+                                continue;
+                            }
+                        };
+                        match entry.haptic_pattern() {
+                            Ok(name) => *field = name.to_string(),
+                            Err(e) => errors.push(e),
+                        }
+                    }
+                }
+                // already reported by the parser
+                (name, _) if !is_bare_key(name) => {}
+                _ => errors.push(ConfigError::new(
+                    Some(section.pos),
+                    ConfigErrorKind::UnknownSection(section.name),
+                )),
+            }
+        }
+
+        if config.feedback == FeedbackBackend::Command && config.feedback_command.is_none() {
+            errors.push(ConfigError::new(
+                feedback_pos,
+                ConfigErrorKind::MissingFeedbackCommand,
+            ));
+        }
+
+        for (i, (mode, pos)) in modes.iter().enumerate() {
+            if modes[..i].iter().any(|(m, _)| m.id == mode.id) {
+                errors.push(ConfigError::new(
+                    Some(*pos),
+                    ConfigErrorKind::DuplicateModeId(mode.id.clone()),
+                ));
+            }
+        }
+
+        if !errors.is_empty() {
+            errors.sort_by_key(|e| e.pos);
+            return Err(errors);
+        }
+
+        if !modes.is_empty() {
+            config.modes = modes.into_iter().map(|(mode, _)| mode).collect();
+        }
+
+        Ok(config)
+    }
+}
+
+impl ModeConfig {
+    /// Also returns the position of the section header, for error reporting.
+    fn from_section(
+        section: Section,
+    ) -> std::result::Result<(ModeConfig, (usize, usize)), Vec<ConfigError>> {
+        let mut errors = Vec::new();
+
+        let mut mode = ModeConfig::new("");
+        let mut kind = None;
+        let mut id = None;
+
+        for entry in section.entries.iter() {
+            match entry.key.as_str() {
+                "type" => match entry.string() {
+                    Ok(val) if controls::ALL_MODES.contains(&val) => kind = Some(val.to_string()),
+                    Ok(val) => errors.push(ConfigError::new(
+                        Some(entry.value_pos),
+                        ConfigErrorKind::UnknownModeType(val.to_string()),
+                    )),
+                    Err(e) => errors.push(e),
+                },
+                "id" => match entry.string() {
+                    Ok(val) if is_bare_key(val) => id = Some(val.to_string()),
+                    Ok(val) => errors.push(ConfigError::new(
+                        Some(entry.value_pos),
+                        ConfigErrorKind::InvalidModeId(val.to_string()),
+                    )),
+                    Err(e) => errors.push(e),
+                },
+                "haptics" => match entry.boolean() {
+                    Ok(val) => mode.haptics = Some(val),
+                    Err(e) => errors.push(e),
+                },
+                "steps" => match entry.integer_in_range(0, 3600) {
+                    Ok(val) => mode.steps = Some(val as u16),
+                    Err(e) => errors.push(e),
+                },
+                // Bluetooth addresses are case-insensitive
+                "dial" => match entry.string() {
+                    Ok(val) => mode.dial = Some(val.to_lowercase()),
+                    Err(e) => errors.push(e),
+                },
+                _ => errors.push(entry.unknown_key()),
+            }
+        }
+
+        match kind {
+            Some(kind) => mode.kind = kind,
+            // don't pile on if there _was_ a type, it just wasn't valid
+            None if section.entries.iter().any(|e| e.key == "type") => {}
+            None => errors.push(ConfigError::new(
+                Some(section.pos),
+                ConfigErrorKind::MissingModeType,
+            )),
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        mode.id = id.unwrap_or_else(|| mode.kind.clone());
+
+        Ok((mode, section.pos))
+    }
+}
+
+/// Watch the config file for changes, invoking `on_change` with the freshly
+/// loaded config whenever it's modified.
+///
+/// The file going missing isn't a change: editors which save by renaming /
+/// deleting the old file only leave it missing briefly, and reverting to the
+/// default config in the meantime would reset every dial.
+///
+/// Blocks forever (unless an error occurs).
+pub fn watch(mut on_change: impl FnMut(Result<Config>)) -> Result<()> {
+    use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+    let to_err = |e: nix::Error| ConfigError::new(None, ConfigErrorKind::Watch(e));
+
+    let path = config_file_path()?;
+    let dir = path.parent().unwrap();
+
+    // watch the parent directory, since editors tend to replace files wholesale
+    // (instead of writing to them in-place), and the file might not exist yet
+    fs::create_dir_all(dir).map_err(|e| ConfigError::io(dir, e))?;
+
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC).map_err(to_err)?;
+    inotify
+        .add_watch(
+            dir,
+            AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO,
+        )
+        .map_err(to_err)?;
+
+    loop {
+        let events = inotify.read_events().map_err(to_err)?;
+        let changed = events
+            .iter()
+            .any(|evt| evt.name.as_deref() == Some(OsStr::new(CONFIG_FILE_NAME)));
+
+        // the file may have been replaced again since the event was queued
+        if changed && path.exists() {
+            on_change(Config::from_file(&path));
+        }
+    }
+}
+
+/* ------------------------------ TOML subset ------------------------------ */
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "a string",
+            Value::Integer(_) => "an integer",
+            Value::Boolean(_) => "a boolean",
+        }
+    }
+}
+
+struct Entry {
+    key: String,
+    key_pos: (usize, usize),
+    value: Value,
+    value_pos: (usize, usize),
+}
+
+impl Entry {
+    fn type_mismatch(&self, expected: &'static str) -> ConfigError {
+        ConfigError::new(
+            Some(self.value_pos),
+            ConfigErrorKind::TypeMismatch {
+                key: self.key.clone(),
+                expected,
+                found: self.value.type_name(),
+            },
+        )
+    }
+
+    fn unknown_key(&self) -> ConfigError {
+        ConfigError::new(
+            Some(self.key_pos),
+            ConfigErrorKind::UnknownKey(self.key.clone()),
+        )
+    }
+
+    fn string(&self) -> std::result::Result<&str, ConfigError> {
+        match &self.value {
+            Value::String(s) => Ok(s),
+            _ => Err(self.type_mismatch("a string")),
+        }
+    }
+
+    fn haptic_pattern(&self) -> std::result::Result<&str, ConfigError> {
+        let name = self.string()?;
+        match HapticPattern::by_name(name) {
+            Some(_) => Ok(name),
+            None => Err(ConfigError::new(
+                Some(self.value_pos),
+                ConfigErrorKind::UnknownHapticPattern(name.to_string()),
+            )),
+        }
+    }
+
+    fn input_backend(&self) -> std::result::Result<InputBackend, ConfigError> {
+        let name = self.string()?;
+        InputBackend::from_name(name).ok_or_else(|| {
+            ConfigError::new(
+                Some(self.value_pos),
+                ConfigErrorKind::UnknownInputBackend(name.to_string()),
+            )
+        })
+    }
+
+    fn feedback_backend(&self) -> std::result::Result<FeedbackBackend, ConfigError> {
+        let name = self.string()?;
+        FeedbackBackend::from_name(name).ok_or_else(|| {
+            ConfigError::new(
+                Some(self.value_pos),
+                ConfigErrorKind::UnknownFeedbackBackend(name.to_string()),
+            )
+        })
+    }
+
+    fn boolean(&self) -> std::result::Result<bool, ConfigError> {
+        match self.value {
+            Value::Boolean(b) => Ok(b),
+            _ => Err(self.type_mismatch("a boolean")),
+        }
+    }
+
+    fn integer_in_range(&self, min: i64, max: i64) -> std::result::Result<i64, ConfigError> {
+        match self.value {
+            Value::Integer(i) if (min..=max).contains(&i) => Ok(i),
+            Value::Integer(_) => Err(ConfigError::new(
+                Some(self.value_pos),
+                ConfigErrorKind::OutOfRange {
+                    key: self.key.clone(),
+                    min,
+                    max,
+                },
+            )),
+            _ => Err(self.type_mismatch("an integer")),
+        }
+    }
+}
+
+struct Section {
+    name: String,
+    is_array: bool,
+    pos: (usize, usize),
+    entries: Vec<Entry>,
+}
+
+struct Document {
+    root: Vec<Entry>,
+    sections: Vec<Section>,
+}
+
+fn is_bare_key(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Malformed lines are skipped (and reported), so that a single typo doesn't
+/// hide every other error in the file.
+fn parse_document(src: &str) -> (Document, Vec<ConfigError>) {
+    let mut doc = Document {
+        root: Vec::new(),
+        sections: Vec::new(),
+    };
+    let mut errors = Vec::new();
+
+    for (idx, line) in src.lines().enumerate() {
+        let lineno = idx + 1;
+        // every `&str` below is a subslice of `line`, so its column can be
+        // recovered from its offset
+        let pos = |s: &str| {
+            let offset = s.as_ptr() as usize - line.as_ptr() as usize;
+            (lineno, line[..offset].chars().count() + 1)
+        };
+        let err = |s: &str, kind| ConfigError::new(Some(pos(s)), kind);
+
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        if let Some(rest) = trimmed.strip_prefix('[') {
+            let (is_array, rest) = match rest.strip_prefix('[') {
+                Some(rest) => (true, rest),
+                None => (false, rest),
+            };
+            let close = if is_array { "]]" } else { "]" };
+
+            let end = match rest.find(close) {
+                Some(end) => end,
+                None => {
+                    errors.push(err(trimmed, ConfigErrorKind::UnterminatedTableHeader));
+                    continue;
+                }
+            };
+            let name = rest[..end].trim();
+            let trailing = rest[end + close.len()..].trim();
+
+            if !is_bare_key(name) {
+                errors.push(err(
+                    name,
+                    ConfigErrorKind::InvalidTableName(name.to_string()),
+                ));
+            } else if !(trailing.is_empty() || trailing.starts_with('#')) {
+                errors.push(err(trailing, ConfigErrorKind::TrailingCharacters));
+            } else if !is_array && doc.sections.iter().any(|s| s.name == name) {
+                errors.push(err(
+                    trimmed,
+                    ConfigErrorKind::DuplicateTable(name.to_string()),
+                ));
+            }
+
+            // push the section even if the header is malformed, so that the
+            // keys which follow aren't misattributed to the previous section
+            doc.sections.push(Section {
+                name: name.to_string(),
+                is_array,
+                pos: pos(trimmed),
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        let eq = match trimmed.find('=') {
+            Some(eq) => eq,
+            None => {
+                errors.push(err(trimmed, ConfigErrorKind::ExpectedKeyValue));
+                continue;
+            }
+        };
+
+        let key = trimmed[..eq].trim();
+        if !is_bare_key(key) {
+            errors.push(err(key, ConfigErrorKind::InvalidKey(key.to_string())));
+            continue;
+        }
+
+        let value_str = trimmed[eq + 1..].trim_start();
+        let (value, rest) = match parse_value(value_str) {
+            Ok(res) => res,
+            Err((s, kind)) => {
+                errors.push(err(s, kind));
+                continue;
+            }
+        };
+
+        let rest = rest.trim();
+        if !(rest.is_empty() || rest.starts_with('#')) {
+            errors.push(err(rest, ConfigErrorKind::TrailingCharacters));
+            continue;
+        }
+
+        let entries = match doc.sections.last_mut() {
+            Some(section) => &mut section.entries,
+            None => &mut doc.root,
+        };
+
+        if entries.iter().any(|e| e.key == key) {
+            errors.push(err(key, ConfigErrorKind::DuplicateKey(key.to_string())));
+            continue;
+        }
+
+        entries.push(Entry {
+            key: key.to_string(),
+            key_pos: pos(key),
+            value,
+            value_pos: pos(value_str),
+        });
+    }
+
+    (doc, errors)
+}
+
+/// Parse a single value from the start of `s`, returning the value along with
+/// any remaining unparsed input.
+///
+/// On error, also returns the subslice of `s` where things went wrong.
+fn parse_value(s: &str) -> std::result::Result<(Value, &str), (&str, ConfigErrorKind)> {
+    if let Some(rest) = s.strip_prefix('"') {
+        let mut out = String::new();
+        let mut chars = rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => return Ok((Value::String(out), &rest[i + 1..])),
+                '\\' => match chars.next() {
+                    Some((_, 'n')) => out.push('\n'),
+                    Some((_, 't')) => out.push('\t'),
+                    Some((_, '"')) => out.push('"'),
+                    Some((_, '\\')) => out.push('\\'),
+                    Some((_, c)) => return Err((&rest[i..], ConfigErrorKind::InvalidEscape(c))),
+                    None => break,
+                },
+                c => out.push(c),
+            }
+        }
+        return Err((s, ConfigErrorKind::UnterminatedString));
+    }
+
+    let end = s
+        .find(|c: char| c.is_whitespace() || c == '#')
+        .unwrap_or(s.len());
+    let (token, rest) = s.split_at(end);
+
+    let value = match token {
+        "" => return Err((s, ConfigErrorKind::MissingValue)),
+        "true" => Value::Boolean(true),
+        "false" => Value::Boolean(false),
+        _ => match token.replace('_', "").parse() {
+            Ok(i) => Value::Integer(i),
+            Err(_) => return Err((s, ConfigErrorKind::InvalidValue(token.to_string()))),
+        },
+    };
+
+    Ok((value, rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use self::ConfigErrorKind::*;
+
+    fn parse(src: &str) -> Config {
+        Config::parse(src).unwrap_or_else(|errors| panic!("{}", ConfigErrors(errors)))
+    }
+
+    /// Asserts that `src` contains exactly one error, at `pos`.
+    fn assert_error(src: &str, pos: (usize, usize), check: impl Fn(&ConfigErrorKind) -> bool) {
+        let errors = match Config::parse(src) {
+            Ok(config) => panic!("expected an error, got {:?}", config),
+            Err(errors) => errors,
+        };
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].pos, Some(pos), "{:?}", errors[0]);
+        assert!(check(&errors[0].kind), "{:?}", errors[0]);
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("surface-dial-test-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn empty_file() {
+        assert_eq!(parse(""), Config::default());
+        assert_eq!(parse("\n  # just a comment\n\n"), Config::default());
+    }
+
+    #[test]
+    fn valid_file() {
+        let src = r#"
+# how long the button must be held to open the meta-menu
+long_press_timeout_ms = 1_000
+low_battery_threshold = 20 # percent
+input_backend = "hidraw"
+grab_input = true
+bluetooth_reconnect = true
+feedback = "command"
+feedback_command = "/usr/bin/dial-osd \"quoted\""
+
+[[mode]]
+type = "scroll"
+
+  [[ mode ]]  # indented, with whitespace inside the brackets
+  type = "volume"
+  steps = 36
+
+[[mode]]
+id = "fine-volume"
+type = "volume"
+steps = 3600
+haptics = false
+dial = "AA:BB:CC:DD:EE:FF"
+
+[ haptics ]
+meta_enter = "heartbeat"
+limit_reached = "error"
+"#;
+
+        let mode = |id: &str, kind: &str| ModeConfig {
+            id: id.into(),
+            kind: kind.into(),
+            ..ModeConfig::new(kind)
+        };
+
+        assert_eq!(
+            parse(src),
+            Config {
+                long_press_timeout: Duration::from_millis(1000),
+                modes: vec![
+                    mode("scroll", "scroll"),
+                    ModeConfig {
+                        steps: Some(36),
+                        ..mode("volume", "volume")
+                    },
+                    ModeConfig {
+                        steps: Some(3600),
+                        haptics: Some(false),
+                        dial: Some("aa:bb:cc:dd:ee:ff".into()),
+                        ..mode("fine-volume", "volume")
+                    },
+                ],
+                haptics: HapticsConfig {
+                    meta_enter: "heartbeat".into(),
+                    mode_select: "double-tap".into(),
+                    limit_reached: "error".into(),
+                },
+                low_battery_threshold: 20,
+                input_backend: InputBackend::Hidraw,
+                grab_input: true,
+                bluetooth_reconnect: true,
+                feedback: FeedbackBackend::Command,
+                feedback_command: Some("/usr/bin/dial-osd \"quoted\"".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn string_escapes() {
+        let config = parse(r#"feedback_command = "a\tb\nc\\d\"e#f" # comment"#);
+        assert_eq!(config.feedback_command.as_deref(), Some("a\tb\nc\\d\"e#f"));
+    }
+
+    #[test]
+    fn from_file() {
+        let path = temp_path("config.toml");
+        fs::write(&path, "low_battery_threshold = 5\n").unwrap();
+        let config = Config::from_file_or_default(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.unwrap().low_battery_threshold, 5);
+    }
+
+    #[test]
+    fn missing_file() {
+        let path = temp_path("missing.toml");
+        assert_eq!(
+            Config::from_file_or_default(&path).unwrap(),
+            Config::default()
+        );
+
+        match Config::from_file(&path) {
+            Err(Error::Config(ConfigErrors(errors))) => {
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].path.as_deref(), Some(path.as_path()));
+                assert_eq!(errors[0].pos, None);
+                assert!(matches!(errors[0].kind, Io(_)));
+            }
+            res => panic!("expected an I/O error, got {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn errors_include_path() {
+        let path = temp_path("invalid.toml");
+        fs::write(&path, "\n  grab_input = 1\n").unwrap();
+        let res = Config::from_file(&path);
+        fs::remove_file(&path).unwrap();
+
+        match res {
+            Err(Error::Config(errors)) => assert_eq!(
+                errors.to_string(),
+                format!(
+                    "{}:2:16: expected \"grab_input\" to be a boolean, found an integer",
+                    path.display()
+                )
+            ),
+            res => panic!("expected a config error, got {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_error("grab_input", (1, 1), |k| matches!(k, ExpectedKeyValue));
+        assert_error("  grab_input", (1, 3), |k| matches!(k, ExpectedKeyValue));
+        assert_error("[haptics", (1, 1), |k| matches!(k, UnterminatedTableHeader));
+        assert_error("[[mode]", (1, 1), |k| matches!(k, UnterminatedTableHeader));
+        assert_error(r#"feedback = "log"#, (1, 12), |k| {
+            matches!(k, UnterminatedString)
+        });
+        assert_error(r#"feedback_command = "a\qb""#, (1, 22), |k| {
+            matches!(k, InvalidEscape('q'))
+        });
+        assert_error("grab_input =", (1, 13), |k| matches!(k, MissingValue));
+        assert_error("grab_input = # comment", (1, 14), |k| {
+            matches!(k, MissingValue)
+        });
+        assert_error(
+            "grab_input = yes",
+            (1, 14),
+            |k| matches!(k, InvalidValue(v) if v == "yes"),
+        );
+        assert_error("grab_input = true false", (1, 19), |k| {
+            matches!(k, TrailingCharacters)
+        });
+        assert_error("[haptics] x", (1, 11), |k| matches!(k, TrailingCharacters));
+        assert_error(
+            "grab input = true",
+            (1, 1),
+            |k| matches!(k, InvalidKey(key) if key == "grab input"),
+        );
+        assert_error(
+            "[ha ptics]",
+            (1, 2),
+            |k| matches!(k, InvalidTableName(name) if name == "ha ptics"),
+        );
+        assert_error(
+            "[[ mode! ]]",
+            (1, 4),
+            |k| matches!(k, InvalidTableName(name) if name == "mode!"),
+        );
+        assert_error(
+            "[]",
+            (1, 2),
+            |k| matches!(k, InvalidTableName(name) if name.is_empty()),
+        );
+        assert_error(
+            "grab_input = true\n grab_input = false",
+            (2, 2),
+            |k| matches!(k, DuplicateKey(key) if key == "grab_input"),
+        );
+        assert_error(
+            "[haptics]\n\n[haptics]",
+            (3, 1),
+            |k| matches!(k, DuplicateTable(name) if name == "haptics"),
+        );
+    }
+
+    #[test]
+    fn table_headers() {
+        // `[[` is only an array header when the brackets are adjacent
+        assert_error(
+            "[ [mode]]",
+            (1, 3),
+            |k| matches!(k, InvalidTableName(name) if name == "[mode"),
+        );
+        // `[[mode]]` isn't a table, and `[haptics]` isn't an array
+        assert_error(
+            "[mode]",
+            (1, 1),
+            |k| matches!(k, UnknownSection(name) if name == "mode"),
+        );
+        assert_error(
+            "[[haptics]]",
+            (1, 1),
+            |k| matches!(k, UnknownSection(name) if name == "haptics"),
+        );
+        // arrays of tables can (of course) be repeated
+        assert_eq!(
+            parse("[[mode]]\ntype = \"zoom\"\n[[mode]]\ntype = \"paddle\"")
+                .modes
+                .len(),
+            2
+        );
+    }
+
+    #[test]
+    fn semantic_errors() {
+        assert_error(
+            "foo = 1",
+            (1, 1),
+            |k| matches!(k, UnknownKey(key) if key == "foo"),
+        );
+        assert_error(
+            "[haptics]\n  foo = \"tick\"",
+            (2, 3),
+            |k| matches!(k, UnknownKey(key) if key == "foo"),
+        );
+        assert_error(
+            "[[mode]]\ntype = \"zoom\"\nfoo = 1",
+            (3, 1),
+            |k| matches!(k, UnknownKey(key) if key == "foo"),
+        );
+        assert_error(
+            "\n[bar]",
+            (2, 1),
+            |k| matches!(k, UnknownSection(name) if name == "bar"),
+        );
+        assert_error("grab_input = 1", (1, 14), |k| {
+            matches!(
+                k,
+                TypeMismatch { key, expected: "a boolean", found: "an integer" } if key == "grab_input"
+            )
+        });
+        assert_error("low_battery_threshold = \"low\"", (1, 25), |k| {
+            matches!(
+                k,
+                TypeMismatch {
+                    expected: "an integer",
+                    found: "a string",
+                    ..
+                }
+            )
+        });
+        assert_error("feedback = true", (1, 12), |k| {
+            matches!(
+                k,
+                TypeMismatch {
+                    expected: "a string",
+                    found: "a boolean",
+                    ..
+                }
+            )
+        });
+        assert_error(
+            "[[mode]]\ntype = \"zoom\"\nsteps = 3601",
+            (3, 9),
+            |k| matches!(k, OutOfRange { key, min: 0, max: 3600 } if key == "steps"),
+        );
+        assert_error("[[mode]]\ntype = \"zoom\"\nsteps = -1", (3, 9), |k| {
+            matches!(k, OutOfRange { .. })
+        });
+        assert_error("long_press_timeout_ms = 60_001", (1, 25), |k| {
+            matches!(
+                k,
+                OutOfRange {
+                    min: 0,
+                    max: 60_000,
+                    ..
+                }
+            )
+        });
+        assert_error("low_battery_threshold = 101", (1, 25), |k| {
+            matches!(
+                k,
+                OutOfRange {
+                    min: 0,
+                    max: 100,
+                    ..
+                }
+            )
+        });
+        assert_error(
+            "[[mode]]\ntype = \"nope\"",
+            (2, 8),
+            |k| matches!(k, UnknownModeType(kind) if kind == "nope"),
+        );
+        assert_error("\n[[mode]]\nsteps = 10", (2, 1), |k| {
+            matches!(k, MissingModeType)
+        });
+        assert_error(
+            "[[mode]]\ntype = \"zoom\"\nid = \"my zoom\"",
+            (3, 6),
+            |k| matches!(k, InvalidModeId(id) if id == "my zoom"),
+        );
+        assert_error(
+            "[haptics]\nmeta_enter = \"nope\"",
+            (2, 14),
+            |k| matches!(k, UnknownHapticPattern(name) if name == "nope"),
+        );
+        assert_error(
+            "input_backend = \"nope\"",
+            (1, 17),
+            |k| matches!(k, UnknownInputBackend(name) if name == "nope"),
+        );
+        assert_error(
+            "feedback = \"nope\"",
+            (1, 12),
+            |k| matches!(k, UnknownFeedbackBackend(name) if name == "nope"),
+        );
+        assert_error("feedback = \"command\"", (1, 12), |k| {
+            matches!(k, MissingFeedbackCommand)
+        });
+    }
+
+    #[test]
+    fn duplicate_mode_ids() {
+        let src = "[[mode]]\ntype = \"volume\"\n\n[[mode]]\ntype = \"volume\"";
+        assert_error(
+            src,
+            (4, 1),
+            |k| matches!(k, DuplicateModeId(id) if id == "volume"),
+        );
+
+        let src = "[[mode]]\ntype = \"zoom\"\nid = \"x\"\n[[mode]]\ntype = \"volume\"\nid = \"x\"";
+        assert_error(
+            src,
+            (4, 1),
+            |k| matches!(k, DuplicateModeId(id) if id == "x"),
+        );
+
+        // distinct IDs are fine
+        let src = "[[mode]]\ntype = \"volume\"\n[[mode]]\ntype = \"volume\"\nid = \"fine-volume\"";
+        assert_eq!(parse(src).modes.len(), 2);
+    }
+
+    #[test]
+    fn invalid_type_doesnt_pile_on() {
+        // i.e: no MissingModeType on top of the UnknownModeType
+        assert_error("[[mode]]\ntype = 1", (2, 8), |k| {
+            matches!(k, TypeMismatch { .. })
+        });
+    }
+
+    #[test]
+    fn every_error_reported_in_order() {
+        let src = "
+[[mode]]
+type = \"zoom\"
+steps = 9000
+oops
+
+[bar]
+grab_input = 1
+";
+        let errors = Config::parse(src).unwrap_err();
+        let positions = errors.iter().map(|e| e.pos).collect::<Vec<_>>();
+        assert_eq!(positions, [Some((4, 9)), Some((5, 1)), Some((7, 1))]);
+        // `grab_input` belongs to `[bar]` (which is reported as a whole), not
+        // the root table
+        assert!(matches!(&errors[2].kind, UnknownSection(name) if name == "bar"));
+    }
+
+    #[test]
+    fn columns_count_chars() {
+        // `é` is two bytes, but one column
+        assert_error("feedback_command = \"é\" x", (1, 24), |k| {
+            matches!(k, TrailingCharacters)
+        });
+    }
+
+    #[test]
+    fn for_dial() {
+        let config = parse(
+            r#"
+low_battery_threshold = 30
+
+[[mode]]
+type = "scroll"
+
+[[mode]]
+type = "volume"
+dial = "AA:BB:CC:DD:EE:FF"
+
+[[mode]]
+type = "zoom"
+dial = "11:22:33:44:55:66"
+
+[[mode]]
+type = "media"
+
+[haptics]
+meta_enter = "tick"
+"#,
+        );
+        let ids = |dial_id: &str| {
+            config
+                .for_dial(dial_id)
+                .modes
+                .into_iter()
+                .map(|mode| mode.id)
+                .collect::<Vec<_>>()
+        };
+
+        // global modes are shared, and the config's order is kept
+        assert_eq!(ids("aa:bb:cc:dd:ee:ff"), ["scroll", "volume", "media"]);
+        assert_eq!(ids("11:22:33:44:55:66"), ["scroll", "zoom", "media"]);
+        assert_eq!(ids("some-other-dial"), ["scroll", "media"]);
+
+        // everything else is the same for every dial
+        let dial_config = config.for_dial("aa:bb:cc:dd:ee:ff");
+        assert_eq!(dial_config.low_battery_threshold, 30);
+        assert_eq!(dial_config.haptics, config.haptics);
+    }
+
+    #[test]
+    fn for_dial_without_modes() {
+        let config = parse("[[mode]]\ntype = \"volume\"\ndial = \"aa:bb:cc:dd:ee:ff\"");
+
+        assert_eq!(config.for_dial("aa:bb:cc:dd:ee:ff").modes.len(), 1);
+        assert_eq!(
+            config.for_dial("11:22:33:44:55:66").modes,
+            Config::default().modes
+        );
+    }
+
+    #[test]
+    fn display_positions() {
+        let mut e = ConfigError::new(Some((3, 7)), MissingValue);
+        assert_eq!(e.to_string(), "3:7: missing value");
+        e.path = Some("config.toml".into());
+        assert_eq!(e.to_string(), "config.toml:3:7: missing value");
+        e.pos = None;
+        assert_eq!(e.to_string(), "config.toml: missing value");
+
+        let e = ConfigError::new(None, NoConfigDir);
+        assert_eq!(e.to_string(), "could not find the config directory");
+        let e = ConfigError::new(None, Watch(nix::Error::Sys(nix::errno::Errno::EACCES)));
+        assert!(e
+            .to_string()
+            .starts_with("could not watch the config file: "));
     }
 }
@@ -0,0 +1,176 @@
+/// A bounded range of logical positions, tracked by the daemon on behalf of a
+/// [`ControlMode`].
+///
+/// Once the position reaches either end of the range, the dial's haptics are
+/// switched into a distinct state, so that users can _feel_ the limit.
+/// Rotating the dial past the limit is still forwarded to the mode, as the
+/// position is only an approximation of the underlying value.
+///
+/// NOTE: the position is only updated by the dial itself. If the underlying
+/// value can be changed some other way (e.g: via volume keys on a keyboard),
+/// modes should periodically resync using [`ModeContext::set_position`].
+///
+/// [`ControlMode`]: super::ControlMode
+/// [`ModeContext::set_position`]: super::ModeContext::set_position
+#[derive(Debug)]
+pub struct Bounds {
+    min: i32,
+    max: i32,
+    pos: i32,
+    /// Set while the user is pushing against one of the limits.
+    pushing: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Motion {
+    /// The position changed (though possibly by less than the full delta).
+    Moved,
+    /// The position is pinned against a limit. `first` is set if this is the
+    /// first event to push against the limit.
+    Blocked { first: bool },
+}
+
+impl Bounds {
+    pub(super) fn new(min: i32, max: i32, pos: i32) -> Bounds {
+        let mut bounds = Bounds {
+            min,
+            max: max.max(min),
+            pos: 0,
+            pushing: false,
+        };
+        bounds.set_position(pos);
+        bounds
+    }
+
+    pub fn position(&self) -> i32 {
+        self.pos
+    }
+
+    pub(super) fn set_position(&mut self, pos: i32) {
+        self.pos = pos.max(self.min).min(self.max);
+    }
+
+    /// Returns `true` if the position is at either end of the range.
+    pub fn at_limit(&self) -> bool {
+        self.pos == self.min || self.pos == self.max
+    }
+
+    pub(super) fn apply(&mut self, delta: i32) -> Motion {
+        let old = self.pos;
+        self.set_position(old.saturating_add(delta));
+
+        if self.pos != old {
+            self.pushing = false;
+            Motion::Moved
+        } else {
+            let first = !self.pushing;
+            self.pushing = true;
+            Motion::Blocked { first }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use Motion::*;
+
+    #[test]
+    fn moved_within_range() {
+        let mut bounds = Bounds::new(0, 100, 50);
+
+        assert_eq!(bounds.apply(1), Moved);
+        assert_eq!(bounds.position(), 51);
+        assert_eq!(bounds.apply(-11), Moved);
+        assert_eq!(bounds.position(), 40);
+        assert!(!bounds.at_limit());
+    }
+
+    #[test]
+    fn clamped_to_limit() {
+        let mut bounds = Bounds::new(0, 100, 95);
+
+        // moves as far as it can
+        assert_eq!(bounds.apply(10), Moved);
+        assert_eq!(bounds.position(), 100);
+        assert!(bounds.at_limit());
+    }
+
+    #[test]
+    fn blocked_at_limit() {
+        let mut bounds = Bounds::new(0, 100, 99);
+
+        assert_eq!(bounds.apply(1), Moved);
+        assert_eq!(bounds.apply(1), Blocked { first: true });
+        assert_eq!(bounds.apply(1), Blocked { first: false });
+        assert_eq!(bounds.apply(5), Blocked { first: false });
+        assert_eq!(bounds.position(), 100);
+    }
+
+    #[test]
+    fn blocked_at_min() {
+        let mut bounds = Bounds::new(0, 100, 0);
+
+        assert!(bounds.at_limit());
+        assert_eq!(bounds.apply(-1), Blocked { first: true });
+        assert_eq!(bounds.apply(-1), Blocked { first: false });
+        assert_eq!(bounds.position(), 0);
+    }
+
+    #[test]
+    fn backing_off_limit() {
+        let mut bounds = Bounds::new(0, 100, 100);
+
+        assert_eq!(bounds.apply(1), Blocked { first: true });
+        assert_eq!(bounds.apply(-1), Moved);
+        assert_eq!(bounds.position(), 99);
+        assert!(!bounds.at_limit());
+
+        // pushing against the limit again counts as a new push
+        assert_eq!(bounds.apply(1), Moved);
+        assert_eq!(bounds.apply(1), Blocked { first: true });
+    }
+
+    #[test]
+    fn no_overflow() {
+        let mut bounds = Bounds::new(i32::MIN, i32::MAX, i32::MAX);
+
+        assert_eq!(bounds.apply(i32::MAX), Blocked { first: true });
+        assert_eq!(bounds.position(), i32::MAX);
+    }
+
+    #[test]
+    fn resync() {
+        // e.g: the volume mode's bounds, with the volume changed by another
+        // program
+        let mut bounds = Bounds::new(0, 100, 50);
+        assert_eq!(bounds.apply(2), Moved);
+
+        bounds.set_position(100);
+        assert!(bounds.at_limit());
+        assert_eq!(bounds.apply(1), Blocked { first: true });
+
+        // resynced away from the limit while pushing against it
+        bounds.set_position(80);
+        assert!(!bounds.at_limit());
+        assert_eq!(bounds.apply(1), Moved);
+        assert_eq!(bounds.position(), 81);
+
+        // out-of-range readings are clamped
+        bounds.set_position(150);
+        assert_eq!(bounds.position(), 100);
+        bounds.set_position(-5);
+        assert_eq!(bounds.position(), 0);
+    }
+
+    #[test]
+    fn initial_position_clamped() {
+        assert_eq!(Bounds::new(0, 100, 200).position(), 100);
+        assert_eq!(Bounds::new(0, 100, -1).position(), 0);
+        // an empty range is pinned to `min`
+        let mut bounds = Bounds::new(10, 0, 5);
+        assert_eq!(bounds.position(), 10);
+        assert_eq!(bounds.apply(1), Blocked { first: true });
+    }
+}
@@ -1,7 +1,5 @@
-use crate::controller::{ControlMode, ControlModeMeta};
-use crate::dial_device::DialHaptics;
+use crate::controller::{ControlMode, ControlModeMeta, ModeContext};
 use crate::error::{Error, Result};
-use crate::fake_input;
 
 use evdev_rs::enums::EV_KEY;
 
@@ -23,22 +21,28 @@ impl ControlMode for Media {
         }
     }
 
-    fn on_btn_press(&mut self, _: &DialHaptics) -> Result<()> {
+    fn on_btn_press(&mut self, _: &mut ModeContext) -> Result<()> {
         Ok(())
     }
 
-    fn on_btn_release(&mut self, _: &DialHaptics) -> Result<()> {
-        fake_input::key_click(&[EV_KEY::KEY_PLAYPAUSE]).map_err(Error::Evdev)?;
+    fn on_btn_release(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        ctx.output()
+            .key_click(&[EV_KEY::KEY_PLAYPAUSE])
+            .map_err(Error::Evdev)?;
         Ok(())
     }
 
-    fn on_dial(&mut self, _: &DialHaptics, delta: i32) -> Result<()> {
+    fn on_dial(&mut self, ctx: &mut ModeContext, delta: i32) -> Result<()> {
         if delta > 0 {
             eprintln!("next song");
-            fake_input::key_click(&[EV_KEY::KEY_NEXTSONG]).map_err(Error::Evdev)?;
+            ctx.output()
+                .key_click(&[EV_KEY::KEY_NEXTSONG])
+                .map_err(Error::Evdev)?;
         } else {
             eprintln!("last song");
-            fake_input::key_click(&[EV_KEY::KEY_PREVIOUSSONG]).map_err(Error::Evdev)?;
+            ctx.output()
+                .key_click(&[EV_KEY::KEY_PREVIOUSSONG])
+                .map_err(Error::Evdev)?;
         }
         Ok(())
     }
@@ -1,63 +1,109 @@
-use std::sync::mpsc;
 use std::time::Duration;
 
-use crate::controller::{ControlMode, ControlModeMeta};
-use crate::dial_device::DialHaptics;
+use crate::controller::{ControlMode, ControlModeMeta, ModeContext, TimerId, Timers};
 use crate::error::{Error, Result};
-use crate::fake_input;
 
 use evdev_rs::enums::EV_KEY;
 
-fn double_click_worker(click: mpsc::Receiver<()>, release: mpsc::Receiver<()>) -> Result<()> {
-    loop {
-        // drain any spurious clicks/releases
-        for _ in click.try_iter() {}
-        for _ in release.try_iter() {}
-
-        click.recv().unwrap();
-        // recv with timeout, in case this is a long-press
-        match release.recv_timeout(Duration::from_secs(1)) {
-            Ok(()) => {}
-            Err(mpsc::RecvTimeoutError::Timeout) => continue,
-            Err(mpsc::RecvTimeoutError::Disconnected) => panic!(),
+const DOUBLE_CLICK: TimerId = 0;
+
+enum ClickState {
+    Idle,
+    Pressed(Duration),
+    /// Waiting to see if a second click is coming.
+    Released,
+    SecondPress,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Click {
+    Single,
+    Double,
+}
+
+/// Tells single clicks apart from double clicks (and from long presses).
+struct ClickTracker {
+    // presses held for longer than this aren't considered clicks
+    click_timeout: Duration,
+    double_click_window: Duration,
+
+    state: ClickState,
+}
+
+impl ClickTracker {
+    fn new() -> ClickTracker {
+        ClickTracker {
+            click_timeout: Duration::from_secs(1),
+            double_click_window: Duration::from_millis(250),
+
+            state: ClickState::Idle,
         }
+    }
 
-        match click.recv_timeout(Duration::from_millis(250)) {
-            Ok(()) => {
-                // double click
-                release.recv().unwrap(); // should only fire after button is released
-                eprintln!("next track");
-                fake_input::key_click(&[EV_KEY::KEY_NEXTSONG]).map_err(Error::Evdev)?;
+    fn reset(&mut self) {
+        self.state = ClickState::Idle;
+    }
+
+    fn on_timer(&mut self, id: TimerId) -> Option<Click> {
+        if id == DOUBLE_CLICK && matches!(self.state, ClickState::Released) {
+            self.state = ClickState::Idle;
+            return Some(Click::Single);
+        }
+        None
+    }
+
+    fn on_press(&mut self, timers: &mut Timers) {
+        self.state = match self.state {
+            ClickState::Released => {
+                timers.cancel(DOUBLE_CLICK);
+                ClickState::SecondPress
             }
-            Err(mpsc::RecvTimeoutError::Timeout) => {
-                // single click
-                eprintln!("play/pause");
-                fake_input::key_click(&[EV_KEY::KEY_PLAYPAUSE]).map_err(Error::Evdev)?;
+            _ => ClickState::Pressed(timers.now()),
+        };
+    }
+
+    fn on_release(&mut self, timers: &mut Timers) -> Option<Click> {
+        let (state, click) = match self.state {
+            ClickState::Pressed(t) if timers.now().saturating_sub(t) < self.click_timeout => {
+                timers.schedule_once(DOUBLE_CLICK, self.double_click_window);
+                (ClickState::Released, None)
             }
-            Err(mpsc::RecvTimeoutError::Disconnected) => panic!(),
-        }
+            ClickState::SecondPress => (ClickState::Idle, Some(Click::Double)),
+            _ => (ClickState::Idle, None),
+        };
+        self.state = state;
+        click
     }
 }
 
 pub struct MediaWithVolume {
-    click_tx: mpsc::Sender<()>,
-    release_tx: mpsc::Sender<()>,
-    worker_handle: Option<std::thread::JoinHandle<Result<()>>>,
+    clicks: ClickTracker,
 }
 
 impl MediaWithVolume {
     pub fn new() -> MediaWithVolume {
-        let (click_tx, click_rx) = mpsc::channel();
-        let (release_tx, release_rx) = mpsc::channel();
-
-        let worker_handle = std::thread::spawn(move || double_click_worker(click_rx, release_rx));
-
         MediaWithVolume {
-            click_tx,
-            release_tx,
-            worker_handle: Some(worker_handle),
+            clicks: ClickTracker::new(),
         }
     }
+
+    fn on_click(&mut self, ctx: &mut ModeContext, click: Click) -> Result<()> {
+        match click {
+            Click::Single => {
+                eprintln!("play/pause");
+                ctx.output()
+                    .key_click(&[EV_KEY::KEY_PLAYPAUSE])
+                    .map_err(Error::Evdev)?;
+            }
+            Click::Double => {
+                eprintln!("next track");
+                ctx.output()
+                    .key_click(&[EV_KEY::KEY_NEXTSONG])
+                    .map_err(Error::Evdev)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 impl ControlMode for MediaWithVolume {
@@ -70,39 +116,124 @@ impl ControlMode for MediaWithVolume {
         }
     }
 
-    fn on_btn_press(&mut self, _: &DialHaptics) -> Result<()> {
-        if self.click_tx.send(()).is_err() {
-            self.worker_handle
-                .take()
-                .unwrap()
-                .join()
-                .expect("panic on thread join")?;
-        }
+    fn on_end(&mut self, _ctx: &mut ModeContext) -> Result<()> {
+        self.clicks.reset();
         Ok(())
     }
 
-    fn on_btn_release(&mut self, _: &DialHaptics) -> Result<()> {
-        if self.release_tx.send(()).is_err() {
-            self.worker_handle
-                .take()
-                .unwrap()
-                .join()
-                .expect("panic on thread join")?;
+    fn on_timer(&mut self, ctx: &mut ModeContext, id: TimerId) -> Result<()> {
+        match self.clicks.on_timer(id) {
+            Some(click) => self.on_click(ctx, click),
+            None => Ok(()),
         }
+    }
+
+    fn on_btn_press(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        self.clicks.on_press(ctx.timers());
         Ok(())
     }
 
-    fn on_dial(&mut self, _: &DialHaptics, delta: i32) -> Result<()> {
+    fn on_btn_release(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        match self.clicks.on_release(ctx.timers()) {
+            Some(click) => self.on_click(ctx, click),
+            None => Ok(()),
+        }
+    }
+
+    fn on_dial(&mut self, ctx: &mut ModeContext, delta: i32) -> Result<()> {
         if delta > 0 {
             eprintln!("volume up");
-            fake_input::key_click(&[EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_VOLUMEUP])
+            ctx.output()
+                .key_click(&[EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_VOLUMEUP])
                 .map_err(Error::Evdev)?;
         } else {
             eprintln!("volume down");
-            fake_input::key_click(&[EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_VOLUMEDOWN])
+            ctx.output()
+                .key_click(&[EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_VOLUMEDOWN])
                 .map_err(Error::Evdev)?;
         }
 
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Advance to `now`, feeding any expired timers to the tracker.
+    fn advance(clicks: &mut ClickTracker, timers: &mut Timers, now: Duration) -> Vec<Click> {
+        timers.set_now(now);
+        std::iter::from_fn(|| timers.pop_expired())
+            .filter_map(|id| clicks.on_timer(id))
+            .collect()
+    }
+
+    #[test]
+    fn single_click_once_the_window_closes() {
+        let (mut clicks, mut timers) = (ClickTracker::new(), Timers::new(ms(0)));
+
+        clicks.on_press(&mut timers);
+        timers.set_now(ms(100));
+        assert_eq!(clicks.on_release(&mut timers), None);
+
+        assert_eq!(advance(&mut clicks, &mut timers, ms(349)), vec![]);
+        assert_eq!(
+            advance(&mut clicks, &mut timers, ms(350)),
+            vec![Click::Single]
+        );
+        assert_eq!(advance(&mut clicks, &mut timers, ms(1000)), vec![]);
+    }
+
+    #[test]
+    fn double_click() {
+        let (mut clicks, mut timers) = (ClickTracker::new(), Timers::new(ms(0)));
+
+        clicks.on_press(&mut timers);
+        timers.set_now(ms(100));
+        assert_eq!(clicks.on_release(&mut timers), None);
+        timers.set_now(ms(200));
+        clicks.on_press(&mut timers);
+        timers.set_now(ms(300));
+        assert_eq!(clicks.on_release(&mut timers), Some(Click::Double));
+
+        // the pending single click was cancelled
+        assert_eq!(advance(&mut clicks, &mut timers, ms(1000)), vec![]);
+    }
+
+    #[test]
+    fn slow_second_click() {
+        let (mut clicks, mut timers) = (ClickTracker::new(), Timers::new(ms(0)));
+
+        clicks.on_press(&mut timers);
+        timers.set_now(ms(100));
+        clicks.on_release(&mut timers);
+        assert_eq!(
+            advance(&mut clicks, &mut timers, ms(400)),
+            vec![Click::Single]
+        );
+
+        clicks.on_press(&mut timers);
+        timers.set_now(ms(500));
+        assert_eq!(clicks.on_release(&mut timers), None);
+        assert_eq!(
+            advance(&mut clicks, &mut timers, ms(750)),
+            vec![Click::Single]
+        );
+    }
+
+    #[test]
+    fn held_press_isnt_a_click() {
+        let (mut clicks, mut timers) = (ClickTracker::new(), Timers::new(ms(0)));
+
+        clicks.on_press(&mut timers);
+        timers.set_now(ms(1000));
+        assert_eq!(clicks.on_release(&mut timers), None);
+        assert!(!timers.is_scheduled(DOUBLE_CLICK));
+        assert_eq!(advance(&mut clicks, &mut timers, ms(2000)), vec![]);
+    }
+}
@@ -15,3 +15,34 @@ pub use self::scroll::*;
 pub use self::scroll_mt::*;
 pub use self::volume::*;
 pub use self::zoom::*;
+
+use crate::controller::ControlMode;
+
+/// Modes used when the config file doesn't specify any (in meta-menu order).
+pub const DEFAULT_MODES: &[&str] = &[
+    "scroll",
+    "scroll-mt",
+    "zoom",
+    "volume",
+    "media",
+    "media-with-volume",
+    "paddle",
+];
+
+/// Every mode type which can be instantiated via [`new_by_type`].
+pub const ALL_MODES: &[&str] = DEFAULT_MODES;
+
+/// Instantiate a built-in mode using its config-file type name.
+pub fn new_by_type(kind: &str) -> Option<Box<dyn ControlMode>> {
+    let mode: Box<dyn ControlMode> = match kind {
+        "scroll" => Box::new(Scroll::new()),
+        "scroll-mt" => Box::new(ScrollMT::new()),
+        "zoom" => Box::new(Zoom::new()),
+        "volume" => Box::new(Volume::new()),
+        "media" => Box::new(Media::new()),
+        "media-with-volume" => Box::new(MediaWithVolume::new()),
+        "paddle" => Box::new(Paddle::new()),
+        _ => return None,
+    };
+    Some(mode)
+}
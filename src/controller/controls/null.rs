@@ -1,5 +1,4 @@
-use crate::controller::{ControlMode, ControlModeMeta};
-use crate::dial_device::DialHaptics;
+use crate::controller::{ControlMode, ControlModeMeta, ModeContext};
 use crate::error::Result;
 
 impl ControlMode for () {
@@ -12,15 +11,15 @@ impl ControlMode for () {
         }
     }
 
-    fn on_btn_press(&mut self, _haptics: &DialHaptics) -> Result<()> {
+    fn on_btn_press(&mut self, _ctx: &mut ModeContext) -> Result<()> {
         Ok(())
     }
 
-    fn on_btn_release(&mut self, _haptics: &DialHaptics) -> Result<()> {
+    fn on_btn_release(&mut self, _ctx: &mut ModeContext) -> Result<()> {
         Ok(())
     }
 
-    fn on_dial(&mut self, _haptics: &DialHaptics, _delta: i32) -> Result<()> {
+    fn on_dial(&mut self, _ctx: &mut ModeContext, _delta: i32) -> Result<()> {
         Ok(())
     }
 }
@@ -1,145 +1,85 @@
 use std::cmp::Ordering;
-use std::sync::mpsc;
-use std::thread::JoinHandle;
 use std::time::Duration;
 
-use crate::controller::{ControlMode, ControlModeMeta};
-use crate::dial_device::DialHaptics;
-use crate::error::Result;
-use crate::fake_input;
+use crate::controller::{ControlMode, ControlModeMeta, ModeContext, TimerId};
+use crate::error::{Error, Result};
 
 use evdev_rs::enums::EV_KEY;
 
-// everything is done in a worker, as we need use `recv_timeout` as a (very)
-// poor man's `select!`.
+const TICK: TimerId = 0;
 
-enum Msg {
-    Kill,
-    ButtonDown,
-    ButtonUp,
-    Delta(i32),
-    Enabled(bool),
-}
-
-struct Worker {
-    msg: mpsc::Receiver<Msg>,
-
-    timeout: u64,
+/// A bit of a misnomer, since it's only left-right.
+pub struct Paddle {
+    // tweak these for "feel"
+    tick: Duration,
     falloff: i32,
     cap: i32,
     deadzone: i32,
 
-    enabled: bool,
     last_delta: i32,
     velocity: i32,
 }
 
-impl Worker {
-    pub fn new(msg: mpsc::Receiver<Msg>) -> Worker {
-        Worker {
-            msg,
-
-            // tweak these for "feel"
-            timeout: 5,
+impl Paddle {
+    pub fn new() -> Paddle {
+        Paddle {
+            tick: Duration::from_millis(5),
             falloff: 10,
             cap: 250,
             deadzone: 10,
 
-            enabled: false,
             last_delta: 0,
             velocity: 0,
         }
     }
 
-    pub fn run(&mut self) {
-        loop {
-            let falloff = self.velocity.abs() / self.falloff + 1;
-
-            let msg = if self.enabled {
-                self.msg.recv_timeout(Duration::from_millis(self.timeout))
-            } else {
-                self.msg
-                    .recv()
-                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected)
-            };
-
-            match msg {
-                Ok(Msg::Kill) => return,
-                Ok(Msg::Enabled(enabled)) => {
-                    self.enabled = enabled;
-                    if !enabled {
-                        fake_input::key_release(&[
-                            EV_KEY::KEY_SPACE,
-                            EV_KEY::KEY_LEFT,
-                            EV_KEY::KEY_RIGHT,
-                        ])
-                        .unwrap()
-                    }
-                }
-                Ok(Msg::ButtonDown) => fake_input::key_press(&[EV_KEY::KEY_SPACE]).unwrap(),
-                Ok(Msg::ButtonUp) => fake_input::key_release(&[EV_KEY::KEY_SPACE]).unwrap(),
-                Ok(Msg::Delta(delta)) => {
-                    // abrupt direction change!
-                    if (delta < 0) != (self.last_delta < 0) {
-                        self.velocity = 0
-                    }
-                    self.last_delta = delta;
-
-                    self.velocity += delta
-                }
-                Err(mpsc::RecvTimeoutError::Timeout) => match self.velocity.cmp(&0) {
-                    Ordering::Equal => {}
-                    Ordering::Less => self.velocity += falloff,
-                    Ordering::Greater => self.velocity -= falloff,
-                },
-                Err(other) => panic!("{}", other),
-            }
-
-            // clamp velocity within the cap bounds
-            if self.velocity > self.cap {
-                self.velocity = self.cap;
-            } else if self.velocity < -self.cap {
-                self.velocity = -self.cap;
-            }
-
-            if self.velocity.abs() < self.deadzone {
-                fake_input::key_release(&[EV_KEY::KEY_LEFT, EV_KEY::KEY_RIGHT]).unwrap();
-                continue;
-            }
+    /// Speed up the paddle.
+    fn push(&mut self, delta: i32) {
+        // abrupt direction change!
+        if (delta < 0) != (self.last_delta < 0) {
+            self.velocity = 0
+        }
+        self.last_delta = delta;
 
-            match self.velocity.cmp(&0) {
-                Ordering::Equal => {}
-                Ordering::Less => fake_input::key_press(&[EV_KEY::KEY_LEFT]).unwrap(),
-                Ordering::Greater => fake_input::key_press(&[EV_KEY::KEY_RIGHT]).unwrap(),
-            }
+        // clamp velocity within the cap bounds
+        self.velocity = (self.velocity + delta).clamp(-self.cap, self.cap);
+    }
 
-            // eprintln!("{:?}", self.velocity);
+    /// Slow down the paddle (once every `tick`).
+    fn on_tick(&mut self) {
+        let falloff = self.velocity.abs() / self.falloff + 1;
+        match self.velocity.cmp(&0) {
+            Ordering::Equal => {}
+            Ordering::Less => self.velocity += falloff,
+            Ordering::Greater => self.velocity -= falloff,
         }
     }
-}
 
-/// A bit of a misnomer, since it's only left-right.
-pub struct Paddle {
-    _worker: JoinHandle<()>,
-    msg: mpsc::Sender<Msg>,
-}
+    /// The arrow key which should be held down (if any).
+    fn held_key(&self) -> Option<EV_KEY> {
+        if self.velocity.abs() < self.deadzone {
+            return None;
+        }
 
-impl Drop for Paddle {
-    fn drop(&mut self) {
-        let _ = self.msg.send(Msg::Kill);
+        match self.velocity.cmp(&0) {
+            Ordering::Equal => None,
+            Ordering::Less => Some(EV_KEY::KEY_LEFT),
+            Ordering::Greater => Some(EV_KEY::KEY_RIGHT),
+        }
     }
-}
 
-impl Paddle {
-    pub fn new() -> Paddle {
-        let (msg_tx, msg_rx) = mpsc::channel();
+    fn update_keys(&mut self, ctx: &ModeContext) -> Result<()> {
+        match self.held_key() {
+            None => ctx
+                .output()
+                .key_release(&[EV_KEY::KEY_LEFT, EV_KEY::KEY_RIGHT])
+                .map_err(Error::Evdev)?,
+            Some(key) => ctx.output().key_press(&[key]).map_err(Error::Evdev)?,
+        }
 
-        let worker = std::thread::spawn(move || Worker::new(msg_rx).run());
+        // eprintln!("{:?}", self.velocity);
 
-        Paddle {
-            _worker: worker,
-            msg: msg_tx,
-        }
+        Ok(())
     }
 }
 
@@ -153,28 +93,129 @@ impl ControlMode for Paddle {
         }
     }
 
-    fn on_start(&mut self, _haptics: &DialHaptics) -> Result<()> {
-        let _ = self.msg.send(Msg::Enabled(true));
+    fn on_start(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        self.last_delta = 0;
+        self.velocity = 0;
+        ctx.timers().schedule_repeating(TICK, self.tick);
         Ok(())
     }
 
-    fn on_end(&mut self, _haptics: &DialHaptics) -> Result<()> {
-        let _ = self.msg.send(Msg::Enabled(false));
+    fn on_end(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        ctx.output()
+            .key_release(&[EV_KEY::KEY_SPACE, EV_KEY::KEY_LEFT, EV_KEY::KEY_RIGHT])
+            .map_err(Error::Evdev)?;
         Ok(())
     }
 
-    fn on_btn_press(&mut self, _: &DialHaptics) -> Result<()> {
-        let _ = self.msg.send(Msg::ButtonDown);
-        Ok(())
+    fn on_timer(&mut self, ctx: &mut ModeContext, id: TimerId) -> Result<()> {
+        if id != TICK {
+            return Ok(());
+        }
+
+        self.on_tick();
+        self.update_keys(ctx)
     }
 
-    fn on_btn_release(&mut self, _: &DialHaptics) -> Result<()> {
-        let _ = self.msg.send(Msg::ButtonUp);
+    fn on_btn_press(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        ctx.output()
+            .key_press(&[EV_KEY::KEY_SPACE])
+            .map_err(Error::Evdev)?;
         Ok(())
     }
 
-    fn on_dial(&mut self, _: &DialHaptics, delta: i32) -> Result<()> {
-        let _ = self.msg.send(Msg::Delta(delta));
+    fn on_btn_release(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        ctx.output()
+            .key_release(&[EV_KEY::KEY_SPACE])
+            .map_err(Error::Evdev)?;
         Ok(())
     }
+
+    fn on_dial(&mut self, ctx: &mut ModeContext, delta: i32) -> Result<()> {
+        self.push(delta);
+        self.update_keys(ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::Timers;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn start() -> (Paddle, Timers) {
+        let paddle = Paddle::new();
+        let mut timers = Timers::new(ms(0));
+        timers.schedule_repeating(TICK, paddle.tick);
+        (paddle, timers)
+    }
+
+    /// Advance to `now`, ticking the paddle whenever its timer fires (waking
+    /// up at each deadline, like the controller does).
+    fn advance(paddle: &mut Paddle, timers: &mut Timers, now: Duration) {
+        while let Some(deadline) = timers.next_deadline().filter(|d| *d <= now) {
+            timers.set_now(deadline);
+            while let Some(id) = timers.pop_expired() {
+                assert_eq!(id, TICK);
+                paddle.on_tick();
+            }
+        }
+        timers.set_now(now);
+    }
+
+    #[test]
+    fn velocity_falls_off_over_time() {
+        let (mut paddle, mut timers) = start();
+        paddle.push(60);
+        assert_eq!(paddle.held_key(), Some(EV_KEY::KEY_RIGHT));
+
+        // 60 -> 53 -> 47 -> 42 -> 37 -> 33 -> 29 -> 26 -> 23 -> 20 -> 17 -> 15
+        // -> 13 -> 11 -> 9 (i.e: 14 ticks until it's within the deadzone)
+        advance(&mut paddle, &mut timers, ms(65));
+        assert_eq!(paddle.held_key(), Some(EV_KEY::KEY_RIGHT));
+        advance(&mut paddle, &mut timers, ms(70));
+        assert_eq!(paddle.held_key(), None);
+
+        // ...and eventually comes to a complete stop
+        advance(&mut paddle, &mut timers, ms(500));
+        assert_eq!(paddle.velocity, 0);
+    }
+
+    #[test]
+    fn velocity_doesnt_fall_off_between_ticks() {
+        let (mut paddle, mut timers) = start();
+        paddle.push(-60);
+
+        advance(&mut paddle, &mut timers, ms(4));
+        assert_eq!(paddle.velocity, -60);
+        advance(&mut paddle, &mut timers, ms(5));
+        assert_eq!(paddle.velocity, -53);
+        assert_eq!(paddle.held_key(), Some(EV_KEY::KEY_LEFT));
+    }
+
+    #[test]
+    fn direction_change_resets_velocity() {
+        let (mut paddle, _) = start();
+        paddle.push(200);
+        paddle.push(-15);
+        assert_eq!(paddle.velocity, -15);
+        assert_eq!(paddle.held_key(), Some(EV_KEY::KEY_LEFT));
+    }
+
+    #[test]
+    fn velocity_is_capped() {
+        let (mut paddle, _) = start();
+        paddle.push(200);
+        paddle.push(200);
+        assert_eq!(paddle.velocity, 250);
+    }
+
+    #[test]
+    fn small_nudges_stay_within_the_deadzone() {
+        let (mut paddle, _) = start();
+        paddle.push(5);
+        assert_eq!(paddle.held_key(), None);
+    }
 }
@@ -1,7 +1,6 @@
-use crate::controller::{ControlMode, ControlModeMeta};
-use crate::dial_device::DialHaptics;
+use crate::controller::{ControlMode, ControlModeMeta, ModeContext};
 use crate::error::{Error, Result};
-use crate::fake_input::{self, ScrollStep};
+use crate::fake_input::ScrollStep;
 
 pub struct Scroll {}
 
@@ -21,21 +20,25 @@ impl ControlMode for Scroll {
         }
     }
 
-    fn on_btn_press(&mut self, _: &DialHaptics) -> Result<()> {
+    fn on_btn_press(&mut self, _: &mut ModeContext) -> Result<()> {
         Ok(())
     }
 
-    fn on_btn_release(&mut self, _haptics: &DialHaptics) -> Result<()> {
+    fn on_btn_release(&mut self, _ctx: &mut ModeContext) -> Result<()> {
         Ok(())
     }
 
-    fn on_dial(&mut self, _: &DialHaptics, delta: i32) -> Result<()> {
+    fn on_dial(&mut self, ctx: &mut ModeContext, delta: i32) -> Result<()> {
         if delta > 0 {
             eprintln!("scroll down");
-            fake_input::scroll_step(ScrollStep::Down).map_err(Error::Evdev)?;
+            ctx.output()
+                .scroll_step(ScrollStep::Down)
+                .map_err(Error::Evdev)?;
         } else {
             eprintln!("scroll up");
-            fake_input::scroll_step(ScrollStep::Up).map_err(Error::Evdev)?;
+            ctx.output()
+                .scroll_step(ScrollStep::Up)
+                .map_err(Error::Evdev)?;
         }
 
         Ok(())
@@ -1,7 +1,9 @@
-use crate::controller::{ControlMode, ControlModeMeta};
-use crate::dial_device::DialHaptics;
+use std::time::Duration;
+
+use crate::controller::{ControlMode, ControlModeMeta, ModeContext, TimerId};
 use crate::error::{Error, Result};
-use crate::fake_input;
+
+const RESTART_TOUCH: TimerId = 0;
 
 pub struct ScrollMT {
     acc_delta: i32,
@@ -23,40 +25,47 @@ impl ControlMode for ScrollMT {
         }
     }
 
-    fn on_start(&mut self, _haptics: &DialHaptics) -> Result<()> {
+    fn on_start(&mut self, ctx: &mut ModeContext) -> Result<()> {
         self.acc_delta = 0;
 
         // HACK: for some reason, if scroll mode is the startup mode, then just calling
         // `scroll_mt_start` doesn't work as expected.
-        std::thread::spawn(move || {
-            fake_input::scroll_mt_end().unwrap();
-            std::thread::sleep(std::time::Duration::from_millis(200));
-            fake_input::scroll_mt_start().unwrap();
-        });
+        ctx.output().scroll_mt_end().map_err(Error::Evdev)?;
+        ctx.timers()
+            .schedule_once(RESTART_TOUCH, Duration::from_millis(200));
+
+        Ok(())
+    }
 
+    fn on_timer(&mut self, ctx: &mut ModeContext, id: TimerId) -> Result<()> {
+        if id == RESTART_TOUCH {
+            ctx.output().scroll_mt_start().map_err(Error::Evdev)?;
+        }
         Ok(())
     }
 
-    fn on_end(&mut self, _haptics: &DialHaptics) -> Result<()> {
-        fake_input::scroll_mt_end().map_err(Error::Evdev)?;
+    fn on_end(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        ctx.output().scroll_mt_end().map_err(Error::Evdev)?;
         Ok(())
     }
 
     // HACK: the button will reset the scroll event, which sometimes helps
 
-    fn on_btn_press(&mut self, _: &DialHaptics) -> Result<()> {
-        fake_input::scroll_mt_end().map_err(Error::Evdev)?;
+    fn on_btn_press(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        ctx.output().scroll_mt_end().map_err(Error::Evdev)?;
         Ok(())
     }
 
-    fn on_btn_release(&mut self, _haptics: &DialHaptics) -> Result<()> {
-        fake_input::scroll_mt_start().map_err(Error::Evdev)?;
+    fn on_btn_release(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        ctx.output().scroll_mt_start().map_err(Error::Evdev)?;
         Ok(())
     }
 
-    fn on_dial(&mut self, _: &DialHaptics, delta: i32) -> Result<()> {
+    fn on_dial(&mut self, ctx: &mut ModeContext, delta: i32) -> Result<()> {
         self.acc_delta += delta;
-        fake_input::scroll_mt_step(self.acc_delta).map_err(Error::Evdev)?;
+        ctx.output()
+            .scroll_mt_step(self.acc_delta)
+            .map_err(Error::Evdev)?;
 
         Ok(())
     }
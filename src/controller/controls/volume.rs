@@ -1,15 +1,84 @@
-use crate::controller::{ControlMode, ControlModeMeta};
-use crate::dial_device::DialHaptics;
+use std::process::Command;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
+
+use crate::controller::{ControlMode, ControlModeMeta, ModeContext, TimerId};
 use crate::error::{Error, Result};
-use crate::fake_input;
+use crate::feedback::Value;
 
 use evdev_rs::enums::EV_KEY;
 
-pub struct Volume {}
+// assumes that each (shifted) key press nudges the volume by 1%
+const VOLUME_STEPS: i32 = 100;
+
+/// Fires once the dial has been idle for a bit, at which point the bounds are
+/// resynced with the actual system volume.
+const RESYNC: TimerId = 0;
+const RESYNC_DELAY: Duration = Duration::from_millis(500);
+
+/// Checks for new readings of the volume to show, until the next resync.
+const POLL: TimerId = 1;
+const POLL_PERIOD: Duration = Duration::from_millis(50);
+
+/// Gives the desktop a moment to act on injected key presses before reading
+/// back the volume.
+const SETTLE_DELAY: Duration = Duration::from_millis(50);
+
+pub struct Volume {
+    /// Started the first time the mode is used.
+    reader: Option<VolumeReader>,
+}
 
 impl Volume {
     pub fn new() -> Volume {
-        Volume {}
+        Volume { reader: None }
+    }
+
+    /// Read back the volume after it's been changed, showing it as soon as
+    /// the new reading comes in.
+    fn refresh(&mut self, ctx: &mut ModeContext) {
+        self.reader.get_or_insert_with(VolumeReader::new).refresh();
+        ctx.timers().schedule_once(RESYNC, RESYNC_DELAY);
+        if !ctx.timers().is_scheduled(POLL) {
+            ctx.timers().schedule_repeating(POLL, POLL_PERIOD);
+        }
+    }
+
+    /// Resync the bounds with the most recently read volume. Until the volume
+    /// has been read, the mode doesn't have any bounds (i.e: no end-stops).
+    fn resync(&mut self, ctx: &mut ModeContext) {
+        let volume = match self.reader.as_ref().and_then(VolumeReader::latest) {
+            Some(volume) => volume,
+            None => return,
+        };
+
+        if ctx.bounds().is_none() {
+            ctx.set_bounds(0, VOLUME_STEPS, volume.percent);
+        }
+        ctx.set_position(volume.percent);
+    }
+
+    /// Show the most recently read volume (if it hasn't been shown already).
+    fn show_new(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        let volume = match self.reader.as_ref().and_then(VolumeReader::take_new) {
+            Some(volume) => volume,
+            None => return Ok(()),
+        };
+
+        let icon = match volume.percent {
+            _ if volume.muted => "audio-volume-muted",
+            0 => "audio-volume-muted",
+            pos if pos < VOLUME_STEPS / 3 => "audio-volume-low",
+            pos if pos < VOLUME_STEPS * 2 / 3 => "audio-volume-medium",
+            _ => "audio-volume-high",
+        };
+        ctx.show_value(&Value {
+            label: "Volume",
+            icon,
+            value: volume.percent,
+            min: 0,
+            max: VOLUME_STEPS,
+        })
     }
 }
 
@@ -23,27 +92,204 @@ impl ControlMode for Volume {
         }
     }
 
-    fn on_btn_press(&mut self, _: &DialHaptics) -> Result<()> {
+    fn on_start(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        self.reader.get_or_insert_with(VolumeReader::new).refresh();
+        ctx.timers().schedule_once(RESYNC, RESYNC_DELAY);
         Ok(())
     }
 
-    fn on_btn_release(&mut self, _: &DialHaptics) -> Result<()> {
+    fn on_timer(&mut self, ctx: &mut ModeContext, id: TimerId) -> Result<()> {
+        match id {
+            POLL => self.show_new(ctx)?,
+            RESYNC => {
+                ctx.timers().cancel(POLL);
+                self.resync(ctx);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn on_btn_press(&mut self, _: &mut ModeContext) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_btn_release(&mut self, ctx: &mut ModeContext) -> Result<()> {
         eprintln!("mute");
-        fake_input::key_click(&[EV_KEY::KEY_MUTE]).map_err(Error::Evdev)?;
+        ctx.output()
+            .key_click(&[EV_KEY::KEY_MUTE])
+            .map_err(Error::Evdev)?;
+        self.refresh(ctx);
         Ok(())
     }
 
-    fn on_dial(&mut self, _: &DialHaptics, delta: i32) -> Result<()> {
+    fn on_dial(&mut self, ctx: &mut ModeContext, delta: i32) -> Result<()> {
         if delta > 0 {
             eprintln!("volume up");
-            fake_input::key_click(&[EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_VOLUMEUP])
+            ctx.output()
+                .key_click(&[EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_VOLUMEUP])
                 .map_err(Error::Evdev)?;
         } else {
             eprintln!("volume down");
-            fake_input::key_click(&[EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_VOLUMEDOWN])
+            ctx.output()
+                .key_click(&[EV_KEY::KEY_LEFTSHIFT, EV_KEY::KEY_VOLUMEDOWN])
                 .map_err(Error::Evdev)?;
         }
 
+        self.refresh(ctx);
         Ok(())
     }
 }
+
+/// The default audio sink's volume.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct SinkVolume {
+    /// Averaged across channels.
+    percent: i32,
+    muted: bool,
+}
+
+#[derive(Default)]
+struct Readings {
+    latest: Option<SinkVolume>,
+    /// Set until `latest` is returned by [`VolumeReader::take_new`].
+    new: bool,
+}
+
+/// Reads back the default audio sink's volume on a background thread, as
+/// spawning `pactl` is far too slow to do on every tick of the dial.
+struct VolumeReader {
+    requests: mpsc::Sender<()>,
+    readings: Arc<Mutex<Readings>>,
+}
+
+impl VolumeReader {
+    fn new() -> VolumeReader {
+        let (requests, requests_rx) = mpsc::channel();
+        let readings = Arc::new(Mutex::new(Readings::default()));
+
+        std::thread::spawn({
+            let readings = readings.clone();
+            // exits once the reader is dropped
+            move || {
+                while requests_rx.recv().is_ok() {
+                    std::thread::sleep(SETTLE_DELAY);
+                    // any requests which came in while waiting are covered by
+                    // this read
+                    while requests_rx.try_recv().is_ok() {}
+                    let latest = read_volume();
+                    *readings.lock().unwrap() = Readings {
+                        latest,
+                        new: latest.is_some(),
+                    };
+                }
+            }
+        });
+
+        VolumeReader { requests, readings }
+    }
+
+    /// Read back the volume (in the background).
+    fn refresh(&self) {
+        let _ = self.requests.send(());
+    }
+
+    /// The most recently read volume, or `None` if it couldn't be read (e.g:
+    /// if `pactl` isn't installed).
+    fn latest(&self) -> Option<SinkVolume> {
+        self.readings.lock().unwrap().latest
+    }
+
+    /// Like [`VolumeReader::latest`], but only returns each reading once.
+    fn take_new(&self) -> Option<SinkVolume> {
+        let mut readings = self.readings.lock().unwrap();
+        if !std::mem::take(&mut readings.new) {
+            return None;
+        }
+        readings.latest
+    }
+}
+
+/// Works with both PulseAudio and PipeWire (via `pipewire-pulse`).
+fn read_volume() -> Option<SinkVolume> {
+    Some(SinkVolume {
+        percent: parse_volume(&pactl("get-sink-volume")?)?,
+        muted: parse_mute(&pactl("get-sink-mute")?)?,
+    })
+}
+
+/// Returns the output of `pactl <command> @DEFAULT_SINK@`.
+fn pactl(command: &str) -> Option<String> {
+    let output = Command::new("pactl")
+        .args([command, "@DEFAULT_SINK@"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses the output of `pactl get-sink-volume`, averaging the volume across
+/// channels, e.g:
+///
+/// ```text
+/// Volume: front-left: 32768 /  50% / -18.06 dB,   front-right: 32768 /  50% / -18.06 dB
+///         balance 0.00
+/// ```
+fn parse_volume(output: &str) -> Option<i32> {
+    let line = output.lines().find(|line| line.starts_with("Volume:"))?;
+    let channels = line
+        .split_whitespace()
+        .filter_map(|word| word.strip_suffix('%'))
+        .map(|percent| percent.parse::<i32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    if channels.is_empty() {
+        return None;
+    }
+    Some(channels.iter().sum::<i32>() / channels.len() as i32)
+}
+
+/// Parses the output of `pactl get-sink-mute` (i.e: `Mute: yes`).
+fn parse_mute(output: &str) -> Option<bool> {
+    match output.trim().strip_prefix("Mute:")?.trim() {
+        "yes" => Some(true),
+        "no" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_stereo_volume() {
+        let output = "Volume: front-left: 32768 /  50% / -18.06 dB,   front-right: 36045 /  55% / -15.58 dB\n        balance 0.09\n";
+        assert_eq!(parse_volume(output), Some(52));
+    }
+
+    #[test]
+    fn parse_mono_volume() {
+        let output = "Volume: mono: 65536 / 100% / 0.00 dB\n";
+        assert_eq!(parse_volume(output), Some(100));
+    }
+
+    #[test]
+    fn parse_volume_garbage() {
+        assert_eq!(parse_volume(""), None);
+        assert_eq!(
+            parse_volume("Volume: front-left: 32768 / lots% / 0 dB"),
+            None
+        );
+        assert_eq!(parse_volume("Connection failure: Connection refused"), None);
+    }
+
+    #[test]
+    fn parse_mute_state() {
+        assert_eq!(parse_mute("Mute: yes\n"), Some(true));
+        assert_eq!(parse_mute("Mute: no\n"), Some(false));
+        assert_eq!(parse_mute("Connection failure: Connection refused\n"), None);
+    }
+}
@@ -1,7 +1,5 @@
-use crate::controller::{ControlMode, ControlModeMeta};
-use crate::dial_device::DialHaptics;
+use crate::controller::{ControlMode, ControlModeMeta, ModeContext};
 use crate::error::{Error, Result};
-use crate::fake_input;
 
 use evdev_rs::enums::EV_KEY;
 
@@ -23,22 +21,24 @@ impl ControlMode for Zoom {
         }
     }
 
-    fn on_btn_press(&mut self, _: &DialHaptics) -> Result<()> {
+    fn on_btn_press(&mut self, _: &mut ModeContext) -> Result<()> {
         Ok(())
     }
 
-    fn on_btn_release(&mut self, _haptics: &DialHaptics) -> Result<()> {
+    fn on_btn_release(&mut self, _ctx: &mut ModeContext) -> Result<()> {
         Ok(())
     }
 
-    fn on_dial(&mut self, _: &DialHaptics, delta: i32) -> Result<()> {
+    fn on_dial(&mut self, ctx: &mut ModeContext, delta: i32) -> Result<()> {
         if delta > 0 {
             eprintln!("zoom in");
-            fake_input::key_click(&[EV_KEY::KEY_LEFTCTRL, EV_KEY::KEY_EQUAL])
+            ctx.output()
+                .key_click(&[EV_KEY::KEY_LEFTCTRL, EV_KEY::KEY_EQUAL])
                 .map_err(Error::Evdev)?;
         } else {
             eprintln!("zoom out");
-            fake_input::key_click(&[EV_KEY::KEY_LEFTCTRL, EV_KEY::KEY_MINUS])
+            ctx.output()
+                .key_click(&[EV_KEY::KEY_LEFTCTRL, EV_KEY::KEY_MINUS])
                 .map_err(Error::Evdev)?;
         }
 
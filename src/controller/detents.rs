@@ -0,0 +1,306 @@
+use crate::dial_device::{ManualTrigger, Waveform};
+
+/// Number of positions in a full rotation of the dial, when tracking
+/// rotation at the dial's maximum resolution.
+pub const RESOLUTION: u16 = 3600;
+
+/// A single software-driven detent.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Detent {
+    /// Position around the dial, from 0 to [`RESOLUTION`] (exclusive).
+    pub at: u16,
+    /// Played whenever the dial is rotated past the detent.
+    pub trigger: ManualTrigger,
+}
+
+impl Detent {
+    pub fn click(at: u16) -> Detent {
+        Detent {
+            at,
+            trigger: ManualTrigger::new(Waveform::Click),
+        }
+    }
+
+    /// A more pronounced click, for snap points.
+    pub fn strong(at: u16) -> Detent {
+        Detent {
+            at,
+            trigger: ManualTrigger {
+                repeat: 1,
+                ..ManualTrigger::new(Waveform::Click)
+            },
+        }
+    }
+}
+
+/// Detents at arbitrary positions around the dial.
+///
+/// Unlike the dial's built-in detents (see [`ControlModeMeta::steps`]), these
+/// don't need to be evenly spaced. The catch is that they're implemented in
+/// software: the dial is switched to its highest resolution, and the daemon
+/// fires manual haptic triggers as the dial is rotated past each detent.
+///
+/// While active, [`ControlMode::on_dial`] is invoked with the number of
+/// detents crossed (instead of the raw rotation delta).
+///
+/// [`ControlModeMeta::steps`]: super::ControlModeMeta
+/// [`ControlMode::on_dial`]: super::ControlMode::on_dial
+#[derive(Debug, Clone)]
+pub struct Detents {
+    /// Sorted by position, with no duplicates.
+    detents: Vec<Detent>,
+}
+
+impl Detents {
+    /// Out-of-range detents are discarded.
+    pub fn new(mut detents: Vec<Detent>) -> Detents {
+        detents.retain(|d| d.at < RESOLUTION);
+        detents.sort_by_key(|d| d.at);
+        detents.dedup_by_key(|d| d.at);
+        Detents { detents }
+    }
+
+    /// `n` evenly spaced detents.
+    pub fn uniform(n: u16) -> Detents {
+        let n = n.clamp(1, RESOLUTION) as u32;
+        Detents::new(
+            (0..n)
+                .map(|i| Detent::click((i * RESOLUTION as u32 / n) as u16))
+                .collect(),
+        )
+    }
+
+    /// Evenly spaced detents, with a stronger click at every `snap_every`th
+    /// detent (e.g: `with_snap_points(20, 5)` puts snap points at 0%, 25%,
+    /// 50%, and 75% of a full rotation).
+    pub fn with_snap_points(n: u16, snap_every: u16) -> Detents {
+        let mut detents = Detents::uniform(n);
+        for (i, detent) in detents.detents.iter_mut().enumerate() {
+            if snap_every != 0 && i % snap_every as usize == 0 {
+                *detent = Detent::strong(detent.at);
+            }
+        }
+        detents
+    }
+}
+
+/// Tracks the dial's absolute rotation against a set of [`Detents`].
+pub(super) struct DetentTracker {
+    detents: Detents,
+    pos: i64,
+}
+
+impl DetentTracker {
+    pub fn new(detents: Detents) -> DetentTracker {
+        DetentTracker { detents, pos: 0 }
+    }
+
+    /// Returns the (signed) number of detents crossed, along with the trigger
+    /// of the last detent crossed (if any).
+    pub fn apply(&mut self, delta: i32) -> (i32, Option<ManualTrigger>) {
+        let (old, new) = (self.pos, self.pos + delta as i64);
+        self.pos = new;
+
+        // a detent is crossed if it lies within (low, high]
+        let (low, high) = if new > old { (old, new) } else { (new, old) };
+        let res = RESOLUTION as i64;
+
+        let mut crossed = 0;
+        let mut last: Option<(i64, ManualTrigger)> = None;
+        for detent in self.detents.detents.iter() {
+            let at = detent.at as i64;
+            let count = (high - at).div_euclid(res) - (low - at).div_euclid(res);
+            if count == 0 {
+                continue;
+            }
+            crossed += count;
+
+            // the crossing closest to the dial's new position
+            let nearest = if new > old {
+                high - (high - at).rem_euclid(res)
+            } else {
+                low + 1 + (at - low - 1).rem_euclid(res)
+            };
+            let is_nearer = match last {
+                None => true,
+                Some((pos, _)) => (new - nearest).abs() < (new - pos).abs(),
+            };
+            if is_nearer {
+                last = Some((nearest, detent.trigger));
+            }
+        }
+
+        let crossed = crossed as i32;
+        (
+            if new > old { crossed } else { -crossed },
+            last.map(|(_, trigger)| trigger),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A detent whose trigger can be told apart from the others.
+    fn tagged(at: u16, tag: u8) -> Detent {
+        Detent {
+            at,
+            trigger: ManualTrigger {
+                repeat: tag,
+                ..ManualTrigger::new(Waveform::Click)
+            },
+        }
+    }
+
+    /// Detents at each quarter of a rotation, tagged 0 to 3.
+    fn quarters() -> DetentTracker {
+        DetentTracker::new(Detents::new(vec![
+            tagged(0, 0),
+            tagged(900, 1),
+            tagged(1800, 2),
+            tagged(2700, 3),
+        ]))
+    }
+
+    /// Returns the number of detents crossed, and the tag of the trigger.
+    fn apply(tracker: &mut DetentTracker, delta: i32) -> (i32, Option<u8>) {
+        let (crossed, trigger) = tracker.apply(delta);
+        (crossed, trigger.map(|t| t.repeat))
+    }
+
+    fn positions(detents: &Detents) -> Vec<u16> {
+        detents.detents.iter().map(|d| d.at).collect()
+    }
+
+    #[test]
+    fn forward() {
+        let mut tracker = quarters();
+
+        assert_eq!(apply(&mut tracker, 100), (0, None));
+        assert_eq!(apply(&mut tracker, 799), (0, None));
+        // landing exactly on a detent crosses it
+        assert_eq!(apply(&mut tracker, 1), (1, Some(1)));
+        assert_eq!(apply(&mut tracker, 1), (0, None));
+        assert_eq!(apply(&mut tracker, 900), (1, Some(2)));
+    }
+
+    #[test]
+    fn backward() {
+        let mut tracker = quarters();
+        apply(&mut tracker, 901);
+
+        assert_eq!(apply(&mut tracker, -1), (0, None));
+        // leaving a detent backwards crosses it
+        assert_eq!(apply(&mut tracker, -1), (-1, Some(1)));
+        assert_eq!(apply(&mut tracker, -899), (0, None));
+        assert_eq!(apply(&mut tracker, -1), (-1, Some(0)));
+    }
+
+    #[test]
+    fn back_and_forth() {
+        let mut tracker = quarters();
+        apply(&mut tracker, 899);
+
+        assert_eq!(apply(&mut tracker, 1), (1, Some(1)));
+        assert_eq!(apply(&mut tracker, -1), (-1, Some(1)));
+        assert_eq!(apply(&mut tracker, 1), (1, Some(1)));
+    }
+
+    #[test]
+    fn small_deltas_accumulate() {
+        let mut tracker = quarters();
+
+        for _ in 0..8 {
+            assert_eq!(apply(&mut tracker, 100), (0, None));
+        }
+        assert_eq!(apply(&mut tracker, 100), (1, Some(1)));
+    }
+
+    #[test]
+    fn several_detents_at_once() {
+        let mut tracker = quarters();
+
+        // the trigger is the detent nearest to the new position
+        assert_eq!(apply(&mut tracker, 2000), (2, Some(2)));
+        assert_eq!(apply(&mut tracker, -2000), (-2, Some(1)));
+    }
+
+    #[test]
+    fn wraparound() {
+        let mut tracker = quarters();
+        apply(&mut tracker, 3500);
+
+        assert_eq!(apply(&mut tracker, 200), (1, Some(0)));
+        assert_eq!(apply(&mut tracker, -200), (-1, Some(0)));
+
+        // positions below 0 behave the same
+        let mut tracker = quarters();
+        assert_eq!(apply(&mut tracker, -100), (-1, Some(0)));
+        assert_eq!(apply(&mut tracker, -800), (0, None));
+        assert_eq!(apply(&mut tracker, -1), (-1, Some(3)));
+        assert_eq!(apply(&mut tracker, 901), (2, Some(0)));
+    }
+
+    #[test]
+    fn full_rotations() {
+        let mut tracker = quarters();
+        assert_eq!(apply(&mut tracker, RESOLUTION as i32 * 2), (8, Some(0)));
+
+        let mut tracker = quarters();
+        assert_eq!(apply(&mut tracker, -(RESOLUTION as i32)), (-4, Some(1)));
+    }
+
+    #[test]
+    fn no_detents() {
+        let mut tracker = DetentTracker::new(Detents::new(Vec::new()));
+        assert_eq!(apply(&mut tracker, 5000), (0, None));
+    }
+
+    #[test]
+    fn new_sorts_and_dedups() {
+        let detents = Detents::new(vec![
+            tagged(1800, 0),
+            tagged(0, 1),
+            tagged(RESOLUTION, 2),
+            tagged(1800, 3),
+            tagged(900, 4),
+        ]);
+        assert_eq!(positions(&detents), [0, 900, 1800]);
+    }
+
+    #[test]
+    fn uniform() {
+        assert_eq!(positions(&Detents::uniform(4)), [0, 900, 1800, 2700]);
+        assert_eq!(
+            positions(&Detents::uniform(7)),
+            [0, 514, 1028, 1542, 2057, 2571, 3085]
+        );
+    }
+
+    #[test]
+    fn uniform_clamps() {
+        assert_eq!(positions(&Detents::uniform(0)), [0]);
+        assert_eq!(positions(&Detents::uniform(1)), [0]);
+
+        let max = positions(&Detents::uniform(RESOLUTION));
+        assert_eq!(max, (0..RESOLUTION).collect::<Vec<_>>());
+        assert_eq!(positions(&Detents::uniform(u16::MAX)), max);
+    }
+
+    #[test]
+    fn snap_points() {
+        let detents = Detents::with_snap_points(20, 5);
+        let strong = detents
+            .detents
+            .iter()
+            .filter(|d| d.trigger == Detent::strong(d.at).trigger)
+            .map(|d| d.at)
+            .collect::<Vec<_>>();
+        assert_eq!(detents.detents.len(), 20);
+        assert_eq!(strong, [0, 900, 1800, 2700]);
+
+        let detents = Detents::with_snap_points(20, 0);
+        assert!(detents.detents.iter().all(|d| *d == Detent::click(d.at)));
+    }
+}
@@ -0,0 +1,128 @@
+/// Tracks whether the active mode should be running, i.e: while the dial is
+/// connected and the system is awake.
+///
+/// Connects / disconnects and suspends / resumes can arrive in any order (e.g:
+/// the dial often disconnects while the system is asleep), so each change
+/// reports whether the mode needs to be started or ended as a result.
+#[derive(Debug, Default)]
+pub(super) struct Lifecycle {
+    connected: bool,
+    /// Set between [`ControllerMsg::Suspend`] and [`ControllerMsg::Resume`].
+    ///
+    /// [`ControllerMsg::Suspend`]: super::ControllerMsg::Suspend
+    /// [`ControllerMsg::Resume`]: super::ControllerMsg::Resume
+    suspended: bool,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(super) enum Transition {
+    Start,
+    End,
+    Unchanged,
+}
+
+impl Lifecycle {
+    pub fn connected(&self) -> bool {
+        self.connected
+    }
+
+    pub fn suspended(&self) -> bool {
+        self.suspended
+    }
+
+    pub fn running(&self) -> bool {
+        self.connected && !self.suspended
+    }
+
+    pub fn set_connected(&mut self, connected: bool) -> Transition {
+        self.update(|lifecycle| lifecycle.connected = connected)
+    }
+
+    pub fn set_suspended(&mut self, suspended: bool) -> Transition {
+        self.update(|lifecycle| lifecycle.suspended = suspended)
+    }
+
+    fn update(&mut self, f: impl FnOnce(&mut Lifecycle)) -> Transition {
+        let was_running = self.running();
+        f(self);
+        match (was_running, self.running()) {
+            (false, true) => Transition::Start,
+            (true, false) => Transition::End,
+            _ => Transition::Unchanged,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use Transition::*;
+
+    #[test]
+    fn connect_disconnect() {
+        let mut lifecycle = Lifecycle::default();
+
+        assert_eq!(lifecycle.set_connected(true), Start);
+        assert_eq!(lifecycle.set_connected(true), Unchanged);
+        assert_eq!(lifecycle.set_connected(false), End);
+        assert_eq!(lifecycle.set_connected(false), Unchanged);
+    }
+
+    #[test]
+    fn suspend_resume() {
+        let mut lifecycle = Lifecycle::default();
+        lifecycle.set_connected(true);
+
+        assert_eq!(lifecycle.set_suspended(true), End);
+        assert!(lifecycle.suspended());
+        assert_eq!(lifecycle.set_suspended(false), Start);
+        assert!(!lifecycle.suspended());
+    }
+
+    #[test]
+    fn repeated_suspend_resume() {
+        let mut lifecycle = Lifecycle::default();
+        lifecycle.set_connected(true);
+
+        assert_eq!(lifecycle.set_suspended(true), End);
+        assert_eq!(lifecycle.set_suspended(true), Unchanged);
+        assert_eq!(lifecycle.set_suspended(false), Start);
+        assert_eq!(lifecycle.set_suspended(false), Unchanged);
+    }
+
+    #[test]
+    fn suspend_while_disconnected() {
+        let mut lifecycle = Lifecycle::default();
+
+        assert_eq!(lifecycle.set_suspended(true), Unchanged);
+        assert_eq!(lifecycle.set_suspended(false), Unchanged);
+        assert!(!lifecycle.running());
+    }
+
+    #[test]
+    fn disconnect_while_suspended() {
+        let mut lifecycle = Lifecycle::default();
+        lifecycle.set_connected(true);
+        lifecycle.set_suspended(true);
+
+        // the mode was already ended on suspend
+        assert_eq!(lifecycle.set_connected(false), Unchanged);
+        assert_eq!(lifecycle.set_suspended(false), Unchanged);
+        assert_eq!(lifecycle.set_connected(true), Start);
+    }
+
+    #[test]
+    fn reconnect_while_suspended() {
+        let mut lifecycle = Lifecycle::default();
+        lifecycle.set_connected(true);
+        lifecycle.set_suspended(true);
+        lifecycle.set_connected(false);
+
+        // the mode is started on resume
+        assert_eq!(lifecycle.set_connected(true), Unchanged);
+        assert!(lifecycle.connected());
+        assert_eq!(lifecycle.set_suspended(false), Start);
+        assert!(lifecycle.running());
+    }
+}
@@ -0,0 +1,93 @@
+/// Decides when to warn the user that the dial's battery is running low.
+///
+/// The warning is shown once when the level drops to the threshold. Battery
+/// readings tend to wobble by a percent or two around any given level, so the
+/// warning is only re-armed once the level climbs comfortably back above the
+/// threshold (e.g: after the batteries are replaced).
+#[derive(Debug, Default)]
+pub(super) struct LowBatteryWarning {
+    warned: bool,
+}
+
+/// How far above the threshold the level has to climb to re-arm the warning.
+const HYSTERESIS: u8 = 5;
+
+impl LowBatteryWarning {
+    /// Returns `true` if the user should be warned about `level`. A
+    /// `threshold` of 0 disables the warning.
+    pub fn check(&mut self, level: u8, threshold: u8) -> bool {
+        if threshold == 0 || level > threshold.saturating_add(HYSTERESIS) {
+            self.warned = false;
+            return false;
+        }
+
+        if level > threshold || self.warned {
+            return false;
+        }
+        self.warned = true;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warns_once() {
+        let mut warning = LowBatteryWarning::default();
+
+        assert!(!warning.check(50, 15));
+        assert!(!warning.check(16, 15));
+        assert!(warning.check(15, 15));
+        assert!(!warning.check(15, 15));
+        assert!(!warning.check(10, 15));
+    }
+
+    #[test]
+    fn warns_when_first_reading_is_low() {
+        let mut warning = LowBatteryWarning::default();
+        assert!(warning.check(5, 15));
+    }
+
+    #[test]
+    fn wobbling_level_doesnt_rewarn() {
+        let mut warning = LowBatteryWarning::default();
+
+        assert!(warning.check(15, 15));
+        for level in [16, 15, 17, 14, 20, 15] {
+            assert!(!warning.check(level, 15), "rewarned at {}%", level);
+        }
+    }
+
+    #[test]
+    fn rearms_after_recovering() {
+        let mut warning = LowBatteryWarning::default();
+
+        assert!(warning.check(15, 15));
+        // batteries replaced
+        assert!(!warning.check(100, 15));
+        assert!(warning.check(15, 15));
+
+        assert!(!warning.check(21, 15));
+        assert!(warning.check(14, 15));
+    }
+
+    #[test]
+    fn disabled() {
+        let mut warning = LowBatteryWarning::default();
+
+        assert!(!warning.check(0, 0));
+        assert!(!warning.check(5, 0));
+        // re-enabled (e.g: by reloading the config)
+        assert!(warning.check(5, 15));
+    }
+
+    #[test]
+    fn threshold_near_max() {
+        let mut warning = LowBatteryWarning::default();
+
+        assert!(warning.check(100, 100));
+        assert!(!warning.check(100, 100));
+    }
+}
@@ -1,9 +1,31 @@
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 
-use crate::dial_device::{DialDevice, DialEventKind, DialHaptics};
+use crate::config::{Config, ConfigError, ConfigErrorKind, HapticsConfig};
+use crate::dial_device::{
+    self, DialDevice, DialEventKind, DialHaptics, DialWaker, PuckPosition, SystemKey,
+};
 use crate::error::{Error, Result};
+use crate::fake_input::{FakeInputs, FAKE_INPUTS};
+use crate::feedback::{self, Menu, MenuFeedback, MenuItem, Value};
+use crate::ipc::StatusMap;
 
+mod bounds;
 pub mod controls;
+mod detents;
+mod lifecycle;
+mod low_battery;
+mod state;
+mod timers;
+
+pub use bounds::Bounds;
+use bounds::Motion;
+use detents::DetentTracker;
+pub use detents::{Detent, Detents};
+use lifecycle::{Lifecycle, Transition};
+use low_battery::LowBatteryWarning;
+pub use state::{ModeState, StateStore};
+pub use timers::{TimerId, Timers};
 
 pub struct ControlModeMeta {
     /// Mode Name (as displayed in the Meta selection menu)
@@ -18,104 +40,730 @@ pub struct ControlModeMeta {
     steps: u16,
 }
 
+/// Requests a [`ControlMode`] can make of the [`DialController`].
+///
+/// Requests are queued up, and are processed once the mode has finished
+/// handling the current event.
+#[derive(Debug)]
+pub enum ControllerRequest {
+    /// Switch to the mode at the given index.
+    SwitchMode(usize),
+    /// Open the mode selection menu.
+    EnterMetaMode,
+}
+
+/// Handles to everything a [`ControlMode`] can interact with while handling
+/// an event.
+pub struct ModeContext<'a> {
+    haptics: &'a DialHaptics,
+    timers: &'a mut Timers,
+    state: &'a mut ModeState,
+    bounds: &'a mut Option<Bounds>,
+    detents: &'a mut Option<DetentTracker>,
+    requests: &'a mut Vec<ControllerRequest>,
+    battery: Option<u8>,
+    puck: Option<PuckPosition>,
+}
+
+/// Key under which a mode's [`Bounds`] position is persisted.
+const POSITION_KEY: &str = ":position";
+
+impl<'a> ModeContext<'a> {
+    pub fn haptics(&self) -> &DialHaptics {
+        self.haptics
+    }
+
+    /// Virtual input devices used to inject events into the system.
+    pub fn output(&self) -> &'static FakeInputs {
+        &FAKE_INPUTS
+    }
+
+    /// Briefly show a message (via the configured feedback backend).
+    pub fn notify(&self, msg: &str, icon: &str) -> Result<()> {
+        feedback::backend().message(msg, icon)
+    }
+
+    /// Show a value (and its range) as an on-screen display, e.g: a progress
+    /// bar (via the configured feedback backend).
+    pub fn show_value(&self, value: &Value) -> Result<()> {
+        feedback::backend().value(value)
+    }
+
+    /// Timers scheduled by a mode only fire while that mode is active, and are
+    /// cancelled once the mode ends.
+    pub fn timers(&mut self) -> &mut Timers {
+        self.timers
+    }
+
+    /// The time at which the event currently being handled occurred (see
+    /// [`dial_device::now`]).
+    pub fn now(&self) -> Duration {
+        self.timers.now()
+    }
+
+    /// State belonging to the current mode, which is retained across mode
+    /// switches (and daemon restarts).
+    pub fn state(&mut self) -> &mut ModeState {
+        self.state
+    }
+
+    pub fn request(&mut self, request: ControllerRequest) {
+        self.requests.push(request)
+    }
+
+    /// The dial's most recently reported battery level (as a percentage).
+    pub fn battery(&self) -> Option<u8> {
+        self.battery
+    }
+
+    /// Where the dial is sitting on a touchscreen (if it is).
+    pub fn puck(&self) -> Option<PuckPosition> {
+        self.puck
+    }
+
+    /// Declare that the mode controls a value within `min..=max`, enabling
+    /// haptic end-stops (see [`Bounds`]).
+    ///
+    /// The position is persisted across mode switches, so `initial` is only
+    /// used the very first time the mode's bounds are set.
+    pub fn set_bounds(&mut self, min: i32, max: i32, initial: i32) {
+        let pos = self.state.get(POSITION_KEY).unwrap_or(initial);
+        *self.bounds = Some(Bounds::new(min, max, pos));
+    }
+
+    pub fn clear_bounds(&mut self) {
+        *self.bounds = None;
+    }
+
+    pub fn bounds(&self) -> Option<&Bounds> {
+        self.bounds.as_ref()
+    }
+
+    /// Replace the dial's evenly spaced detents with custom [`Detents`].
+    pub fn set_detents(&mut self, detents: Detents) {
+        *self.detents = Some(DetentTracker::new(detents));
+    }
+
+    pub fn clear_detents(&mut self) {
+        *self.detents = None;
+    }
+
+    /// Resync the position within the mode's bounds (no-op if the mode
+    /// doesn't have any).
+    pub fn set_position(&mut self, pos: i32) {
+        if let Some(bounds) = self.bounds.as_mut() {
+            bounds.set_position(pos);
+            self.state.set(POSITION_KEY, bounds.position());
+        }
+    }
+}
+
 pub trait ControlMode {
     fn meta(&self) -> ControlModeMeta;
 
-    fn on_start(&mut self, _haptics: &DialHaptics) -> Result<()> {
+    fn on_start(&mut self, _ctx: &mut ModeContext) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_end(&mut self, _ctx: &mut ModeContext) -> Result<()> {
+        Ok(())
+    }
+
+    /// Invoked when a timer scheduled via [`ModeContext::timers`] expires.
+    fn on_timer(&mut self, _ctx: &mut ModeContext, _id: TimerId) -> Result<()> {
+        Ok(())
+    }
+
+    /// Invoked when the dial reports a system key (e.g: the system sleep /
+    /// wake keys). Ignored by default.
+    fn on_system_key(&mut self, _ctx: &mut ModeContext, _key: SystemKey) -> Result<()> {
         Ok(())
     }
 
-    fn on_end(&mut self, _haptics: &DialHaptics) -> Result<()> {
+    /// Invoked when the dial is placed on (or lifted off of) a touchscreen.
+    /// Ignored by default.
+    fn on_contact(&mut self, _ctx: &mut ModeContext, _on_screen: bool) -> Result<()> {
         Ok(())
     }
 
-    fn on_btn_press(&mut self, haptics: &DialHaptics) -> Result<()>;
-    fn on_btn_release(&mut self, haptics: &DialHaptics) -> Result<()>;
-    fn on_dial(&mut self, haptics: &DialHaptics, delta: i32) -> Result<()>;
+    /// Invoked when the dial moves on a touchscreen. Ignored by default.
+    fn on_position(&mut self, _ctx: &mut ModeContext, _pos: PuckPosition) -> Result<()> {
+        Ok(())
+    }
+
+    fn on_btn_press(&mut self, ctx: &mut ModeContext) -> Result<()>;
+    fn on_btn_release(&mut self, ctx: &mut ModeContext) -> Result<()>;
+    fn on_dial(&mut self, ctx: &mut ModeContext, delta: i32) -> Result<()>;
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 enum ActiveMode {
     Normal(usize),
     Meta,
 }
 
+/// Messages which can be sent to a running [`DialController`] from other
+/// threads (via a [`ControllerHandle`]).
+#[derive(Debug)]
+pub enum ControllerMsg {
+    /// Swap out the current config, rebuilding the list of modes.
+    ReloadConfig(Config),
+    /// The system is about to suspend. The active mode is ended (releasing
+    /// any inputs it's holding down), after which `done` is notified.
+    Suspend(mpsc::Sender<()>),
+    /// The system has resumed. The active mode is restarted (which also
+    /// re-applies the dial's haptics settings).
+    Resume,
+}
+
+/// A handle to a running [`DialController`], which can be used to send it
+/// messages from other threads.
+#[derive(Clone)]
+pub struct ControllerHandle {
+    msgs: mpsc::Sender<ControllerMsg>,
+    waker: DialWaker,
+}
+
+impl ControllerHandle {
+    pub fn send(&self, msg: ControllerMsg) {
+        let _ = self.msgs.send(msg);
+        self.waker.wake();
+    }
+}
+
+/// How long to wait before flushing modified mode state to disk. Batches up
+/// writes from modes which update their state on every dial tick.
+///
+/// Mode switches are written immediately, and any pending state is flushed
+/// when the daemon exits.
+const STATE_SAVE_DELAY: Duration = Duration::from_secs(2);
+
 pub struct DialController {
     device: DialDevice,
+    /// While the system is suspended, the active mode is ended, and input
+    /// from the dial is dropped.
+    lifecycle: Lifecycle,
 
+    config: Config,
     modes: Vec<Box<dyn ControlMode>>,
     active_mode: ActiveMode,
+    /// The most recently active (non-meta) mode
+    selected_mode: usize,
 
-    new_mode: Arc<Mutex<Option<usize>>>,
     meta_mode: Box<dyn ControlMode>, // concrete type is always `MetaMode`
+
+    msgs: mpsc::Receiver<ControllerMsg>,
+    msgs_tx: mpsc::Sender<ControllerMsg>,
+
+    timers: Timers,
+    /// Shared between every dial's controller.
+    state: Arc<Mutex<StateStore>>,
+    state_save_deadline: Option<Duration>,
+    meta_state: ModeState, // not persisted
+    requests: Vec<ControllerRequest>,
+
+    bounds: Option<Bounds>,
+    detents: Option<DetentTracker>,
+    /// The most recent (haptics, steps) sent to the dial by
+    /// `update_haptics`.
+    auto_haptics: Option<(bool, u16)>,
+
+    /// Reported over IPC.
+    status: StatusMap,
+    battery: Option<u8>,
+    puck: Option<PuckPosition>,
+    low_battery: LowBatteryWarning,
+}
+
+/// Instantiate the modes listed in the config.
+fn build_modes(config: &Config) -> Result<Vec<Box<dyn ControlMode>>> {
+    config
+        .modes
+        .iter()
+        .map(|mode| {
+            controls::new_by_type(&mode.kind).ok_or_else(|| {
+                Error::from(ConfigError::new(
+                    None,
+                    ConfigErrorKind::UnknownModeType(mode.kind.clone()),
+                ))
+            })
+        })
+        .collect()
 }
 
 impl DialController {
+    /// `config` should be the config for _every_ dial (the modes which aren't
+    /// available on this particular dial are filtered out).
     pub fn new(
-        device: DialDevice,
-        initial_mode: usize,
-        modes: Vec<Box<dyn ControlMode>>,
-    ) -> DialController {
-        let metas = modes.iter().map(|m| m.meta()).collect();
+        mut device: DialDevice,
+        config: Config,
+        state: Arc<Mutex<StateStore>>,
+        status: StatusMap,
+    ) -> Result<DialController> {
+        let config = config.for_dial(device.id());
+        let modes = build_modes(&config)?;
+
+        device.set_long_press_timeout(config.long_press_timeout);
+
+        let last_mode = {
+            let mut state = state.lock().unwrap();
+            // the mode that was last selected before the daemon supported
+            // multiple dials is handed to whichever dial connects first
+            state.migrate_to_dial(device.id(), "last_mode");
+            state.dial(device.id()).get::<String>("last_mode")
+        };
+        let initial_mode = config
+            .modes
+            .iter()
+            .position(|mode| Some(&mode.id) == last_mode.as_ref())
+            .unwrap_or(0);
 
-        let new_mode = Arc::new(Mutex::new(None));
+        let (msgs_tx, msgs) = mpsc::channel();
 
-        DialController {
+        let mut controller = DialController {
             device,
+            lifecycle: Lifecycle::default(),
 
+            config,
             modes,
             active_mode: ActiveMode::Normal(initial_mode),
+            selected_mode: initial_mode,
+
+            meta_mode: Box::new(()), // replaced below
+
+            msgs,
+            msgs_tx,
+
+            timers: Timers::new(dial_device::now()),
+            state,
+            state_save_deadline: None,
+            meta_state: ModeState::new(),
+            requests: Vec::new(),
+
+            bounds: None,
+            detents: None,
+            auto_haptics: None,
+
+            status,
+            battery: None,
+            puck: None,
+            low_battery: LowBatteryWarning::default(),
+        };
+
+        controller.update_status();
+
+        controller.meta_mode = controller.new_meta_mode();
+
+        Ok(controller)
+    }
 
-            new_mode: new_mode.clone(),
-            meta_mode: Box::new(MetaMode::new(new_mode, initial_mode, metas)),
+    pub fn handle(&self) -> ControllerHandle {
+        ControllerHandle {
+            msgs: self.msgs_tx.clone(),
+            waker: self.device.waker(),
         }
     }
 
     pub fn run(&mut self) -> Result<()> {
         loop {
-            let evt = self.device.next_event()?;
-            let haptics = self.device.haptics();
+            let deadline = match (self.timers.next_deadline(), self.state_save_deadline) {
+                (Some(a), Some(b)) => Some(a.min(b)),
+                (a, b) => a.or(b),
+            };
+            let timeout = deadline.map(|deadline| deadline.saturating_sub(dial_device::now()));
 
-            if let Some(new_mode) = self.new_mode.lock().unwrap().take() {
-                self.active_mode = ActiveMode::Normal(new_mode);
-                let mode = &mut self.modes[new_mode];
+            let evt = self.device.next_event(timeout)?;
+            self.timers.set_now(dial_device::now());
 
-                haptics.set_mode(mode.meta().haptics, mode.meta().steps)?;
-                mode.on_start(haptics)?;
+            while let Ok(msg) = self.msgs.try_recv() {
+                self.handle_msg(msg)?;
+                self.handle_requests()?;
             }
 
-            let mode = match self.active_mode {
-                ActiveMode::Normal(idx) => &mut self.modes[idx],
-                ActiveMode::Meta => &mut self.meta_mode,
-            };
+            if let Some(evt) = evt {
+                // timers which expired before the event occurred fire first
+                self.fire_timers(evt.time)?;
+                self.timers.set_now(evt.time);
+                self.dispatch(evt.kind)?;
+                self.handle_requests()?;
+            }
+
+            self.fire_timers(dial_device::now())?;
+
+            self.maybe_save_state();
+        }
+    }
+
+    /// Fire every timer which expired by `now`.
+    fn fire_timers(&mut self, now: Duration) -> Result<()> {
+        self.timers.set_now(now);
+        while let Some(id) = self.timers.pop_expired() {
+            self.with_mode(|mode, ctx| mode.on_timer(ctx, id))?;
+            self.handle_requests()?;
+        }
+        Ok(())
+    }
+
+    fn handle_msg(&mut self, msg: ControllerMsg) -> Result<()> {
+        match msg {
+            ControllerMsg::ReloadConfig(config) => {
+                let config = config.for_dial(self.device.id());
+                if config == self.config {
+                    return Ok(());
+                }
+
+                eprintln!("reloading config");
+
+                // validate everything _before_ touching any existing state
+                let modes = build_modes(&config)?;
+
+                let mode_running = self.lifecycle.running();
+                if mode_running {
+                    self.end_mode()?;
+                }
+
+                // try to stick with the same mode
+                let selected_id = &self.config.modes[self.selected_mode].id;
+                let selected_mode = config
+                    .modes
+                    .iter()
+                    .position(|mode| &mode.id == selected_id)
+                    .unwrap_or(0);
+
+                self.device
+                    .set_long_press_timeout(config.long_press_timeout);
+                self.config = config;
+                self.modes = modes;
+                self.active_mode = ActiveMode::Normal(selected_mode);
+                self.selected_mode = selected_mode;
+                self.meta_mode = self.new_meta_mode();
+
+                if mode_running {
+                    self.start_mode()?;
+                }
+
+                self.check_battery();
+            }
+            ControllerMsg::Suspend(done) => {
+                let transition = self.lifecycle.set_suspended(true);
+                self.device.cancel_long_press();
+                self.apply(transition)?;
+                let _ = done.send(());
+            }
+            ControllerMsg::Resume => {
+                let transition = self.lifecycle.set_suspended(false);
+                self.apply(transition)?;
+            }
+        }
 
-            match evt.kind {
-                DialEventKind::Ignored => {}
+        Ok(())
+    }
+
+    fn new_meta_mode(&self) -> Box<dyn ControlMode> {
+        let metas = (0..self.modes.len())
+            .map(|idx| self.mode_meta(idx))
+            .collect();
+        Box::new(MetaMode::new(
+            self.selected_mode,
+            metas,
+            self.config.haptics.clone(),
+        ))
+    }
+
+    /// Returns the mode's metadata, with any overrides from the config file
+    /// applied.
+    fn mode_meta(&self, idx: usize) -> ControlModeMeta {
+        let config = &self.config.modes[idx];
+        let mut meta = self.modes[idx].meta();
+        meta.haptics = config.haptics.unwrap_or(meta.haptics);
+        meta.steps = config.steps.unwrap_or(meta.steps);
+        meta
+    }
+
+    /// Publish the dial's current status over IPC.
+    fn update_status(&self) {
+        let mut status = self.status.lock().unwrap();
+        let status = status.entry(self.device.id().to_string()).or_default();
+        status.connected = self.lifecycle.connected();
+        status.battery = self.battery;
+        status.device = Some(self.device.info());
+    }
+
+    /// Warn the user (once) when the battery is running low.
+    fn check_battery(&mut self) {
+        let threshold = self.config.low_battery_threshold;
+        let level = match self.battery {
+            Some(level) => level,
+            None => return,
+        };
+
+        if !self.low_battery.check(level, threshold) {
+            return;
+        }
+
+        eprintln!("low battery: {}%", level);
+        let res =
+            feedback::backend().alert(&format!("Battery low ({}%)", level), "battery-caution");
+        if let Err(e) = res {
+            eprintln!("could not show low battery warning: {}", e);
+        }
+    }
+
+    fn maybe_save_state(&mut self) {
+        let now = self.timers.now();
+
+        if self.state_save_deadline.is_none() && self.state.lock().unwrap().is_dirty() {
+            self.state_save_deadline = Some(now + STATE_SAVE_DELAY);
+        }
+
+        if matches!(self.state_save_deadline, Some(deadline) if deadline <= now) {
+            self.save_state();
+        }
+    }
+
+    /// Write any modified state to disk right away.
+    fn save_state(&mut self) {
+        self.state_save_deadline = None;
+        let mut state = self.state.lock().unwrap();
+        // losing some state isn't the end of the world
+        if state.is_dirty() {
+            if let Err(e) = state.to_disk() {
+                eprintln!("could not save mode state: {}", e);
+            }
+        }
+    }
+
+    fn dispatch(&mut self, kind: DialEventKind) -> Result<()> {
+        match kind {
+            DialEventKind::Ignored => {}
 
-                DialEventKind::Connect => {
-                    eprintln!("Dial Connected");
-                    haptics.set_mode(mode.meta().haptics, mode.meta().steps)?;
-                    mode.on_start(haptics)?
+            DialEventKind::Connect => {
+                eprintln!("Dial Connected ({})", self.device.id());
+                let transition = self.lifecycle.set_connected(true);
+                self.update_status();
+                self.apply(transition)?;
+            }
+            DialEventKind::Disconnect => {
+                eprintln!("Dial Disconnected ({})", self.device.id());
+                let transition = self.lifecycle.set_connected(false);
+                self.puck = None;
+                self.update_status();
+                self.apply(transition)?;
+            }
+            DialEventKind::Battery(level) => {
+                eprintln!("battery level: {}%", level);
+                self.battery = Some(level);
+                self.update_status();
+                self.check_battery();
+            }
+
+            // the active mode was ended on suspend, so there's nothing to
+            // forward input to until the system resumes
+            DialEventKind::ButtonPress
+            | DialEventKind::ButtonRelease
+            | DialEventKind::ButtonLongPress
+            | DialEventKind::Dial(_)
+            | DialEventKind::SystemKey(_)
+            | DialEventKind::Contact(_)
+            | DialEventKind::Position(_)
+                if self.lifecycle.suspended() => {}
+
+            DialEventKind::ButtonPress => self.with_mode(|mode, ctx| mode.on_btn_press(ctx))?,
+            DialEventKind::ButtonRelease => self.with_mode(|mode, ctx| mode.on_btn_release(ctx))?,
+            DialEventKind::Dial(delta) => self.on_dial(delta)?,
+            DialEventKind::SystemKey(key) => {
+                eprintln!("system key: {:?}", key);
+                self.with_mode(|mode, ctx| mode.on_system_key(ctx, key))?
+            }
+            DialEventKind::Contact(on_screen) => {
+                if !on_screen {
+                    self.puck = None;
                 }
-                DialEventKind::Disconnect => {
-                    eprintln!("Dial Disconnected");
-                    mode.on_end(haptics)?
+                self.with_mode(|mode, ctx| mode.on_contact(ctx, on_screen))?
+            }
+            DialEventKind::Position(pos) => {
+                self.puck = Some(pos);
+                self.with_mode(|mode, ctx| mode.on_position(ctx, pos))?
+            }
+
+            DialEventKind::ButtonLongPress => {
+                eprintln!("long press!");
+                if self.active_mode != ActiveMode::Meta {
+                    self.switch_mode(ActiveMode::Meta)?;
                 }
+            }
+        }
 
-                DialEventKind::ButtonPress => mode.on_btn_press(haptics)?,
-                DialEventKind::ButtonRelease => mode.on_btn_release(haptics)?,
-                DialEventKind::Dial(delta) => mode.on_dial(haptics, delta)?,
-
-                DialEventKind::ButtonLongPress => {
-                    eprintln!("long press!");
-                    if !matches!(self.active_mode, ActiveMode::Meta) {
-                        mode.on_end(haptics)?;
-                        self.active_mode = ActiveMode::Meta;
-                        // meta_mode sets haptic feedback manually
-                        self.meta_mode.on_start(haptics)?;
+        Ok(())
+    }
+
+    fn on_dial(&mut self, delta: i32) -> Result<()> {
+        let delta = match &mut self.detents {
+            Some(detents) => {
+                let (crossed, trigger) = detents.apply(delta);
+                if let Some(trigger) = trigger {
+                    self.device.haptics().trigger(trigger)?;
+                }
+                if crossed == 0 {
+                    return Ok(());
+                }
+                crossed
+            }
+            None => delta,
+        };
+
+        if let (ActiveMode::Normal(idx), Some(bounds)) = (self.active_mode, &mut self.bounds) {
+            match bounds.apply(delta) {
+                Motion::Moved => {
+                    let id = &self.config.modes[idx].id;
+                    let mut state = self.state.lock().unwrap();
+                    state.mode(id).set(POSITION_KEY, bounds.position());
+                }
+                Motion::Blocked { first } => {
+                    if first {
+                        let pattern = &self.config.haptics.limit_reached;
+                        self.device.haptics().play(pattern)?;
                     }
                 }
             }
         }
+
+        self.with_mode(|mode, ctx| mode.on_dial(ctx, delta))
+    }
+
+    /// Keep the dial's automatic haptics in sync with the active mode.
+    ///
+    /// - software detents require the dial to be at full resolution, with its
+    ///   own detents disabled
+    /// - the mode's detents are disabled while its position is pinned against
+    ///   one of its bounds, making the limit feel distinct from the rest of
+    ///   the range
+    fn update_haptics(&mut self) -> Result<()> {
+        let at_limit = self.bounds.as_ref().is_some_and(Bounds::at_limit);
+        let auto_haptics = match (&self.detents, self.active_mode) {
+            (Some(_), _) => (false, detents::RESOLUTION),
+            (None, ActiveMode::Normal(idx)) => {
+                let meta = self.mode_meta(idx);
+                (meta.haptics && !at_limit, meta.steps)
+            }
+            // meta mode manages haptics itself
+            (None, ActiveMode::Meta) => return Ok(()),
+        };
+
+        if self.auto_haptics != Some(auto_haptics) {
+            self.auto_haptics = Some(auto_haptics);
+            let (haptics, steps) = auto_haptics;
+            self.device.haptics().set_mode(haptics, steps)?;
+        }
+
+        Ok(())
+    }
+
+    fn handle_requests(&mut self) -> Result<()> {
+        // handling a request may result in more requests being queued up
+        while !self.requests.is_empty() {
+            for request in std::mem::take(&mut self.requests) {
+                match request {
+                    ControllerRequest::SwitchMode(idx) if idx < self.modes.len() => {
+                        let id = &self.config.modes[idx].id;
+                        let mut state = self.state.lock().unwrap();
+                        state.dial(self.device.id()).set("last_mode", id);
+                        drop(state);
+                        self.switch_mode(ActiveMode::Normal(idx))?;
+                        // mode switches are rare, and well worth persisting
+                        // straight away
+                        self.save_state();
+                    }
+                    ControllerRequest::SwitchMode(idx) => {
+                        eprintln!("ignoring request to switch to invalid mode {}", idx)
+                    }
+                    ControllerRequest::EnterMetaMode => {
+                        if self.active_mode != ActiveMode::Meta {
+                            self.switch_mode(ActiveMode::Meta)?
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn switch_mode(&mut self, new_mode: ActiveMode) -> Result<()> {
+        self.end_mode()?;
+        self.active_mode = new_mode;
+        if let ActiveMode::Normal(idx) = new_mode {
+            self.selected_mode = idx;
+        }
+        self.start_mode()
+    }
+
+    fn start_mode(&mut self) -> Result<()> {
+        // haptics are set up once `on_start` returns (see `update_haptics`)
+        self.with_mode(|mode, ctx| mode.on_start(ctx))
+    }
+
+    /// Start / end the active mode, following a change to the dial's
+    /// [`Lifecycle`].
+    fn apply(&mut self, transition: Transition) -> Result<()> {
+        match transition {
+            Transition::Start => self.start_mode(),
+            Transition::End => self.end_mode(),
+            Transition::Unchanged => Ok(()),
+        }
+    }
+
+    fn end_mode(&mut self) -> Result<()> {
+        self.with_mode(|mode, ctx| mode.on_end(ctx))?;
+        self.timers.clear();
+        self.bounds = None;
+        self.detents = None;
+        self.auto_haptics = None;
+        Ok(())
+    }
+
+    /// Invoke `f` with the currently active mode + a fresh [`ModeContext`].
+    fn with_mode(
+        &mut self,
+        f: impl FnOnce(&mut dyn ControlMode, &mut ModeContext) -> Result<()>,
+    ) -> Result<()> {
+        // the store is shared with every other dial's controller, so it's only
+        // locked long enough to take a snapshot of the mode's state (and to
+        // write it back afterwards)
+        let (mode, mut state) = match self.active_mode {
+            ActiveMode::Normal(idx) => {
+                let id = &self.config.modes[idx].id;
+                (
+                    &mut self.modes[idx],
+                    self.state.lock().unwrap().snapshot(id),
+                )
+            }
+            ActiveMode::Meta => (&mut self.meta_mode, std::mem::take(&mut self.meta_state)),
+        };
+
+        let mut ctx = ModeContext {
+            haptics: self.device.haptics(),
+            timers: &mut self.timers,
+            state: &mut state,
+            bounds: &mut self.bounds,
+            detents: &mut self.detents,
+            requests: &mut self.requests,
+            battery: self.battery,
+            puck: self.puck,
+        };
+
+        let res = f(mode.as_mut(), &mut ctx);
+
+        match self.active_mode {
+            ActiveMode::Normal(idx) => {
+                let id = &self.config.modes[idx].id;
+                self.state.lock().unwrap().write_back(id, state);
+            }
+            ActiveMode::Meta => self.meta_state = state,
+        }
+        res?;
+
+        self.update_haptics()
     }
 }
 
@@ -123,30 +771,26 @@ impl DialController {
 struct MetaMode {
     // constant
     metas: Vec<ControlModeMeta>,
+    patterns: HapticsConfig,
 
     // stateful (across invocations)
     current_mode: usize,
-    new_mode: Arc<Mutex<Option<usize>>>,
 
     // reset in on_start
     first_release: bool,
-    notif: Option<notify_rust::NotificationHandle>,
+    menu: Option<Box<dyn MenuFeedback>>,
 }
 
 impl MetaMode {
-    fn new(
-        new_mode: Arc<Mutex<Option<usize>>>,
-        current_mode: usize,
-        metas: Vec<ControlModeMeta>,
-    ) -> MetaMode {
+    fn new(current_mode: usize, metas: Vec<ControlModeMeta>, patterns: HapticsConfig) -> MetaMode {
         MetaMode {
             metas,
+            patterns,
 
             current_mode,
-            new_mode,
 
             first_release: true,
-            notif: None,
+            menu: None,
         }
     }
 }
@@ -156,71 +800,63 @@ impl ControlMode for MetaMode {
         unreachable!() // meta mode never queries itself
     }
 
-    fn on_start(&mut self, haptics: &DialHaptics) -> Result<()> {
-        use notify_rust::*;
-        self.notif = Some(
-            Notification::new()
-                .hint(Hint::Resident(true))
-                .hint(Hint::Category("device".into()))
-                .timeout(Timeout::Never)
-                .summary("Surface Dial")
-                .body(&format!(
-                    "Entered Meta Mode (From Mode: {})",
-                    self.metas[self.current_mode].name
-                ))
-                .icon("emblem-system")
-                .show()
-                .map_err(Error::Notif)?,
-        );
-
-        haptics.set_mode(true, 36)?;
-        haptics.buzz(1)?;
+    fn on_start(&mut self, ctx: &mut ModeContext) -> Result<()> {
+        let items = self
+            .metas
+            .iter()
+            .map(|meta| MenuItem {
+                name: meta.name.into(),
+                icon: meta.icon.into(),
+            })
+            .collect();
+        self.menu = Some(feedback::backend().open_menu(Menu {
+            items,
+            selected: self.current_mode,
+            battery: ctx.battery(),
+        })?);
+
+        // one detent per mode
+        ctx.set_detents(Detents::uniform(self.metas.len() as u16));
+        ctx.haptics().play(&self.patterns.meta_enter)?;
 
         self.first_release = true;
 
         Ok(())
     }
 
-    fn on_btn_press(&mut self, _haptics: &DialHaptics) -> Result<()> {
+    fn on_end(&mut self, _ctx: &mut ModeContext) -> Result<()> {
+        if let Some(menu) = self.menu.take() {
+            menu.close();
+        }
+        Ok(())
+    }
+
+    fn on_btn_press(&mut self, _ctx: &mut ModeContext) -> Result<()> {
         Ok(())
     }
 
-    fn on_btn_release(&mut self, haptics: &DialHaptics) -> Result<()> {
+    fn on_btn_release(&mut self, ctx: &mut ModeContext) -> Result<()> {
         if self.first_release {
             self.first_release = false;
         } else {
-            *self.new_mode.lock().unwrap() = Some(self.current_mode);
+            ctx.request(ControllerRequest::SwitchMode(self.current_mode));
 
-            crate::config::Config {
-                last_mode: self.current_mode,
+            if let Some(menu) = self.menu.take() {
+                menu.close();
             }
-            .to_disk()?;
-
-            self.notif.take().unwrap().close();
-            haptics.buzz(1)?;
+            ctx.haptics().play(&self.patterns.mode_select)?;
         }
         Ok(())
     }
 
-    fn on_dial(&mut self, _haptics: &DialHaptics, delta: i32) -> Result<()> {
-        if delta > 0 {
-            self.current_mode += 1;
-        } else {
-            if self.current_mode == 0 {
-                self.current_mode = self.metas.len() - 1;
-            } else {
-                self.current_mode -= 1;
-            }
-        };
-
-        self.current_mode %= self.metas.len();
+    fn on_dial(&mut self, _ctx: &mut ModeContext, delta: i32) -> Result<()> {
+        // `delta` is the number of detents crossed (one detent per mode), so a
+        // quick flick can skip past several modes at once
+        let len = self.metas.len() as i32;
+        self.current_mode = (self.current_mode as i32 + delta).rem_euclid(len) as usize;
 
-        let mode_meta = &self.metas[self.current_mode];
-        if let Some(ref mut notification) = self.notif {
-            notification
-                .body(&format!("New Mode: {}", mode_meta.name))
-                .icon(mode_meta.icon);
-            notification.update();
+        if let Some(ref mut menu) = self.menu {
+            menu.select(self.current_mode)?;
         }
 
         Ok(())
@@ -0,0 +1,467 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::error::{Error, Result};
+
+/// Key-value storage owned by a single [`ControlMode`].
+///
+/// Values are stored as strings, and are converted to/from their concrete
+/// types using `FromStr` and `ToString`.
+///
+/// [`ControlMode`]: super::ControlMode
+#[derive(Debug, Default, Clone)]
+pub struct ModeState {
+    values: BTreeMap<String, String>,
+    dirty: bool,
+}
+
+impl ModeState {
+    pub fn new() -> ModeState {
+        ModeState {
+            values: BTreeMap::new(),
+            dirty: false,
+        }
+    }
+
+    /// Returns `None` if the key is missing, or if its value couldn't be parsed
+    /// as a `T`.
+    pub fn get<T: FromStr>(&self, key: &str) -> Option<T> {
+        self.values.get(key)?.parse().ok()
+    }
+
+    pub fn set<T: ToString>(&mut self, key: &str, val: T) {
+        let val = val.to_string();
+        if self.values.get(key) != Some(&val) {
+            self.values.insert(key.to_string(), val);
+            self.dirty = true;
+        }
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        if self.values.remove(key).is_some() {
+            self.dirty = true;
+        }
+    }
+}
+
+/// The state of every mode, keyed by mode ID.
+///
+/// Persisted under `$XDG_STATE_HOME/surface-dial-daemon/state.txt` using a
+/// dead-simple INI-like format:
+///
+/// ```text
+/// [mode-id]
+/// key=value
+/// ```
+///
+/// Mode IDs, keys, and values are escaped, so they can contain anything.
+pub struct StateStore {
+    path: PathBuf,
+    modes: BTreeMap<String, ModeState>,
+}
+
+fn get_state_file_path() -> io::Result<PathBuf> {
+    let state_home = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => directories::BaseDirs::new()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?
+            .home_dir()
+            .join(".local/state"),
+    };
+
+    Ok(state_home.join("surface-dial-daemon").join("state.txt"))
+}
+
+// IDs / keys / values are free-form, so make sure they always fit on a single
+// line, and can't be mistaken for a section header or the `=` separator
+fn escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for (i, c) in s.chars().enumerate() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '=' => out.push_str("\\="),
+            '[' if i == 0 => out.push_str("\\["),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Splits a `key=value` line on the first unescaped `=`.
+fn split_entry(line: &str) -> Option<(&str, &str)> {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '=' => return Some((&line[..i], &line[i + 1..])),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn unescape(val: &str) -> String {
+    let mut out = String::with_capacity(val.len());
+    let mut chars = val.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    out
+}
+
+impl StateStore {
+    pub fn from_disk() -> Result<StateStore> {
+        let path = get_state_file_path().map_err(|e| Error::StateFile(PathBuf::new(), e))?;
+        StateStore::from_file(path)
+    }
+
+    /// A missing file is treated as empty.
+    fn from_file(path: PathBuf) -> Result<StateStore> {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(Error::StateFile(path, e)),
+        };
+
+        Ok(StateStore {
+            modes: StateStore::parse(&content),
+            path,
+        })
+    }
+
+    fn parse(content: &str) -> BTreeMap<String, ModeState> {
+        let mut modes = BTreeMap::new();
+        let mut current = None;
+
+        for line in content.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            // keys starting with a `[` are escaped, so this is always a header
+            if line.starts_with('[') && line.ends_with(']') {
+                let id = unescape(&line[1..line.len() - 1]);
+                modes.insert(id.clone(), ModeState::new());
+                current = Some(id);
+                continue;
+            }
+
+            // state is a nice-to-have, so it's better to skip over any garbage
+            // than to refuse to start up
+            let state = match current {
+                Some(ref id) => modes.get_mut(id),
+                None => None,
+            };
+            match (state, split_entry(line)) {
+                (Some(state), Some((key, val))) => {
+                    state.values.insert(unescape(key), unescape(val));
+                }
+                _ => eprintln!("ignoring malformed state file line: {:?}", line),
+            }
+        }
+
+        modes
+    }
+
+    /// Atomically write the current state to disk.
+    pub fn to_disk(&mut self) -> Result<()> {
+        let mut content = String::new();
+        for (id, state) in self.modes.iter() {
+            if state.values.is_empty() {
+                continue;
+            }
+
+            content += &format!("[{}]\n", escape(id));
+            for (key, val) in state.values.iter() {
+                content += &format!("{}={}\n", escape(key), escape(val));
+            }
+            content += "\n";
+        }
+
+        write_atomic(&self.path, content.as_bytes())
+            .map_err(|e| Error::StateFile(self.path.clone(), e))?;
+
+        for state in self.modes.values_mut() {
+            state.dirty = false;
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if any mode's state has changed since it was last
+    /// written to disk.
+    pub fn is_dirty(&self) -> bool {
+        self.modes.values().any(|state| state.dirty)
+    }
+
+    /// State belonging to the daemon itself, rather than to any one dial.
+    pub fn daemon(&mut self) -> &mut ModeState {
+        // mode IDs can't contain a ':', so this can't collide with a mode
+        self.mode(":daemon")
+    }
+
+    /// State belonging to the daemon, but specific to a particular dial (e.g:
+    /// the dial's last selected mode).
+    pub fn dial(&mut self, dial_id: &str) -> &mut ModeState {
+        self.mode(&format!(":dial:{}", dial_id))
+    }
+
+    /// Move a key from the daemon's state into a dial's state (unless the dial
+    /// already has a value of its own), e.g: for state saved before the daemon
+    /// supported multiple dials.
+    pub fn migrate_to_dial(&mut self, dial_id: &str, key: &str) {
+        let val = match self.daemon().values.remove(key) {
+            Some(val) => val,
+            None => return,
+        };
+        self.daemon().dirty = true;
+
+        let dial = self.dial(dial_id);
+        if !dial.values.contains_key(key) {
+            dial.values.insert(key.to_string(), val);
+            dial.dirty = true;
+        }
+    }
+
+    /// A copy of a mode's state, which can be modified without holding on to
+    /// the store (see [`StateStore::write_back`]).
+    pub fn snapshot(&mut self, id: &str) -> ModeState {
+        ModeState {
+            values: self.mode(id).values.clone(),
+            dirty: false,
+        }
+    }
+
+    /// Replace a mode's state with a snapshot taken by
+    /// [`StateStore::snapshot`], if the snapshot was modified.
+    pub fn write_back(&mut self, id: &str, snapshot: ModeState) {
+        if snapshot.dirty {
+            *self.mode(id) = snapshot;
+        }
+    }
+
+    pub fn mode(&mut self, id: &str) -> &mut ModeState {
+        if !self.modes.contains_key(id) {
+            self.modes.insert(id.to_string(), ModeState::new());
+        }
+        self.modes.get_mut(id).unwrap()
+    }
+}
+
+/// Write to a temporary file, and then rename it over the target, ensuring
+/// that a crash mid-write can't leave behind a truncated file.
+fn write_atomic(path: &Path, content: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = fs::File::create(&tmp_path)?;
+        file.write_all(content)?;
+        file.sync_all()?;
+    }
+    fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store() -> StateStore {
+        StateStore {
+            path: PathBuf::new(),
+            modes: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn write_back_modified_snapshot() {
+        let mut store = store();
+        let mut snapshot = store.snapshot("volume");
+        snapshot.set("key", 1);
+        store.write_back("volume", snapshot);
+
+        assert_eq!(store.mode("volume").get("key"), Some(1));
+        assert!(store.is_dirty());
+    }
+
+    #[test]
+    fn unmodified_snapshot_keeps_concurrent_changes() {
+        let mut store = store();
+        store.mode("volume").set("key", 1);
+
+        let snapshot = store.snapshot("volume");
+        // e.g: another dial's controller
+        store.mode("volume").set("key", 2);
+        store.write_back("volume", snapshot);
+
+        assert_eq!(store.mode("volume").get("key"), Some(2));
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir()
+            .join(format!("surface-dial-test-{}-{}", std::process::id(), name))
+            .join("state.txt")
+    }
+
+    fn round_trip(store: &mut StateStore) -> BTreeMap<String, ModeState> {
+        store.to_disk().unwrap();
+        let content = fs::read_to_string(&store.path).unwrap();
+        let _ = fs::remove_dir_all(store.path.parent().unwrap());
+        StateStore::parse(&content)
+    }
+
+    #[test]
+    fn parse() {
+        let modes = StateStore::parse(
+            "[volume]\nposition=3\nlabel=a\\nb\n\n[:dial:usb-1]\nlast_mode=volume\n",
+        );
+
+        assert_eq!(modes.len(), 2);
+        assert_eq!(modes["volume"].get("position"), Some(3));
+        assert_eq!(modes["volume"].get("label"), Some("a\nb".to_string()));
+        assert_eq!(
+            modes[":dial:usb-1"].get("last_mode"),
+            Some("volume".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_skips_garbage() {
+        let modes = StateStore::parse("orphan=1\n[volume]\nno separator\nposition=3\n");
+
+        assert_eq!(modes.len(), 1);
+        assert_eq!(modes["volume"].values.len(), 1);
+        assert_eq!(modes["volume"].get("position"), Some(3));
+    }
+
+    #[test]
+    fn parse_splits_on_first_equals() {
+        let modes = StateStore::parse("[volume]\ncmd=a=b\n");
+        assert_eq!(modes["volume"].get("cmd"), Some("a=b".to_string()));
+    }
+
+    #[test]
+    fn escape_round_trip() {
+        for s in &[
+            "",
+            "plain",
+            "a=b",
+            "=",
+            "a\nb",
+            "a\r\n",
+            "back\\slash\\",
+            "\\n",
+            "[section]",
+            "[",
+            "]",
+            " padded ",
+        ] {
+            let escaped = escape(s);
+            assert!(!escaped.contains(&['\n', '\r'][..]), "{:?}", s);
+            assert!(!escaped.starts_with('['), "{:?}", s);
+            assert_eq!(split_entry(&escaped), None, "{:?}", s);
+            assert_eq!(unescape(&escaped), *s);
+        }
+    }
+
+    #[test]
+    fn to_disk_round_trip() {
+        let tricky = [
+            "a=b",
+            "a\nb",
+            "a\r\n",
+            "back\\slash\\",
+            "[section]",
+            "[x=y]",
+            " padded ",
+            "",
+        ];
+
+        let mut store = StateStore {
+            path: temp_path("to_disk_round_trip"),
+            modes: BTreeMap::new(),
+        };
+        for (i, s) in tricky.iter().enumerate() {
+            store.mode("volume").set(&format!("key{}", i), s);
+            store.mode("volume").set(&format!("{}{}", s, i), i);
+            store.mode(&format!("{}{}", s, i)).set("key", s);
+        }
+        store
+            .dial("/sys/devices/virtual/misc/uhid/0005:045E:091B.0001")
+            .set("last_mode", "scroll");
+
+        let modes = round_trip(&mut store);
+
+        assert_eq!(modes.len(), store.modes.len());
+        for (id, state) in store.modes.iter() {
+            assert_eq!(modes[id].values, state.values, "{:?}", id);
+        }
+    }
+
+    #[test]
+    fn to_disk_skips_empty_modes_and_clears_dirty() {
+        let mut store = StateStore {
+            path: temp_path("to_disk_skips_empty_modes"),
+            modes: BTreeMap::new(),
+        };
+        store.mode("volume").set("position", 3);
+        store.mode("scroll").set("position", 1);
+        store.mode("scroll").remove("position");
+        assert!(store.is_dirty());
+
+        let modes = round_trip(&mut store);
+
+        assert!(!store.is_dirty());
+        assert_eq!(modes.keys().collect::<Vec<_>>(), vec!["volume"]);
+    }
+
+    #[test]
+    fn from_file_missing() {
+        let store = StateStore::from_file(temp_path("from_file_missing")).unwrap();
+        assert!(store.modes.is_empty());
+    }
+
+    #[test]
+    fn migrate_to_dial() {
+        let mut store = store();
+        store.daemon().set("last_mode", "volume");
+
+        store.migrate_to_dial("a", "last_mode");
+        store.migrate_to_dial("b", "last_mode");
+
+        assert_eq!(store.dial("a").get("last_mode"), Some("volume".to_string()));
+        assert_eq!(store.dial("b").get::<String>("last_mode"), None);
+        assert_eq!(store.daemon().get::<String>("last_mode"), None);
+        assert!(store.is_dirty());
+    }
+
+    #[test]
+    fn migrate_to_dial_keeps_dial_value() {
+        let mut store = store();
+        store.daemon().set("last_mode", "volume");
+        store.dial("a").set("last_mode", "scroll");
+
+        store.migrate_to_dial("a", "last_mode");
+
+        assert_eq!(store.dial("a").get("last_mode"), Some("scroll".to_string()));
+        assert_eq!(store.daemon().get::<String>("last_mode"), None);
+    }
+}
@@ -0,0 +1,213 @@
+use std::time::Duration;
+
+/// Identifies a timer. IDs are chosen by the mode which scheduled the timer,
+/// and are handed back to it via [`ControlMode::on_timer`].
+///
+/// [`ControlMode::on_timer`]: super::ControlMode::on_timer
+pub type TimerId = u32;
+
+struct Timer {
+    id: TimerId,
+    deadline: Duration,
+    period: Option<Duration>,
+}
+
+/// A set of pending one-shot and repeating timers.
+///
+/// Times are on the clock used to timestamp dial events (see
+/// [`dial_device::now`]).
+///
+/// `Timers` never reads the clock itself. Instead, the controller advances the
+/// current time to when each event occurred before dispatching it, which keeps
+/// timed behavior deterministic (and trivial to drive using a fake clock).
+///
+/// [`dial_device::now`]: crate::dial_device::now
+pub struct Timers {
+    now: Duration,
+    timers: Vec<Timer>,
+}
+
+impl Timers {
+    pub fn new(now: Duration) -> Timers {
+        Timers {
+            now,
+            timers: Vec::new(),
+        }
+    }
+
+    /// The time at which the event currently being handled occurred.
+    pub fn now(&self) -> Duration {
+        self.now
+    }
+
+    /// Fire timer `id` once, `delay` from now.
+    ///
+    /// Re-scheduling an existing timer replaces it.
+    pub fn schedule_once(&mut self, id: TimerId, delay: Duration) {
+        self.schedule(id, delay, None)
+    }
+
+    /// Fire timer `id` every `period`, starting `period` from now.
+    ///
+    /// Re-scheduling an existing timer replaces it.
+    pub fn schedule_repeating(&mut self, id: TimerId, period: Duration) {
+        self.schedule(id, period, Some(period))
+    }
+
+    pub fn cancel(&mut self, id: TimerId) {
+        self.timers.retain(|t| t.id != id);
+    }
+
+    pub fn is_scheduled(&self, id: TimerId) -> bool {
+        self.timers.iter().any(|t| t.id == id)
+    }
+
+    fn schedule(&mut self, id: TimerId, delay: Duration, period: Option<Duration>) {
+        self.cancel(id);
+        self.timers.push(Timer {
+            id,
+            deadline: self.now + delay,
+            period,
+        });
+    }
+
+    pub(super) fn set_now(&mut self, now: Duration) {
+        self.now = now;
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.timers.clear();
+    }
+
+    pub(super) fn next_deadline(&self) -> Option<Duration> {
+        self.timers.iter().map(|t| t.deadline).min()
+    }
+
+    /// Return the ID of the earliest expired timer (if any), rescheduling it
+    /// if it's a repeating timer.
+    pub(super) fn pop_expired(&mut self) -> Option<TimerId> {
+        let now = self.now;
+        let (idx, _) = self
+            .timers
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.deadline <= now)
+            .min_by_key(|(_, t)| t.deadline)?;
+
+        let timer = &mut self.timers[idx];
+        let id = timer.id;
+        match timer.period {
+            Some(period) => {
+                // if the controller fell behind, don't try to "catch up" by
+                // firing a burst of back-to-back ticks
+                timer.deadline += period;
+                if timer.deadline <= now {
+                    timer.deadline = now + period;
+                }
+            }
+            None => {
+                self.timers.swap_remove(idx);
+            }
+        }
+
+        Some(id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    /// Advance to `now`, returning every timer which fired (in order).
+    fn advance(timers: &mut Timers, now: Duration) -> Vec<TimerId> {
+        timers.set_now(now);
+        std::iter::from_fn(|| timers.pop_expired()).collect()
+    }
+
+    #[test]
+    fn fires_in_deadline_order() {
+        let mut timers = Timers::new(ms(0));
+        timers.schedule_once(1, ms(30));
+        timers.schedule_once(2, ms(10));
+        timers.schedule_once(3, ms(20));
+
+        assert_eq!(timers.next_deadline(), Some(ms(10)));
+        assert_eq!(advance(&mut timers, ms(9)), vec![]);
+        assert_eq!(advance(&mut timers, ms(25)), vec![2, 3]);
+        assert_eq!(advance(&mut timers, ms(30)), vec![1]);
+        assert_eq!(timers.next_deadline(), None);
+    }
+
+    #[test]
+    fn cancel() {
+        let mut timers = Timers::new(ms(0));
+        timers.schedule_once(1, ms(10));
+        timers.schedule_repeating(2, ms(10));
+        timers.cancel(1);
+
+        assert!(!timers.is_scheduled(1));
+        assert!(timers.is_scheduled(2));
+        assert_eq!(advance(&mut timers, ms(10)), vec![2]);
+
+        timers.cancel(2);
+        assert_eq!(advance(&mut timers, ms(100)), vec![]);
+    }
+
+    #[test]
+    fn rescheduling_replaces_the_timer() {
+        let mut timers = Timers::new(ms(0));
+        timers.schedule_once(1, ms(10));
+        timers.set_now(ms(5));
+        timers.schedule_once(1, ms(10));
+
+        assert_eq!(advance(&mut timers, ms(10)), vec![]);
+        assert_eq!(advance(&mut timers, ms(15)), vec![1]);
+        assert_eq!(advance(&mut timers, ms(100)), vec![]);
+    }
+
+    #[test]
+    fn repeating_timer_rearms() {
+        let mut timers = Timers::new(ms(0));
+        timers.schedule_repeating(1, ms(10));
+
+        assert_eq!(advance(&mut timers, ms(10)), vec![1]);
+        assert_eq!(timers.next_deadline(), Some(ms(20)));
+        assert_eq!(advance(&mut timers, ms(25)), vec![1]);
+        assert_eq!(timers.next_deadline(), Some(ms(30)));
+    }
+
+    #[test]
+    fn repeating_timer_doesnt_catch_up() {
+        let mut timers = Timers::new(ms(0));
+        timers.schedule_repeating(1, ms(10));
+
+        // fires once (instead of 10 times in a row), and picks up from now
+        assert_eq!(advance(&mut timers, ms(100)), vec![1]);
+        assert_eq!(timers.next_deadline(), Some(ms(110)));
+    }
+
+    #[test]
+    fn schedules_relative_to_now() {
+        let mut timers = Timers::new(ms(0));
+        timers.set_now(ms(50));
+        timers.schedule_once(1, ms(10));
+
+        assert_eq!(timers.now(), ms(50));
+        assert_eq!(timers.next_deadline(), Some(ms(60)));
+    }
+
+    #[test]
+    fn clear() {
+        let mut timers = Timers::new(ms(0));
+        timers.schedule_once(1, ms(10));
+        timers.schedule_repeating(2, ms(10));
+        timers.clear();
+
+        assert_eq!(timers.next_deadline(), None);
+        assert_eq!(advance(&mut timers, ms(100)), vec![]);
+    }
+}
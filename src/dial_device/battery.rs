@@ -0,0 +1,121 @@
+//! Battery level reporting.
+//!
+//! The kernel usually exposes the dial's battery level as a `power_supply`
+//! belonging to the dial's HID device. If that's missing, the level is queried
+//! from BlueZ's `org.bluez.Battery1` D-Bus interface instead.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use super::events::{DialSenders, RawInputEvent};
+use crate::bluez::Bluez;
+
+/// Batteries drain slowly, so there's no need to check very often.
+const POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// Returns the battery level (as a percentage), if it's available.
+///
+/// `hid_syspath` is the sysfs path of the dial's HID device, and `address` is
+/// the dial's Bluetooth address.
+fn read_battery(hid_syspath: Option<&Path>, address: &str) -> Option<u8> {
+    hid_syspath
+        .and_then(read_sysfs)
+        .or_else(|| read_bluez(address))
+}
+
+/// i.e: `<hid device>/power_supply/<name>/capacity`
+fn read_sysfs(hid_syspath: &Path) -> Option<u8> {
+    fs::read_dir(hid_syspath.join("power_supply"))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| fs::read_to_string(entry.path().join("capacity")).ok())
+        .find_map(|capacity| parse_capacity(&capacity))
+}
+
+/// Parses a `power_supply`'s `capacity` attribute (e.g: `"80\n"`).
+fn parse_capacity(capacity: &str) -> Option<u8> {
+    let capacity = capacity.trim().parse::<u8>().ok()?;
+    Some(capacity.min(100))
+}
+
+fn read_bluez(address: &str) -> Option<u8> {
+    Bluez::system().ok()?.device(address).ok()??.battery
+}
+
+/// Periodically report the dial's battery level (whenever it changes), until
+/// `stop` is disconnected.
+pub(super) fn battery_loop(
+    dial: DialSenders,
+    hid_syspath: Option<PathBuf>,
+    address: String,
+    stop: mpsc::Receiver<()>,
+) {
+    let mut last_level = None;
+    loop {
+        if let Some(level) = read_battery(hid_syspath.as_deref(), &address) {
+            if last_level != Some(level) {
+                last_level = Some(level);
+                let _ = dial.events.send(RawInputEvent::Battery(level));
+            }
+        }
+
+        match stop.recv_timeout(POLL_INTERVAL) {
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            _ => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_capacity_values() {
+        assert_eq!(parse_capacity("80\n"), Some(80));
+        assert_eq!(parse_capacity("0"), Some(0));
+        assert_eq!(parse_capacity("100\n"), Some(100));
+        // some drivers overshoot
+        assert_eq!(parse_capacity("104\n"), Some(100));
+        assert_eq!(parse_capacity(""), None);
+        assert_eq!(parse_capacity("-1\n"), None);
+        assert_eq!(parse_capacity("Full\n"), None);
+    }
+
+    /// Lays out a stand-in `<hid device>/power_supply` tree.
+    fn fake_hid_device(name: &str, supplies: &[(&str, &str)]) -> PathBuf {
+        let hid_syspath =
+            std::env::temp_dir().join(format!("surface-dial-test-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&hid_syspath);
+        for (supply, capacity) in supplies {
+            let dir = hid_syspath.join("power_supply").join(supply);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join("capacity"), capacity).unwrap();
+        }
+        fs::create_dir_all(&hid_syspath).unwrap();
+        hid_syspath
+    }
+
+    #[test]
+    fn read_sysfs_capacity() {
+        let hid_syspath = fake_hid_device("capacity", &[("hid-dial-battery", "42\n")]);
+        assert_eq!(read_sysfs(&hid_syspath), Some(42));
+        fs::remove_dir_all(hid_syspath).unwrap();
+    }
+
+    #[test]
+    fn read_sysfs_skips_unreadable_supplies() {
+        let hid_syspath = fake_hid_device("unreadable", &[("hid-dial-battery", "Unknown\n")]);
+        assert_eq!(read_sysfs(&hid_syspath), None);
+        fs::remove_dir_all(hid_syspath).unwrap();
+    }
+
+    #[test]
+    fn read_sysfs_without_power_supply() {
+        let hid_syspath = fake_hid_device("missing", &[]);
+        assert_eq!(read_sysfs(&hid_syspath), None);
+        fs::remove_dir_all(hid_syspath).unwrap();
+    }
+}
@@ -1,104 +1,190 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fs;
-use std::sync::mpsc;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
 use std::time::Duration;
 
 use evdev_rs::{InputEvent, ReadStatus};
+use nix::time::ClockId;
 use std::os::unix::io::AsRawFd;
 
-use super::DialHapticsWorkerMsg;
+use super::battery;
+use super::haptics::SharedHidApi;
+use super::hidraw_input;
+use super::info::DeviceInfoHandle;
+use super::profiles::{self, DeviceProfile};
+use super::{DialDevice, DialEvent, DialHapticsWorkerMsg, InputBackend};
 
 pub enum RawInputEvent {
-    Event(ReadStatus, InputEvent),
+    /// Includes the profile of the node the event was read from.
+    Event(&'static DeviceProfile, ReadStatus, InputEvent),
+    /// An event which has already been decoded (i.e: by the hidraw backend).
+    Decoded(DialEvent),
     Connect,
     Disconnect,
+    /// Battery level (as a percentage)
+    Battery(u8),
+    /// Not a real event - used to wake up the thread blocked on `next_event`
+    Wake,
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
-pub enum DialInputKind {
-    Control,
-    MultiAxis,
+/// Handles used to route events to a particular dial's [`DialDevice`].
+#[derive(Clone)]
+pub(super) struct DialSenders {
+    pub events: mpsc::Sender<RawInputEvent>,
+    pub haptics_msg: mpsc::Sender<DialHapticsWorkerMsg>,
+    pub info: DeviceInfoHandle,
 }
 
+/// Watches udev for dials (i.e: any device with a matching [`DeviceProfile`]),
+/// spawning a thread to read events from each of the dial's evdev nodes.
+///
+/// A dial may expose several evdev nodes (e.g: the Surface Dial's "System
+/// Control" and "System Multi Axis" nodes), whose events are interleaved into
+/// a single stream. When using [`InputBackend::Hidraw`], events are read from
+/// the dial's hidraw node instead (which carries the reports behind every
+/// evdev node).
+///
+/// The first time a dial is seen, a new [`DialDevice`] is created for it and
+/// sent down `new_dials`. When a dial reconnects, its events are routed to the
+/// existing `DialDevice`.
 pub struct EventsWorker {
-    events: mpsc::Sender<RawInputEvent>,
-    haptics_msg: mpsc::Sender<DialHapticsWorkerMsg>,
-    input_kind: DialInputKind,
+    long_press_timeout: Duration,
+    backend: InputBackend,
+    grab: bool,
+    hid_api: SharedHidApi,
+    new_dials: mpsc::Sender<std::io::Result<DialDevice>>,
+
+    /// Keyed by dial ID (see [`dial_id`]).
+    dials: HashMap<String, DialSenders>,
+    /// evdev nodes that currently have a reader thread.
+    active: Arc<Mutex<HashSet<PathBuf>>>,
 }
 
 impl EventsWorker {
     pub(super) fn new(
-        input_kind: DialInputKind,
-        events: mpsc::Sender<RawInputEvent>,
-        haptics_msg: mpsc::Sender<DialHapticsWorkerMsg>,
+        long_press_timeout: Duration,
+        backend: InputBackend,
+        grab: bool,
+        new_dials: mpsc::Sender<std::io::Result<DialDevice>>,
     ) -> EventsWorker {
         EventsWorker {
-            input_kind,
-            events,
-            haptics_msg,
+            long_press_timeout,
+            backend,
+            grab,
+            hid_api: Arc::new(Mutex::new(None)),
+            new_dials,
+
+            dials: HashMap::new(),
+            active: Arc::new(Mutex::new(HashSet::new())),
         }
     }
 
-    fn udev_to_evdev(&self, device: &udev::Device) -> std::io::Result<Option<evdev_rs::Device>> {
+    fn on_device(&mut self, device: &udev::Device) -> std::io::Result<()> {
         let devnode = match device.devnode() {
-            Some(path) => path,
-            None => return Ok(None),
+            Some(path) => path.to_path_buf(),
+            None => return Ok(()),
         };
 
-        // we care about the `/dev/input/eventXX` device, which is a child of the
-        // actual input device (that has a nice name we can match against)
-        match device.parent() {
-            None => return Ok(None),
-            Some(parent) => {
-                let name = parent
-                    .property_value("NAME")
-                    .unwrap_or_else(|| std::ffi::OsStr::new(""))
-                    .to_string_lossy();
-
-                match (self.input_kind, name.as_ref()) {
-                    (DialInputKind::Control, r#""Surface Dial System Control""#) => {}
-                    (DialInputKind::MultiAxis, r#""Surface Dial System Multi Axis""#) => {}
-                    _ => return Ok(None),
-                }
-            }
-        }
+        let profile = match profiles::find(device) {
+            Some(profile) => profile,
+            None => return Ok(()),
+        };
 
-        let file = fs::File::open(devnode)?;
-        evdev_rs::Device::new_from_fd(file).map(Some)
-    }
+        // the hidraw node carries the reports from _every_ evdev node
+        let use_hidraw = self.backend == InputBackend::Hidraw && profile.raw_reports;
+        if use_hidraw && !profile.primary {
+            return Ok(());
+        }
 
-    fn event_loop(&mut self, device: evdev_rs::Device) -> std::io::Result<()> {
-        // HACK: don't want to double-send these events
-        if self.input_kind != DialInputKind::Control {
-            self.haptics_msg
-                .send(DialHapticsWorkerMsg::DialConnected)
-                .unwrap();
-            self.events.send(RawInputEvent::Connect).unwrap();
+        if self.active.lock().unwrap().contains(&devnode) {
+            return Ok(());
         }
 
-        loop {
-            let _ = self
-                .events
-                .send(match device.next_event(evdev_rs::ReadFlag::BLOCKING) {
-                    Ok((read_status, event)) => RawInputEvent::Event(read_status, event),
-                    // this error corresponds to the device disconnecting, which is fine
-                    Err(e) if e.raw_os_error() == Some(19) => break,
-                    Err(e) => return Err(e),
-                });
+        let hid_syspath = find_hid_parent(device);
+
+        let id = dial_id(device, hid_syspath.as_deref());
+        let dial = match self.dials.get(&id) {
+            Some(dial) => dial.clone(),
+            None => {
+                eprintln!("found new dial: {} ({})", id, profile.name);
+                let res =
+                    DialDevice::new(id.clone(), self.long_press_timeout, self.hid_api.clone());
+                let (device, dial) = match res {
+                    Ok(res) => res,
+                    Err(e) => {
+                        eprintln!("could not set up dial {}: {}", id, e);
+                        return Ok(());
+                    }
+                };
+                let _ = self.new_dials.send(Ok(device));
+                self.dials.insert(id.clone(), dial.clone());
+                dial
+            }
+        };
+
+        self.active.lock().unwrap().insert(devnode.clone());
+
+        // stops the battery thread once the reader thread exits
+        let (stop_battery_tx, stop_battery) = mpsc::channel::<()>();
+        if profile.primary {
+            std::thread::spawn({
+                let dial = dial.clone();
+                let hid_syspath = hid_syspath.clone();
+                move || battery::battery_loop(dial, hid_syspath, id, stop_battery)
+            });
         }
 
-        // HACK: don't want to double-send these events
-        if self.input_kind != DialInputKind::Control {
-            self.haptics_msg
-                .send(DialHapticsWorkerMsg::DialDisconnected)
-                .unwrap();
-            self.events.send(RawInputEvent::Disconnect).unwrap();
+        let hidraw = hid_syspath.as_deref().and_then(find_hidraw);
+        if profile.primary {
+            let mut info = dial.info.0.lock().unwrap();
+            info.profile = Some(profile.name);
+            info.evdev = Some(devnode.clone());
+            info.hidraw = hidraw.clone();
+            info.version = device
+                .parent()
+                .and_then(|parent| read_version(parent.syspath()));
         }
 
+        let grab = self.grab;
+        std::thread::spawn({
+            let active = self.active.clone();
+            move || {
+                let res = match (use_hidraw, &hidraw) {
+                    (true, Some(hidraw)) => hidraw_input::event_loop(&dial, hidraw),
+                    (true, None) => {
+                        eprintln!("no hidraw node for {:?}, falling back to evdev", devnode);
+                        event_loop(profile, &dial, &devnode, None, grab)
+                    }
+                    (false, _) => event_loop(profile, &dial, &devnode, hidraw.clone(), grab),
+                };
+                if let Err(e) = res {
+                    eprintln!("could not read events from {:?}: {}", devnode, e);
+                }
+                active.lock().unwrap().remove(&devnode);
+                drop(stop_battery_tx);
+
+                if profile.primary {
+                    let mut info = dial.info.0.lock().unwrap();
+                    info.profile = None;
+                    info.evdev = None;
+                    info.hidraw = None;
+                    info.version = None;
+                }
+            }
+        });
+
         Ok(())
     }
 
     pub fn run(&mut self) -> std::io::Result<()> {
-        // eagerly check if the device already exists
+        // start listening _before_ scanning for existing devices, so that dials
+        // which show up in the meantime aren't missed
+
+        let mut socket = udev::MonitorBuilder::new()?
+            .match_subsystem("input")?
+            .listen()?;
 
         let mut enumerator = {
             let mut e = udev::Enumerator::new()?;
@@ -106,20 +192,11 @@ impl EventsWorker {
             e
         };
         for device in enumerator.scan_devices()? {
-            let dev = match self.udev_to_evdev(&device)? {
-                None => continue,
-                Some(dev) => dev,
-            };
-
-            self.event_loop(dev)?;
+            self.on_device(&device)?;
         }
 
         // enter udev event loop to gracefully handle disconnect/reconnect
 
-        let mut socket = udev::MonitorBuilder::new()?
-            .match_subsystem("input")?
-            .listen()?;
-
         loop {
             nix::poll::ppoll(
                 &mut [nix::poll::PollFd::new(
@@ -143,12 +220,126 @@ impl EventsWorker {
                 continue;
             }
 
-            let dev = match self.udev_to_evdev(&event.device())? {
-                None => continue,
-                Some(dev) => dev,
-            };
+            self.on_device(&event.device())?;
+        }
+    }
+}
+
+/// Forward events from a single evdev node until it disconnects.
+///
+/// If `grab` is set, the node is grabbed for as long as it's being read from.
+/// The kernel also releases the grab whenever the node is closed, so the grab
+/// never outlives the daemon.
+fn event_loop(
+    profile: &'static DeviceProfile,
+    dial: &DialSenders,
+    devnode: &Path,
+    hidraw: Option<PathBuf>,
+    grab: bool,
+) -> std::io::Result<()> {
+    let mut device = evdev_rs::Device::new_from_fd(fs::File::open(devnode)?)?;
+    // match the clock used by `dial_device::now`
+    device.set_clock_id(ClockId::CLOCK_MONOTONIC.as_raw())?;
+
+    // not fatal, since the daemon works just fine without the grab
+    if grab {
+        if let Err(e) = device.grab(evdev_rs::GrabMode::Grab) {
+            eprintln!("could not grab {:?}: {}", devnode, e);
+        }
+    }
+
+    // HACK: don't want to double-send these events
+    if profile.primary {
+        if profile.haptics {
+            let _ = dial
+                .haptics_msg
+                .send(DialHapticsWorkerMsg::DialConnected(hidraw));
+        }
+        let _ = dial.events.send(RawInputEvent::Connect);
+    }
+
+    let res = loop {
+        let _ = dial
+            .events
+            .send(match device.next_event(evdev_rs::ReadFlag::BLOCKING) {
+                Ok((read_status, event)) => RawInputEvent::Event(profile, read_status, event),
+                // this error corresponds to the device disconnecting, which is fine
+                Err(e) if e.raw_os_error() == Some(19) => break Ok(()),
+                Err(e) => break Err(e),
+            });
+    };
+
+    // fails if the node has already disappeared, which is fine
+    if grab {
+        let _ = device.grab(evdev_rs::GrabMode::Ungrab);
+    }
+
+    // HACK: don't want to double-send these events
+    if profile.primary {
+        if profile.haptics {
+            let _ = dial
+                .haptics_msg
+                .send(DialHapticsWorkerMsg::DialDisconnected);
+        }
+        let _ = dial.events.send(RawInputEvent::Disconnect);
+    }
+
+    res
+}
+
+/// A stable identifier for the physical dial an evdev node belongs to.
+///
+/// This is the dial's Bluetooth address, which the kernel exposes as the
+/// `UNIQ` property of the parent input device. If that's missing, falls back to
+/// the sysfs path of the dial's HID device (see [`find_hid_parent`]), which is
+/// shared by all of the dial's evdev nodes, but is only stable for as long as
+/// the dial stays connected.
+fn dial_id(device: &udev::Device, hid_syspath: Option<&Path>) -> String {
+    let uniq = device
+        .parent()
+        .and_then(|parent| {
+            let uniq = parent.property_value("UNIQ")?;
+            Some(uniq.to_string_lossy().trim_matches('"').to_lowercase())
+        })
+        .filter(|uniq| !uniq.is_empty());
 
-            self.event_loop(dev)?;
+    match (uniq, hid_syspath) {
+        (Some(uniq), _) => uniq,
+        (None, Some(hid_syspath)) => hid_syspath.to_string_lossy().into_owned(),
+        // not a HID device, so there are no sibling evdev nodes to group with
+        (None, None) => device.syspath().to_string_lossy().into_owned(),
+    }
+}
+
+/// i.e: `<input device>/id/version`, which holds the device's version number
+/// (in hex).
+fn read_version(input_syspath: &Path) -> Option<u16> {
+    let version = fs::read_to_string(input_syspath.join("id/version")).ok()?;
+    u16::from_str_radix(version.trim(), 16).ok()
+}
+
+/// Find the sysfs path of the HID device an evdev node belongs to.
+///
+/// i.e: `<hid device>/input/inputXX/eventXX` => `<hid device>`
+fn find_hid_parent(device: &udev::Device) -> Option<PathBuf> {
+    let mut parent = device.parent();
+    while let Some(dev) = parent {
+        if dev.subsystem() == Some(OsStr::new("hid")) {
+            return Some(dev.syspath().to_path_buf());
         }
+        parent = dev.parent();
     }
+    None
+}
+
+/// Find the hidraw node belonging to the same physical device as an evdev
+/// node, given their common HID parent.
+///
+/// i.e: `<hid device>` => `<hid device>/hidraw/hidrawXX`
+fn find_hidraw(hid_syspath: &Path) -> Option<PathBuf> {
+    let entry = fs::read_dir(hid_syspath.join("hidraw"))
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .next()?;
+    Some(Path::new("/dev").join(entry.file_name()))
 }
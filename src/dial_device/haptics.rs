@@ -1,120 +1,526 @@
-use std::sync::mpsc;
+//! Haptic feedback, as described by the HID "Haptics" usage page (0x0E).
+//!
+//! The dial supports two kinds of feedback:
+//!
+//! - _automatic_ feedback, which the dial generates by itself whenever it's
+//!   rotated past a detent (see [`AutoFeedback`])
+//! - _manual_ feedback, which is triggered on-demand (see [`ManualTrigger`])
+//!
+//! The set of supported waveforms (and their durations) is read from the
+//! device when it connects, and can be queried via [`DialHaptics::info`].
+//!
+//! NOTE: the dial's report descriptor does _not_ include the Intensity (0x23)
+//! usage, so there's no way to control how strong the feedback feels.
+//! Waveform choice, repeat count, and retrigger period are the only knobs.
+
+use std::ffi::{CString, OsStr};
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use hidapi::{HidApi, HidDevice};
 
+use super::hid_descriptor::{self, usage, ReportKind, Usage};
+use super::info::{DeviceInfoHandle, ReportSummary};
+use super::patterns::{HapticPattern, Sequencer};
+use super::profiles::SURFACE_DIAL_ID;
 use crate::error::{Error, Result};
+use crate::feedback;
+
+const HAPTICS_PAGE: u16 = 0x0e;
+const ORDINAL_PAGE: u16 = 0x0a;
+
+const WAVEFORM_LIST: Usage = usage(HAPTICS_PAGE, 0x10);
+const DURATION_LIST: Usage = usage(HAPTICS_PAGE, 0x11);
+const AUTO_TRIGGER_ASSOCIATED_CONTROL: Usage = usage(HAPTICS_PAGE, 0x22);
+
+// from the report descriptor
+const MAX_CUTOFF_TIME: u8 = 10;
+const MAX_RETRIGGER_PERIOD: Duration = Duration::from_millis(2000);
+
+/// Standard haptic waveforms.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Waveform {
+    None,
+    Stop,
+    Click,
+    BuzzContinuous,
+    RumbleContinuous,
+    Press,
+    Release,
+    /// Any other (i.e: vendor-defined) waveform, by usage ID.
+    Other(u16),
+}
+
+impl Waveform {
+    fn from_usage_id(id: u16) -> Waveform {
+        match id {
+            0x1001 => Waveform::None,
+            0x1002 => Waveform::Stop,
+            0x1003 => Waveform::Click,
+            0x1004 => Waveform::BuzzContinuous,
+            0x1005 => Waveform::RumbleContinuous,
+            0x1006 => Waveform::Press,
+            0x1007 => Waveform::Release,
+            other => Waveform::Other(other),
+        }
+    }
+}
+
+/// A waveform supported by the device.
+#[derive(Debug, Clone)]
+pub struct WaveformInfo {
+    pub waveform: Waveform,
+    /// Index used to refer to this waveform in reports.
+    pub ordinal: u8,
+    /// How long the waveform lasts (`None` if unknown, or continuous).
+    pub duration: Option<Duration>,
+}
+
+/// The haptic capabilities reported by the device.
+#[derive(Debug, Clone)]
+pub struct HapticInfo {
+    pub waveforms: Vec<WaveformInfo>,
+    /// The control which automatic feedback is tied to (in practice, Generic
+    /// Desktop / Dial).
+    pub auto_trigger_control: Usage,
+}
+
+impl HapticInfo {
+    /// Used if the capabilities can't be read from the device. Matches the
+    /// layout in `notes/HID_Report_Descriptor.txt`.
+    fn fallback() -> HapticInfo {
+        let waveforms = vec![
+            (Waveform::None, 1),
+            (Waveform::Stop, 2),
+            (Waveform::Click, 3),
+            (Waveform::BuzzContinuous, 4),
+        ];
+
+        HapticInfo {
+            waveforms: waveforms
+                .into_iter()
+                .map(|(waveform, ordinal)| WaveformInfo {
+                    waveform,
+                    ordinal,
+                    duration: None,
+                })
+                .collect(),
+            auto_trigger_control: usage(0x01, 0x37),
+        }
+    }
+
+    pub fn ordinal(&self, waveform: Waveform) -> Option<u8> {
+        self.waveforms
+            .iter()
+            .find(|w| w.waveform == waveform)
+            .map(|w| w.ordinal)
+    }
+}
+
+/// Configures the feedback the dial generates by itself as it's rotated.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AutoFeedback {
+    /// How many sections the dial should be divided into (from 0 to 3600).
+    pub steps: u16,
+    /// Played whenever the dial is rotated into the next section.
+    /// `Waveform::Stop` disables automatic feedback entirely.
+    pub waveform: Waveform,
+    /// How many additional times to play the waveform.
+    pub repeat: u8,
+    /// Upper bound on how long a continuous waveform plays for, in (unknown)
+    /// device units from 0 to 10.
+    pub cutoff_time: u8,
+    /// Delay between repeats (at most 2s).
+    pub retrigger_period: Duration,
+}
+
+impl AutoFeedback {
+    /// Evenly spaced clicks (or no feedback at all, if `enabled` is false).
+    pub fn detents(steps: u16, enabled: bool) -> AutoFeedback {
+        AutoFeedback {
+            steps,
+            waveform: if enabled {
+                Waveform::Click
+            } else {
+                Waveform::Stop
+            },
+            repeat: 0,
+            cutoff_time: 0,
+            retrigger_period: Duration::from_millis(0),
+        }
+    }
+}
+
+/// A one-off haptic effect.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ManualTrigger {
+    pub waveform: Waveform,
+    /// How many additional times to play the waveform.
+    pub repeat: u8,
+    /// Delay between repeats (at most 2s).
+    pub retrigger_period: Duration,
+}
+
+impl ManualTrigger {
+    pub const fn new(waveform: Waveform) -> ManualTrigger {
+        ManualTrigger {
+            waveform,
+            repeat: 0,
+            retrigger_period: Duration::from_millis(0),
+        }
+    }
+}
 
 /// Proxy object - forwards requests to the DialHapticsWorker task
 pub struct DialHaptics {
     msg: mpsc::Sender<DialHapticsWorkerMsg>,
+    info: DeviceInfoHandle,
 }
 
 impl DialHaptics {
-    pub(super) fn new(msg: mpsc::Sender<DialHapticsWorkerMsg>) -> Result<DialHaptics> {
-        Ok(DialHaptics { msg })
+    pub(super) fn new(
+        msg: mpsc::Sender<DialHapticsWorkerMsg>,
+        info: DeviceInfoHandle,
+    ) -> Result<DialHaptics> {
+        Ok(DialHaptics { msg, info })
+    }
+
+    /// The haptic capabilities of the connected dial (if any).
+    pub fn info(&self) -> Option<HapticInfo> {
+        self.info.0.lock().unwrap().haptics.clone()
     }
 
     /// `steps` should be a value between 0 and 3600, which corresponds to the
     /// number of subdivisions the dial should use.
     pub fn set_mode(&self, haptics: bool, steps: u16) -> Result<()> {
-        let _ = (self.msg).send(DialHapticsWorkerMsg::SetMode { haptics, steps });
+        self.set_auto(AutoFeedback::detents(steps, haptics))
+    }
+
+    pub fn set_auto(&self, auto: AutoFeedback) -> Result<()> {
+        if auto.steps > 3600 {
+            return Err(Error::InvalidSteps(auto.steps));
+        }
+
+        let _ = (self.msg).send(DialHapticsWorkerMsg::SetAuto(auto));
+        Ok(())
+    }
+
+    pub fn trigger(&self, trigger: ManualTrigger) -> Result<()> {
+        let _ = (self.msg).send(DialHapticsWorkerMsg::Trigger(trigger));
         Ok(())
     }
 
-    pub fn buzz(&self, repeat: u8) -> Result<()> {
-        let _ = (self.msg).send(DialHapticsWorkerMsg::Manual { repeat });
+    /// Play one of the built-in [`HapticPattern`]s, cancelling any pattern
+    /// that's currently playing.
+    pub fn play(&self, pattern: &str) -> Result<()> {
+        let pattern = HapticPattern::by_name(pattern)
+            .ok_or_else(|| Error::UnknownHapticPattern(pattern.to_string()))?;
+        let _ = (self.msg).send(DialHapticsWorkerMsg::Play(pattern));
         Ok(())
     }
 }
 
 #[derive(Debug)]
 pub(super) enum DialHapticsWorkerMsg {
-    DialConnected,
+    /// Includes the path to the dial's hidraw node (if it could be found).
+    DialConnected(Option<PathBuf>),
     DialDisconnected,
-    SetMode { haptics: bool, steps: u16 },
-    Manual { repeat: u8 },
+    SetAuto(AutoFeedback),
+    Trigger(ManualTrigger),
+    Play(&'static HapticPattern),
 }
 
+/// hidapi only allows a single `HidApi` instance at a time, so it's shared
+/// between every dial's haptics worker (and lazily initialized on first use).
+pub(super) type SharedHidApi = Arc<Mutex<Option<HidApi>>>;
+
 pub(super) struct DialHapticsWorker {
     msg: mpsc::Receiver<DialHapticsWorkerMsg>,
+    info: DeviceInfoHandle,
+    hid_api: SharedHidApi,
+    sequencer: Sequencer,
 }
 
 impl DialHapticsWorker {
-    pub(super) fn new(msg: mpsc::Receiver<DialHapticsWorkerMsg>) -> Result<DialHapticsWorker> {
-        Ok(DialHapticsWorker { msg })
+    pub(super) fn new(
+        msg: mpsc::Receiver<DialHapticsWorkerMsg>,
+        info: DeviceInfoHandle,
+        hid_api: SharedHidApi,
+    ) -> Result<DialHapticsWorker> {
+        Ok(DialHapticsWorker {
+            msg,
+            info,
+            hid_api,
+            sequencer: Sequencer::new(),
+        })
     }
 
-    pub(super) fn run(&mut self) -> Result<()> {
+    /// Haptics are a nice-to-have, so instead of bringing down the entire
+    /// daemon, errors are reported and haptics are disabled until the next
+    /// time the dial connects.
+    pub(super) fn run(&mut self) -> ! {
         loop {
             eprintln!("haptics worker is waiting...");
 
-            loop {
+            let hidraw = loop {
                 match self.msg.recv().unwrap() {
-                    DialHapticsWorkerMsg::DialConnected => break,
+                    DialHapticsWorkerMsg::DialConnected(hidraw) => break hidraw,
                     other => eprintln!("haptics worker dropped an event: {:?}", other),
                 }
-            }
+            };
 
             eprintln!("haptics worker is ready");
 
-            let api = HidApi::new().map_err(Error::HidError)?;
-            let hid_device = api.open(0x045e, 0x091b).map_err(Error::HidError)?;
-            let wrapper = DialHidWrapper { hid_device };
+            let wrapper = match DialHidWrapper::open(&self.hid_api, hidraw) {
+                Ok(wrapper) => wrapper,
+                Err(e) => {
+                    report_error(&format!(
+                        "Haptic feedback unavailable: {}. Check the permissions on the \
+                         dial's /dev/hidraw device (see the included udev rules).",
+                        e
+                    ));
+                    continue;
+                }
+            };
 
-            loop {
-                match self.msg.recv().unwrap() {
-                    DialHapticsWorkerMsg::DialConnected => {
-                        eprintln!("Unexpected haptics worker ready event.");
-                        // should be fine though?
-                    }
-                    DialHapticsWorkerMsg::DialDisconnected => break,
-                    DialHapticsWorkerMsg::SetMode { haptics, steps } => {
-                        wrapper.set_mode(haptics, steps)?
+            {
+                let mut info = self.info.0.lock().unwrap();
+                info.haptics = Some(wrapper.info.clone());
+                info.reports = wrapper.reports.clone();
+                info.haptic_report = wrapper.haptic_report.clone();
+            }
+
+            if let Err(e) = self.serve(&wrapper) {
+                report_error(&format!("Haptic feedback stopped working: {}", e));
+            }
+
+            self.sequencer.stop();
+            {
+                let mut info = self.info.0.lock().unwrap();
+                info.haptics = None;
+                info.reports.clear();
+                info.haptic_report.clear();
+            }
+        }
+    }
+
+    /// Handle messages until the dial disconnects (or an error occurs).
+    fn serve(&mut self, wrapper: &DialHidWrapper) -> Result<()> {
+        loop {
+            // only block for as long as the current pattern allows
+            let msg = match self.sequencer.next_deadline() {
+                None => Some(self.msg.recv().unwrap()),
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    match self.msg.recv_timeout(timeout) {
+                        Ok(msg) => Some(msg),
+                        Err(mpsc::RecvTimeoutError::Timeout) => None,
+                        Err(mpsc::RecvTimeoutError::Disconnected) => {
+                            panic!("haptics channel disconnected")
+                        }
                     }
-                    DialHapticsWorkerMsg::Manual { repeat } => wrapper.buzz(repeat)?,
+                }
+            };
+
+            match msg {
+                None => {}
+                Some(DialHapticsWorkerMsg::DialConnected(_)) => {
+                    eprintln!("Unexpected haptics worker ready event.");
+                    // should be fine though?
+                }
+                Some(DialHapticsWorkerMsg::DialDisconnected) => return Ok(()),
+                Some(DialHapticsWorkerMsg::SetAuto(auto)) => wrapper.set_auto(auto)?,
+                Some(DialHapticsWorkerMsg::Trigger(trigger)) => {
+                    self.sequencer.stop();
+                    wrapper.trigger(trigger)?
+                }
+                Some(DialHapticsWorkerMsg::Play(pattern)) => {
+                    self.sequencer.play(pattern, Instant::now())
                 }
             }
+
+            while let Some(trigger) = self.sequencer.pop_due(Instant::now()) {
+                wrapper.trigger(trigger)?;
+            }
         }
     }
 }
 
+fn report_error(msg: &str) {
+    eprintln!("{}", msg);
+    if let Err(e) = feedback::backend().message(msg, "dialog-warning") {
+        eprintln!("could not show warning: {}", e);
+    }
+}
+
 struct DialHidWrapper {
     hid_device: HidDevice,
+    path: PathBuf,
+    info: HapticInfo,
+    // only used for diagnostics
+    reports: Vec<ReportSummary>,
+    haptic_report: Vec<u8>,
 }
 
 impl DialHidWrapper {
-    /// `steps` should be a value between 0 and 3600, which corresponds to the
-    /// number of subdivisions the dial should use. If left unspecified, this
-    /// defaults to 36 (an arbitrary choice that "feels good" most of the time)
-    fn set_mode(&self, haptics: bool, steps: u16) -> Result<()> {
-        assert!(steps <= 3600);
+    /// `hidraw` should be the hidraw node belonging to the same physical dial
+    /// as the evdev node events are being read from. If it's missing, falls
+    /// back to the first device with a matching VID/PID.
+    fn open(hid_api: &SharedHidApi, hidraw: Option<PathBuf>) -> Result<DialHidWrapper> {
+        let mut hid_api = hid_api.lock().unwrap();
+        if hid_api.is_none() {
+            *hid_api = Some(HidApi::new().map_err(Error::HidError)?);
+        }
+        let api = hid_api.as_mut().unwrap();
+
+        let path = match hidraw {
+            Some(path) => path,
+            None => {
+                eprintln!("could not find the dial's hidraw node, guessing...");
+                api.refresh_devices().map_err(Error::HidError)?;
+                api.device_list()
+                    .find(|dev| (dev.vendor_id(), dev.product_id()) == SURFACE_DIAL_ID)
+                    .map(|dev| PathBuf::from(OsStr::from_bytes(dev.path().to_bytes())))
+                    .ok_or(Error::MissingDial)?
+            }
+        };
+
+        // paths can't contain interior nul bytes
+        let c_path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let hid_device = api.open_path(&c_path).map_err(Error::HidError)?;
+
+        let mut wrapper = DialHidWrapper {
+            hid_device,
+            path,
+            info: HapticInfo::fallback(),
+            reports: Vec::new(),
+            haptic_report: Vec::new(),
+        };
+
+        // the fallback covers everything the daemon uses by default, so this
+        // isn't fatal
+        if let Err(e) = wrapper.read_info() {
+            eprintln!("could not read haptic capabilities: {}", e);
+        }
+
+        Ok(wrapper)
+    }
 
-        let steps_lo = steps & 0xff;
-        let steps_hi = (steps >> 8) & 0xff;
+    /// Read the dial's haptic capabilities (along with some diagnostic info),
+    /// combining the waveform list from the report descriptor with the
+    /// duration list from feature report 2.
+    fn read_info(&mut self) -> Result<()> {
+        let desc = hid_descriptor::read_from_hidraw(&self.path)
+            .map_err(|e| Error::ReportDescriptor(self.path.clone(), e))?;
+        let fields = hid_descriptor::parse(&desc);
+        self.reports = ReportSummary::from_fields(&fields);
+
+        let mut buf = [0; 64];
+        buf[0] = 2; // Report ID
+        let len = self
+            .hid_device
+            .get_feature_report(&mut buf)
+            .map_err(Error::HidError)?;
+        let report = &buf[1..len.max(1)];
+        self.haptic_report = report.to_vec();
+
+        // ordinals 1 and 2 are reserved for "None" and "Stop"
+        let mut info = HapticInfo::fallback();
+        info.waveforms.truncate(2);
+
+        let mut durations = Vec::new();
+        for field in fields
+            .iter()
+            .filter(|f| f.kind == ReportKind::Feature && f.report_id == 2)
+        {
+            if field.usages.contains(&AUTO_TRIGGER_ASSOCIATED_CONTROL) {
+                if let Some(control) = field.extract(report, 0) {
+                    info.auto_trigger_control = control as Usage;
+                }
+            } else if field.in_collection(DURATION_LIST) {
+                for idx in 0..field.report_count as usize {
+                    if let (Some(ordinal), Some(ms)) =
+                        (field.usage(idx), field.extract(report, idx))
+                    {
+                        durations.push((ordinal as u8, ms));
+                    }
+                }
+            } else if field.in_collection(WAVEFORM_LIST) {
+                // each waveform is a constant field, whose physical value is
+                // the waveform's usage ID
+                match field.usage(0) {
+                    Some(ordinal) if ordinal >> 16 == ORDINAL_PAGE as u32 => {
+                        info.waveforms.push(WaveformInfo {
+                            waveform: Waveform::from_usage_id(field.physical_min as u16),
+                            ordinal: ordinal as u8,
+                            duration: None,
+                        })
+                    }
+                    _ => continue,
+                }
+            }
+        }
+
+        for (ordinal, ms) in durations {
+            let waveform = info.waveforms.iter_mut().find(|w| w.ordinal == ordinal);
+            if let (Some(waveform), true) = (waveform, ms > 0) {
+                waveform.duration = Some(Duration::from_millis(ms as u64));
+            }
+        }
+
+        self.info = info;
+        Ok(())
+    }
+
+    fn ordinal(&self, waveform: Waveform) -> Option<u8> {
+        let ordinal = self.info.ordinal(waveform);
+        if ordinal.is_none() {
+            eprintln!("the dial does not support the {:?} waveform", waveform);
+        }
+        ordinal
+    }
+
+    fn set_auto(&self, auto: AutoFeedback) -> Result<()> {
+        if auto.steps > 3600 {
+            return Err(Error::InvalidSteps(auto.steps));
+        }
+
+        let waveform = match self.ordinal(auto.waveform) {
+            Some(ordinal) => ordinal,
+            None => return Ok(()),
+        };
+        let retrigger = auto.retrigger_period.min(MAX_RETRIGGER_PERIOD).as_millis() as u16;
 
         let mut buf = [0; 8];
-        buf[0] = 1;
-        buf[1] = steps_lo as u8; // steps
-        buf[2] = steps_hi as u8; // steps
-        buf[3] = 0x00; // Repeat Count
-        buf[4] = if haptics { 0x03 } else { 0x02 }; // auto trigger
-        buf[5] = 0x00; // Waveform Cutoff Time
-        buf[6] = 0x00; // retrigger period
-        buf[7] = 0x00; // retrigger period
+        buf[0] = 0x01; // Report ID
+        buf[1..3].copy_from_slice(&auto.steps.to_le_bytes()); // Resolution Multiplier
+        buf[3] = auto.repeat; // Repeat Count
+        buf[4] = waveform; // Auto Trigger
+        buf[5] = auto.cutoff_time.min(MAX_CUTOFF_TIME); // Waveform Cutoff Time
+        buf[6..8].copy_from_slice(&retrigger.to_le_bytes()); // Retrigger Period
         self.hid_device
-            .send_feature_report(&buf[..8])
+            .send_feature_report(&buf)
             .map_err(Error::HidError)?;
 
         Ok(())
     }
 
-    fn buzz(&self, repeat: u8) -> Result<()> {
+    fn trigger(&self, trigger: ManualTrigger) -> Result<()> {
+        let waveform = match self.ordinal(trigger.waveform) {
+            Some(ordinal) => ordinal,
+            None => return Ok(()),
+        };
+        let retrigger = (trigger.retrigger_period)
+            .min(MAX_RETRIGGER_PERIOD)
+            .as_millis() as u16;
+
         let mut buf = [0; 5];
         buf[0] = 0x01; // Report ID
-        buf[1] = repeat; // RepeatCount
-        buf[2] = 0x03; // ManualTrigger
-        buf[3] = 0x00; // RetriggerPeriod (lo)
-        buf[4] = 0x00; // RetriggerPeriod (hi)
+        buf[1] = trigger.repeat; // Repeat Count
+        buf[2] = waveform; // Manual Trigger
+        buf[3..5].copy_from_slice(&retrigger.to_le_bytes()); // Retrigger Period
         self.hid_device.write(&buf).map_err(Error::HidError)?;
         Ok(())
     }
@@ -0,0 +1,378 @@
+//! A minimal HID report descriptor parser.
+//!
+//! Only supports the subset of the spec used by the Surface Dial (i.e: long
+//! items, delimiters, units, and string / designator indices are ignored),
+//! which is more than enough to figure out where each field lives in a given
+//! report.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Full 32-bit HID usage (usage page in the high 16 bits).
+pub type Usage = u32;
+
+pub const fn usage(page: u16, id: u16) -> Usage {
+    (page as u32) << 16 | id as u32
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ReportKind {
+    Input,
+    Output,
+    Feature,
+}
+
+/// A single `Input`, `Output`, or `Feature` main item.
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub kind: ReportKind,
+    pub report_id: u8,
+    /// Usage of each element in the field.
+    pub usages: Vec<Usage>,
+    /// Usages of the enclosing collections (outermost first).
+    pub collections: Vec<Usage>,
+    pub logical_min: i32,
+    pub physical_min: i32,
+    pub report_size: u32,
+    pub report_count: u32,
+    /// Offset (in bits) from the start of the report, _excluding_ the report ID
+    /// byte.
+    pub bit_offset: u32,
+}
+
+impl Field {
+    pub fn in_collection(&self, collection: Usage) -> bool {
+        self.collections.contains(&collection)
+    }
+
+    /// The usage of the `idx`th element in the field (the last usage is
+    /// repeated if there are more elements than usages).
+    pub fn usage(&self, idx: usize) -> Option<Usage> {
+        self.usages.get(idx).or_else(|| self.usages.last()).copied()
+    }
+
+    /// Extract the `idx`th element of this field from a report (which must
+    /// _not_ include the report ID byte).
+    ///
+    /// Values are sign-extended if the field's logical minimum is negative.
+    pub fn extract(&self, report: &[u8], idx: usize) -> Option<i32> {
+        if idx as u32 >= self.report_count || !(1..=32).contains(&self.report_size) {
+            return None;
+        }
+
+        let start = self.bit_offset + idx as u32 * self.report_size;
+        let mut val: u32 = 0;
+        for bit in 0..self.report_size {
+            let pos = start + bit;
+            let byte = *report.get((pos / 8) as usize)?;
+            val |= (((byte >> (pos % 8)) & 1) as u32) << bit;
+        }
+
+        if self.logical_min < 0 && self.report_size < 32 && val >> (self.report_size - 1) != 0 {
+            val |= !0 << self.report_size;
+        }
+
+        Some(val as i32)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Globals {
+    usage_page: u16,
+    logical_min: i32,
+    physical_min: i32,
+    report_size: u32,
+    report_id: u8,
+    report_count: u32,
+}
+
+fn item_data(data: &[u8], signed: bool) -> i32 {
+    match (data.len(), signed) {
+        (1, true) => data[0] as i8 as i32,
+        (2, true) => i16::from_le_bytes([data[0], data[1]]) as i32,
+        (1, false) => data[0] as i32,
+        (2, false) => u16::from_le_bytes([data[0], data[1]]) as i32,
+        (4, _) => i32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+        _ => 0,
+    }
+}
+
+/// Parse a raw report descriptor into a flat list of fields.
+///
+/// Malformed / unsupported items are skipped.
+pub fn parse(desc: &[u8]) -> Vec<Field> {
+    let mut fields = Vec::new();
+
+    let mut globals = Globals::default();
+    let mut global_stack = Vec::new();
+    let mut usages: Vec<Usage> = Vec::new();
+    let mut usage_min = None;
+    let mut collections = Vec::new();
+    let mut bit_offsets: HashMap<(ReportKind, u8), u32> = HashMap::new();
+
+    let mut i = 0;
+    while i < desc.len() {
+        let prefix = desc[i];
+
+        // long item
+        if prefix == 0xfe {
+            let len = desc.get(i + 1).copied().unwrap_or(0) as usize;
+            i += 3 + len;
+            continue;
+        }
+
+        let size = match prefix & 0b11 {
+            3 => 4,
+            n => n as usize,
+        };
+        let data = match desc.get(i + 1..i + 1 + size) {
+            Some(data) => data,
+            None => break,
+        };
+        i += 1 + size;
+
+        let kind = (prefix >> 2) & 0b11;
+        let tag = prefix >> 4;
+
+        // usages without an explicit page use the current usage page
+        let full_usage = |globals: &Globals| {
+            let val = item_data(data, false) as u32;
+            if size == 4 {
+                val
+            } else {
+                usage(globals.usage_page, val as u16)
+            }
+        };
+
+        match (kind, tag) {
+            // main items
+            (0, 0x8) | (0, 0x9) | (0, 0xb) => {
+                let report_kind = match tag {
+                    0x8 => ReportKind::Input,
+                    0x9 => ReportKind::Output,
+                    _ => ReportKind::Feature,
+                };
+
+                let offset = bit_offsets
+                    .entry((report_kind, globals.report_id))
+                    .or_insert(0);
+
+                fields.push(Field {
+                    kind: report_kind,
+                    report_id: globals.report_id,
+                    usages: std::mem::take(&mut usages),
+                    collections: collections.clone(),
+                    logical_min: globals.logical_min,
+                    physical_min: globals.physical_min,
+                    report_size: globals.report_size,
+                    report_count: globals.report_count,
+                    bit_offset: *offset,
+                });
+
+                *offset += globals.report_size * globals.report_count;
+                usage_min = None;
+            }
+            // collection
+            (0, 0xa) => {
+                collections.push(usages.first().copied().unwrap_or(0));
+                usages.clear();
+                usage_min = None;
+            }
+            // end collection
+            (0, 0xc) => {
+                collections.pop();
+            }
+
+            // global items
+            (1, 0x0) => globals.usage_page = item_data(data, false) as u16,
+            (1, 0x1) => globals.logical_min = item_data(data, true),
+            (1, 0x3) => globals.physical_min = item_data(data, true),
+            (1, 0x7) => globals.report_size = item_data(data, false) as u32,
+            (1, 0x8) => globals.report_id = item_data(data, false) as u8,
+            (1, 0x9) => globals.report_count = item_data(data, false) as u32,
+            (1, 0xa) => global_stack.push(globals.clone()),
+            (1, 0xb) => {
+                if let Some(g) = global_stack.pop() {
+                    globals = g
+                }
+            }
+
+            // local items
+            (2, 0x0) => usages.push(full_usage(&globals)),
+            (2, 0x1) => usage_min = Some(full_usage(&globals)),
+            (2, 0x2) => {
+                if let Some(min) = usage_min.take() {
+                    let max = full_usage(&globals);
+                    usages.extend(min..=max);
+                }
+            }
+
+            // logical / physical max, units, designators, strings, etc...
+            _ => {}
+        }
+    }
+
+    fields
+}
+
+/// Read the report descriptor of the given hidraw device (e.g: `/dev/hidraw3`)
+/// from sysfs.
+pub fn read_from_hidraw(devnode: &Path) -> io::Result<Vec<u8>> {
+    let name = devnode
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid hidraw path"))?;
+
+    fs::read(
+        Path::new("/sys/class/hidraw")
+            .join(name)
+            .join("device/report_descriptor"),
+    )
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    /// The Surface Dial's report descriptor (see `notes/descriptor.c`).
+    pub const SURFACE_DIAL: &[u8] = &[
+        0x05, 0x01, 0x09, 0x0e, 0xa1, 0x01, 0x85, 0x01, 0x05, 0x0d, 0x09, 0x21, 0xa1, 0x02, 0x15,
+        0x00, 0x25, 0x01, 0x75, 0x01, 0x95, 0x01, 0xa1, 0x00, 0x05, 0x09, 0x09, 0x01, 0x81, 0x02,
+        0x05, 0x0d, 0x09, 0x33, 0x81, 0x02, 0x95, 0x06, 0x81, 0x03, 0xa1, 0x02, 0x05, 0x01, 0x09,
+        0x37, 0x16, 0x01, 0x80, 0x26, 0xff, 0x7f, 0x75, 0x10, 0x95, 0x01, 0x81, 0x06, 0x35, 0x00,
+        0x46, 0x10, 0x0e, 0x15, 0x00, 0x26, 0x10, 0x0e, 0x09, 0x48, 0xb1, 0x02, 0x45, 0x00, 0xc0,
+        0x55, 0x0e, 0x65, 0x11, 0x46, 0x00, 0x00, 0x26, 0x00, 0x00, 0x09, 0x30, 0x81, 0x42, 0x09,
+        0x31, 0x46, 0x00, 0x00, 0x26, 0x00, 0x00, 0x81, 0x42, 0x05, 0x0d, 0x09, 0x48, 0x15, 0x3a,
+        0x25, 0x3a, 0x75, 0x08, 0x55, 0x0f, 0x35, 0x3a, 0x45, 0x3a, 0x81, 0x03, 0x55, 0x00, 0x65,
+        0x00, 0x35, 0x00, 0x45, 0x00, 0x05, 0x0e, 0x09, 0x01, 0xa1, 0x02, 0x15, 0x00, 0x26, 0xff,
+        0x00, 0x09, 0x24, 0xb1, 0x42, 0x09, 0x24, 0x91, 0x42, 0x15, 0x01, 0x25, 0x07, 0x09, 0x20,
+        0xb1, 0x42, 0x09, 0x21, 0x91, 0x42, 0x25, 0x0a, 0x09, 0x28, 0xb1, 0x42, 0x75, 0x10, 0x26,
+        0xd0, 0x07, 0x09, 0x25, 0xb1, 0x42, 0x09, 0x25, 0x91, 0x42, 0x85, 0x02, 0x75, 0x20, 0x17,
+        0x37, 0x00, 0x01, 0x00, 0x27, 0x37, 0x00, 0x01, 0x00, 0x09, 0x22, 0xb1, 0x02, 0x09, 0x11,
+        0xa1, 0x02, 0x05, 0x0a, 0x95, 0x03, 0x09, 0x03, 0x09, 0x04, 0x09, 0x05, 0x75, 0x08, 0x15,
+        0x00, 0x25, 0xff, 0xb1, 0x02, 0xc0, 0x05, 0x0e, 0x09, 0x10, 0xa1, 0x02, 0x05, 0x0a, 0x95,
+        0x01, 0x15, 0x03, 0x25, 0x03, 0x36, 0x03, 0x10, 0x46, 0x03, 0x10, 0x09, 0x03, 0xb1, 0x02,
+        0x15, 0x04, 0x25, 0x04, 0x36, 0x04, 0x10, 0x46, 0x04, 0x10, 0x09, 0x04, 0xb1, 0x02, 0x15,
+        0x05, 0x25, 0x05, 0x36, 0x04, 0x10, 0x46, 0x04, 0x10, 0x09, 0x05, 0xb1, 0x02, 0x35, 0x00,
+        0x45, 0x00, 0xc0, 0xc0, 0xc0, 0xc0, 0xc0, 0x06, 0x07, 0xff, 0x09, 0x70, 0xa1, 0x01, 0x85,
+        0x30, 0x15, 0x00, 0x25, 0xff, 0x95, 0x01, 0x75, 0x08, 0x09, 0x00, 0x91, 0x02, 0xc0, 0x09,
+        0x71, 0xa1, 0x01, 0x15, 0x00, 0x25, 0xff, 0x75, 0x08, 0x95, 0x48, 0x85, 0x2a, 0x09, 0xc6,
+        0x82, 0x02, 0x01, 0x09, 0xc7, 0x92, 0x02, 0x01, 0x95, 0x34, 0x09, 0xc8, 0xb2, 0x03, 0x01,
+        0x85, 0x2b, 0x09, 0xc9, 0x82, 0x02, 0x01, 0x09, 0xca, 0x92, 0x02, 0x01, 0x09, 0xcb, 0xb2,
+        0x02, 0x01, 0x17, 0x00, 0x00, 0x00, 0x80, 0x27, 0xff, 0xff, 0xff, 0x7f, 0x75, 0x20, 0x95,
+        0x04, 0x85, 0x2c, 0x19, 0xcc, 0x29, 0xcf, 0x81, 0x02, 0x95, 0x04, 0x85, 0x2d, 0x19, 0xd8,
+        0x29, 0xdb, 0x81, 0x02, 0x95, 0x04, 0x19, 0xdc, 0x29, 0xdf, 0x91, 0x02, 0x19, 0xe0, 0x29,
+        0xe3, 0xb1, 0x02, 0x85, 0x2e, 0x19, 0xe4, 0x29, 0xe7, 0x81, 0x02, 0x19, 0xe8, 0x29, 0xeb,
+        0x91, 0x02, 0x95, 0x0b, 0x19, 0xec, 0x29, 0xef, 0xb1, 0x02, 0x95, 0x04, 0x85, 0x2f, 0x19,
+        0xf0, 0x29, 0xf3, 0x81, 0x02, 0x19, 0xf4, 0x29, 0xf7, 0x91, 0x02, 0x19, 0xf8, 0x29, 0xfb,
+        0xb1, 0x02, 0xc0, 0x05, 0x01, 0x09, 0x80, 0xa1, 0x01, 0x85, 0x32, 0x09, 0x82, 0x09, 0x83,
+        0x15, 0x00, 0x25, 0x01, 0x95, 0x02, 0x75, 0x01, 0x81, 0x02, 0x95, 0x06, 0x81, 0x03, 0xc0,
+        0x09, 0x72, 0xa1, 0x01, 0x85, 0x31, 0x95, 0x0a, 0x75, 0x08, 0x15, 0x00, 0x25, 0xff, 0x09,
+        0xc6, 0x81, 0x02, 0x09, 0xc7, 0x91, 0x02, 0xc0,
+    ];
+
+    fn find(fields: &[Field], kind: ReportKind, usage: Usage) -> &Field {
+        fields
+            .iter()
+            .find(|f| f.kind == kind && f.usages.contains(&usage))
+            .unwrap()
+    }
+
+    #[test]
+    fn parse_puck_input_report() {
+        let fields = parse(SURFACE_DIAL);
+        let layout = |usage| {
+            let f = find(&fields, ReportKind::Input, usage);
+            (f.report_id, f.bit_offset, f.report_size, f.report_count)
+        };
+
+        assert_eq!(layout(usage(0x09, 0x01)), (1, 0, 1, 1)); // button
+        assert_eq!(layout(usage(0x0d, 0x33)), (1, 1, 1, 1)); // touch
+        assert_eq!(layout(usage(0x01, 0x37)), (1, 8, 16, 1)); // dial
+        assert_eq!(layout(usage(0x01, 0x30)), (1, 24, 16, 1)); // x
+        assert_eq!(layout(usage(0x01, 0x31)), (1, 40, 16, 1)); // y
+        assert_eq!(layout(usage(0x0d, 0x48)), (1, 56, 8, 1)); // width
+
+        let dial = find(&fields, ReportKind::Input, usage(0x01, 0x37));
+        assert_eq!(dial.logical_min, -32767);
+        assert!(dial.in_collection(usage(0x0d, 0x21))); // puck
+    }
+
+    #[test]
+    fn parse_system_control_input_report() {
+        let fields = parse(SURFACE_DIAL);
+        let keys = find(&fields, ReportKind::Input, usage(0x01, 0x82));
+
+        assert_eq!(keys.report_id, 0x32);
+        assert_eq!(keys.usage(0), Some(usage(0x01, 0x82))); // sleep
+        assert_eq!(keys.usage(1), Some(usage(0x01, 0x83))); // wake up
+        assert_eq!(
+            (keys.bit_offset, keys.report_size, keys.report_count),
+            (0, 1, 2)
+        );
+    }
+
+    #[test]
+    fn parse_usage_ranges() {
+        let fields = parse(SURFACE_DIAL);
+        let field = find(&fields, ReportKind::Input, usage(0xff07, 0xcc));
+
+        assert_eq!(field.report_id, 0x2c);
+        assert_eq!(
+            field.usages,
+            (0xcc..=0xcf)
+                .map(|id| usage(0xff07, id))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn usage_repeats_last() {
+        let fields = parse(SURFACE_DIAL);
+        let field = find(&fields, ReportKind::Input, usage(0xff07, 0xc6));
+
+        assert_eq!(field.report_count, 72);
+        assert_eq!(field.usage(71), Some(usage(0xff07, 0xc6)));
+    }
+
+    #[test]
+    fn extract_bits() {
+        let fields = parse(SURFACE_DIAL);
+        let button = find(&fields, ReportKind::Input, usage(0x09, 0x01));
+        let touch = find(&fields, ReportKind::Input, usage(0x0d, 0x33));
+
+        assert_eq!(button.extract(&[0b01], 0), Some(1));
+        assert_eq!(touch.extract(&[0b01], 0), Some(0));
+        assert_eq!(touch.extract(&[0b10], 0), Some(1));
+    }
+
+    #[test]
+    fn extract_signed() {
+        let fields = parse(SURFACE_DIAL);
+        let dial = find(&fields, ReportKind::Input, usage(0x01, 0x37));
+
+        assert_eq!(dial.extract(&[0x00, 0x02, 0x00], 0), Some(2));
+        assert_eq!(dial.extract(&[0x00, 0xfe, 0xff], 0), Some(-2));
+        assert_eq!(dial.extract(&[0x00, 0x01, 0x80], 0), Some(-32767));
+    }
+
+    #[test]
+    fn extract_unsigned() {
+        let fields = parse(SURFACE_DIAL);
+        let x = find(&fields, ReportKind::Input, usage(0x01, 0x30));
+
+        assert_eq!(x.extract(&[0, 0, 0, 0xfe, 0xff], 0), Some(0xfffe));
+    }
+
+    #[test]
+    fn extract_out_of_bounds() {
+        let fields = parse(SURFACE_DIAL);
+        let dial = find(&fields, ReportKind::Input, usage(0x01, 0x37));
+
+        // truncated report
+        assert_eq!(dial.extract(&[0x00, 0x02], 0), None);
+        // only one element
+        assert_eq!(dial.extract(&[0x00, 0x02, 0x00, 0x00, 0x00], 1), None);
+    }
+}
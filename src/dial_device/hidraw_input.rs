@@ -0,0 +1,358 @@
+//! An alternative input backend, which bypasses evdev entirely and parses
+//! input reports straight from the dial's hidraw node (using its report
+//! descriptor to locate each field).
+//!
+//! This exposes every field the dial reports (regardless of how well the
+//! kernel translates them into evdev events), and doesn't rely on the kernel's
+//! HID drivers behaving.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use super::events::{DialSenders, RawInputEvent};
+use super::hid_descriptor::{self, usage, Field, ReportKind, Usage};
+use super::{now, DialEvent, DialEventKind, DialHapticsWorkerMsg, PuckPosition, SystemKey};
+
+const BUTTON: Usage = usage(0x09, 0x01);
+const TOUCH: Usage = usage(0x0d, 0x33);
+const WIDTH: Usage = usage(0x0d, 0x48);
+const X: Usage = usage(0x01, 0x30);
+const Y: Usage = usage(0x01, 0x31);
+const DIAL: Usage = usage(0x01, 0x37);
+const SYSTEM_SLEEP: Usage = usage(0x01, 0x82);
+const SYSTEM_WAKE_UP: Usage = usage(0x01, 0x83);
+
+/// The location of a single value within an input report.
+#[derive(Debug, Clone)]
+struct Location {
+    field: Field,
+    idx: usize,
+}
+
+impl Location {
+    fn find(fields: &[Field], usage: Usage) -> Option<Location> {
+        fields
+            .iter()
+            .filter(|f| f.kind == ReportKind::Input)
+            .find_map(|field| {
+                let idx =
+                    (0..field.report_count as usize).find(|&i| field.usage(i) == Some(usage))?;
+                Some(Location {
+                    field: field.clone(),
+                    idx,
+                })
+            })
+    }
+
+    /// `report` _includes_ the report ID byte. Returns `None` if the value
+    /// isn't part of the given report.
+    fn extract(&self, report: &[u8]) -> Option<i32> {
+        let (&id, data) = report.split_first()?;
+        if id != self.field.report_id {
+            return None;
+        }
+        self.field.extract(data, self.idx)
+    }
+}
+
+/// Translates raw input reports into [`DialEventKind`]s.
+///
+/// Reports contain the dial's entire state, so events are generated by
+/// comparing each report against the previous one.
+pub(super) struct ReportDecoder {
+    button: Option<Location>,
+    touch: Option<Location>,
+    dial: Option<Location>,
+    x: Option<Location>,
+    y: Option<Location>,
+    width: Option<Location>,
+    sleep: Option<Location>,
+    wake_up: Option<Location>,
+
+    // previous state
+    pressed: bool,
+    on_screen: bool,
+    position: Option<PuckPosition>,
+    sleep_pressed: bool,
+    wake_up_pressed: bool,
+}
+
+impl ReportDecoder {
+    pub fn new(fields: &[Field]) -> ReportDecoder {
+        ReportDecoder {
+            button: Location::find(fields, BUTTON),
+            touch: Location::find(fields, TOUCH),
+            dial: Location::find(fields, DIAL),
+            x: Location::find(fields, X),
+            y: Location::find(fields, Y),
+            width: Location::find(fields, WIDTH),
+            sleep: Location::find(fields, SYSTEM_SLEEP),
+            wake_up: Location::find(fields, SYSTEM_WAKE_UP),
+
+            pressed: false,
+            on_screen: false,
+            position: None,
+            sleep_pressed: false,
+            wake_up_pressed: false,
+        }
+    }
+
+    /// `report` should be a single input report, _including_ the report ID
+    /// byte. Reports the decoder doesn't know about are ignored.
+    pub fn decode(&mut self, report: &[u8]) -> Vec<DialEventKind> {
+        let get = |loc: &Option<Location>| loc.as_ref().and_then(|loc| loc.extract(report));
+
+        let mut events = Vec::new();
+
+        if let Some(pressed) = get(&self.button).map(|v| v != 0) {
+            if pressed != self.pressed {
+                self.pressed = pressed;
+                events.push(if pressed {
+                    DialEventKind::ButtonPress
+                } else {
+                    DialEventKind::ButtonRelease
+                });
+            }
+        }
+
+        if let Some(on_screen) = get(&self.touch).map(|v| v != 0) {
+            if on_screen != self.on_screen {
+                self.on_screen = on_screen;
+                if !on_screen {
+                    self.position = None;
+                }
+                events.push(DialEventKind::Contact(on_screen));
+            }
+        }
+
+        match get(&self.dial) {
+            Some(delta) if delta != 0 => events.push(DialEventKind::Dial(delta)),
+            _ => {}
+        }
+
+        if self.on_screen {
+            if let (Some(x), Some(y)) = (get(&self.x), get(&self.y)) {
+                let position = PuckPosition {
+                    x,
+                    y,
+                    width: get(&self.width).unwrap_or(0),
+                };
+                if self.position != Some(position) {
+                    self.position = Some(position);
+                    events.push(DialEventKind::Position(position));
+                }
+            }
+        }
+
+        if rising_edge(&self.sleep, report, &mut self.sleep_pressed) {
+            events.push(DialEventKind::SystemKey(SystemKey::Sleep));
+        }
+        if rising_edge(&self.wake_up, report, &mut self.wake_up_pressed) {
+            events.push(DialEventKind::SystemKey(SystemKey::WakeUp));
+        }
+
+        events
+    }
+}
+
+/// Only key presses are reported (matching the evdev backend).
+fn rising_edge(loc: &Option<Location>, report: &[u8], was_pressed: &mut bool) -> bool {
+    match loc.as_ref().and_then(|loc| loc.extract(report)) {
+        Some(v) => {
+            let pressed = v != 0;
+            let rising = pressed && !*was_pressed;
+            *was_pressed = pressed;
+            rising
+        }
+        None => false,
+    }
+}
+
+/// Forward events from the dial's hidraw node until it disconnects.
+pub(super) fn event_loop(dial: &DialSenders, hidraw: &Path) -> io::Result<()> {
+    let desc = hid_descriptor::read_from_hidraw(hidraw)?;
+    let mut decoder = ReportDecoder::new(&hid_descriptor::parse(&desc));
+    let mut file = fs::File::open(hidraw)?;
+
+    let _ = dial
+        .haptics_msg
+        .send(DialHapticsWorkerMsg::DialConnected(Some(PathBuf::from(
+            hidraw,
+        ))));
+    let _ = dial.events.send(RawInputEvent::Connect);
+
+    // each read returns a single report (the largest of which is < 128 bytes)
+    let mut buf = [0; 256];
+    let res = loop {
+        match file.read(&mut buf) {
+            Ok(0) => break Ok(()),
+            Ok(len) => {
+                let time = now();
+                for kind in decoder.decode(&buf[..len]) {
+                    let _ = dial
+                        .events
+                        .send(RawInputEvent::Decoded(DialEvent { time, kind }));
+                }
+            }
+            // these errors correspond to the device disconnecting, which is fine
+            Err(e) if matches!(e.raw_os_error(), Some(5) | Some(19)) => break Ok(()),
+            Err(e) => break Err(e),
+        }
+    };
+
+    let _ = dial
+        .haptics_msg
+        .send(DialHapticsWorkerMsg::DialDisconnected);
+    let _ = dial.events.send(RawInputEvent::Disconnect);
+
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::hid_descriptor::tests::SURFACE_DIAL;
+    use DialEventKind::{ButtonPress, ButtonRelease, Contact, Dial, Position};
+
+    fn decoder() -> ReportDecoder {
+        ReportDecoder::new(&hid_descriptor::parse(SURFACE_DIAL))
+    }
+
+    /// Input report 1: button and touch bits, followed by the dial delta, X,
+    /// Y, and width.
+    fn puck_report(button: bool, touch: bool, dial: i16, x: u16, y: u16) -> Vec<u8> {
+        let mut report = vec![0x01, button as u8 | (touch as u8) << 1];
+        report.extend_from_slice(&dial.to_le_bytes());
+        report.extend_from_slice(&x.to_le_bytes());
+        report.extend_from_slice(&y.to_le_bytes());
+        report.push(58);
+        report
+    }
+
+    fn at(x: i32, y: i32) -> DialEventKind {
+        Position(PuckPosition { x, y, width: 58 })
+    }
+
+    #[test]
+    fn button_edges() {
+        let mut decoder = decoder();
+
+        assert_eq!(
+            decoder.decode(&puck_report(true, false, 0, 0, 0)),
+            [ButtonPress]
+        );
+        assert_eq!(decoder.decode(&puck_report(true, false, 0, 0, 0)), []);
+        assert_eq!(
+            decoder.decode(&puck_report(false, false, 0, 0, 0)),
+            [ButtonRelease]
+        );
+        assert_eq!(decoder.decode(&puck_report(false, false, 0, 0, 0)), []);
+    }
+
+    #[test]
+    fn signed_dial_delta() {
+        let mut decoder = decoder();
+
+        assert_eq!(
+            decoder.decode(&puck_report(false, false, 1, 0, 0)),
+            [Dial(1)]
+        );
+        assert_eq!(
+            decoder.decode(&puck_report(false, false, -1, 0, 0)),
+            [Dial(-1)]
+        );
+        assert_eq!(
+            decoder.decode(&puck_report(false, false, -3, 0, 0)),
+            [Dial(-3)]
+        );
+        assert_eq!(decoder.decode(&puck_report(false, false, 0, 0, 0)), []);
+        // pressed while turning
+        assert_eq!(
+            decoder.decode(&puck_report(true, false, -2, 0, 0)),
+            [ButtonPress, Dial(-2)]
+        );
+    }
+
+    #[test]
+    fn contact_and_position() {
+        let mut decoder = decoder();
+
+        // X / Y aren't meaningful while off-screen
+        assert_eq!(decoder.decode(&puck_report(false, false, 0, 100, 200)), []);
+
+        assert_eq!(
+            decoder.decode(&puck_report(false, true, 0, 100, 200)),
+            [Contact(true), at(100, 200)]
+        );
+        assert_eq!(decoder.decode(&puck_report(false, true, 0, 100, 200)), []);
+        assert_eq!(
+            decoder.decode(&puck_report(false, true, 0, 150, 200)),
+            [at(150, 200)]
+        );
+        // turning on-screen doesn't re-report the position
+        assert_eq!(
+            decoder.decode(&puck_report(false, true, 5, 150, 200)),
+            [Dial(5)]
+        );
+    }
+
+    #[test]
+    fn contact_clear_resets_position() {
+        let mut decoder = decoder();
+
+        decoder.decode(&puck_report(false, true, 0, 100, 200));
+        assert_eq!(
+            decoder.decode(&puck_report(false, false, 0, 100, 200)),
+            [Contact(false)]
+        );
+        // placed back down in the same spot
+        assert_eq!(
+            decoder.decode(&puck_report(false, true, 0, 100, 200)),
+            [Contact(true), at(100, 200)]
+        );
+    }
+
+    #[test]
+    fn system_key_rising_edges() {
+        let mut decoder = decoder();
+
+        assert_eq!(
+            decoder.decode(&[0x32, 0b01]),
+            [DialEventKind::SystemKey(SystemKey::Sleep)]
+        );
+        // still held
+        assert_eq!(decoder.decode(&[0x32, 0b01]), []);
+        assert_eq!(decoder.decode(&[0x32, 0b00]), []);
+        assert_eq!(
+            decoder.decode(&[0x32, 0b10]),
+            [DialEventKind::SystemKey(SystemKey::WakeUp)]
+        );
+        assert_eq!(
+            decoder.decode(&[0x32, 0b11]),
+            [DialEventKind::SystemKey(SystemKey::Sleep)]
+        );
+        assert_eq!(decoder.decode(&[0x32, 0b00]), []);
+        assert_eq!(
+            decoder.decode(&[0x32, 0b01]),
+            [DialEventKind::SystemKey(SystemKey::Sleep)]
+        );
+    }
+
+    #[test]
+    fn reports_dont_affect_each_other() {
+        let mut decoder = decoder();
+
+        decoder.decode(&puck_report(true, true, 0, 100, 200));
+        // a system control report doesn't release the button or lift the puck
+        assert_eq!(decoder.decode(&[0x32, 0b00]), []);
+        // and neither do unrelated reports
+        assert_eq!(decoder.decode(&[0x2c, 0xff]), []);
+        assert_eq!(decoder.decode(&[]), []);
+        assert_eq!(
+            decoder.decode(&puck_report(false, true, 0, 100, 200)),
+            [ButtonRelease]
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use super::haptics::HapticInfo;
+use super::hid_descriptor::{Field, ReportKind, Usage};
+
+/// Everything the daemon knows about a dial's underlying devices.
+///
+/// Fields are filled in as the dial connects, and cleared once it
+/// disconnects.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceInfo {
+    /// Name of the matching device profile.
+    pub profile: Option<&'static str>,
+    pub evdev: Option<PathBuf>,
+    pub hidraw: Option<PathBuf>,
+    /// Device version number (i.e: the firmware version), as reported to the
+    /// kernel.
+    pub version: Option<u16>,
+    /// Layout of each report, in descriptor order.
+    pub reports: Vec<ReportSummary>,
+    /// Raw contents of feature report 2, which describes the dial's haptic
+    /// capabilities (excluding the report ID byte).
+    pub haptic_report: Vec<u8>,
+    pub haptics: Option<HapticInfo>,
+}
+
+/// Summary of a single report, as described by the report descriptor.
+#[derive(Debug, Clone)]
+pub struct ReportSummary {
+    pub kind: ReportKind,
+    pub id: u8,
+    /// Total size (excluding the report ID byte).
+    pub bits: u32,
+    /// Usages of every (non-padding) field in the report.
+    pub usages: Vec<Usage>,
+}
+
+impl ReportSummary {
+    pub(super) fn from_fields(fields: &[Field]) -> Vec<ReportSummary> {
+        let mut reports: Vec<ReportSummary> = Vec::new();
+        for field in fields {
+            let report = match reports
+                .iter_mut()
+                .find(|r| r.kind == field.kind && r.id == field.report_id)
+            {
+                Some(report) => report,
+                None => {
+                    reports.push(ReportSummary {
+                        kind: field.kind,
+                        id: field.report_id,
+                        bits: 0,
+                        usages: Vec::new(),
+                    });
+                    reports.last_mut().unwrap()
+                }
+            };
+
+            let end = field.bit_offset + field.report_size * field.report_count;
+            report.bits = report.bits.max(end);
+            for usage in field.usages.iter() {
+                if !report.usages.contains(usage) {
+                    report.usages.push(*usage);
+                }
+            }
+        }
+        reports
+    }
+}
+
+impl fmt::Display for ReportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            ReportKind::Input => "input",
+            ReportKind::Output => "output",
+            ReportKind::Feature => "feature",
+        };
+        write!(f, "{} report {} ({} bits):", kind, self.id, self.bits)?;
+        for usage in self.usages.iter() {
+            write!(f, " {:02x}:{:04x}", usage >> 16, usage & 0xffff)?;
+        }
+        Ok(())
+    }
+}
+
+/// A handle to a dial's [`DeviceInfo`], which can be queried from any thread.
+#[derive(Debug, Clone, Default)]
+pub struct DeviceInfoHandle(pub(super) Arc<Mutex<DeviceInfo>>);
+
+impl DeviceInfoHandle {
+    pub fn get(&self) -> DeviceInfo {
+        self.0.lock().unwrap().clone()
+    }
+}
@@ -1,167 +1,407 @@
 use std::sync::mpsc;
 use std::time::Duration;
 
+use nix::time::{clock_gettime, ClockId};
+
 use crate::error::{Error, Result};
 
+mod battery;
 mod events;
 mod haptics;
+mod hid_descriptor;
+mod hidraw_input;
+mod info;
+mod patterns;
+mod profiles;
+
+use events::DialSenders;
+use haptics::{DialHapticsWorker, DialHapticsWorkerMsg, SharedHidApi};
+use profiles::{Binding, DeviceProfile};
+
+pub use haptics::{DialHaptics, ManualTrigger, Waveform};
+pub use info::DeviceInfoHandle;
+pub use patterns::{HapticPattern, PATTERNS};
+
+/// Where dial input events are read from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InputBackend {
+    /// The kernel's evdev translation of the dial's HID reports.
+    Evdev,
+    /// The dial's raw HID input reports (via hidraw), decoded using the
+    /// dial's report descriptor. Useful on kernels which translate the dial's
+    /// reports poorly (or not at all).
+    Hidraw,
+}
 
-use haptics::{DialHapticsWorker, DialHapticsWorkerMsg};
+impl InputBackend {
+    pub const NAMES: &'static [&'static str] = &["evdev", "hidraw"];
 
-pub use haptics::DialHaptics;
+    pub fn from_name(name: &str) -> Option<InputBackend> {
+        match name {
+            "evdev" => Some(InputBackend::Evdev),
+            "hidraw" => Some(InputBackend::Hidraw),
+            _ => None,
+        }
+    }
+}
+
+/// Watches for Surface Dials, handing out a separate [`DialDevice`] for each
+/// physical dial.
+pub struct DialManager {
+    new_dials: mpsc::Receiver<std::io::Result<DialDevice>>,
+}
+
+impl DialManager {
+    /// If `grab` is set, the dial's evdev nodes are grabbed exclusively (see
+    /// `EVIOCGRAB`), hiding their events from other programs.
+    pub fn new(long_press_timeout: Duration, backend: InputBackend, grab: bool) -> DialManager {
+        let (new_dials_tx, new_dials) = mpsc::channel();
+
+        std::thread::spawn({
+            let mut worker =
+                events::EventsWorker::new(long_press_timeout, backend, grab, new_dials_tx.clone());
+            move || {
+                let res = worker.run();
+                eprintln!("the events worker died!");
+                if let Err(e) = res {
+                    let _ = new_dials_tx.send(Err(e));
+                }
+            }
+        });
+
+        DialManager { new_dials }
+    }
+
+    /// Blocks until a dial connects for the first time. Subsequent reconnects
+    /// are reported via the existing `DialDevice`.
+    pub fn next_dial(&self) -> Result<DialDevice> {
+        match self.new_dials.recv() {
+            Ok(res) => res.map_err(Error::Udev),
+            Err(_) => Err(Error::Udev(std::io::Error::other("the events worker died"))),
+        }
+    }
+}
 
 /// Encapsulates all the the nitty-gritty (and pretty gnarly) device handling
 /// code, exposing a simple interface to wait for incoming [`DialEvent`]s.
 pub struct DialDevice {
+    /// See [`DialDevice::id`].
+    id: String,
+
     // configurable constants
     long_press_timeout: Duration,
 
     // handles
     haptics: DialHaptics,
+    info: DeviceInfoHandle,
     events: mpsc::Receiver<events::RawInputEvent>,
+    events_tx: mpsc::Sender<events::RawInputEvent>,
 
     // mutable state
-    possible_long_press: bool,
+    /// When the long press fires (see [`now`]).
+    long_press_deadline: Option<Duration>,
+    puck: PuckState,
+}
+
+/// Wakes up a thread blocked in [`DialDevice::next_event`].
+#[derive(Clone)]
+pub struct DialWaker {
+    events: mpsc::Sender<events::RawInputEvent>,
+}
+
+impl DialWaker {
+    pub fn wake(&self) {
+        let _ = self.events.send(events::RawInputEvent::Wake);
+    }
 }
 
 #[derive(Debug)]
 pub struct DialEvent {
+    /// When the event occurred (see [`now`]).
     pub time: Duration,
     pub kind: DialEventKind,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum DialEventKind {
     Connect,
     Disconnect,
+    /// The dial's battery level (as a percentage). Sent shortly after the dial
+    /// connects, and whenever the level changes.
+    Battery(u8),
 
     Ignored,
     ButtonPress,
     ButtonRelease,
     Dial(i32),
+    /// Reported by the dial's "System Control" interface. Only key presses are
+    /// reported (releases are ignored).
+    SystemKey(SystemKey),
+    /// The dial was placed on (`true`) or lifted off of (`false`) a supported
+    /// touchscreen (e.g: a Surface Studio).
+    Contact(bool),
+    /// The dial's position on a supported touchscreen. Only sent while the dial
+    /// is in contact with the screen.
+    Position(PuckPosition),
 
     /// NOTE: this is a synthetic event, and is _not_ directly provided by the
     /// dial itself.
     ButtonLongPress,
 }
 
+/// The current time, on the same clock used to timestamp [`DialEvent`]s (i.e:
+/// `CLOCK_MONOTONIC`, which evdev nodes are switched to when opened).
+pub fn now() -> Duration {
+    clock_gettime(ClockId::CLOCK_MONOTONIC)
+        .expect("CLOCK_MONOTONIC is always available")
+        .into()
+}
+
+/// Where the dial is sitting on a touchscreen.
+///
+/// Units are screen-specific (see the Digitizer / Puck collection in
+/// `notes/HID_Report_Descriptor.txt`).
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PuckPosition {
+    pub x: i32,
+    pub y: i32,
+    /// Width of the contact area.
+    pub width: i32,
+}
+
+/// evdev reports each axis separately, so the position is accumulated until
+/// the end of each frame (i.e: `SYN_REPORT`).
+#[derive(Debug, Default)]
+struct PuckState {
+    x: i32,
+    y: i32,
+    width: i32,
+    changed: bool,
+}
+
+/// Keys reported by the dial's "System Control" interface.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SystemKey {
+    Sleep,
+    WakeUp,
+}
+
 impl DialDevice {
-    pub fn new(long_press_timeout: Duration) -> Result<DialDevice> {
+    /// Also returns the channels used to route events to the new device.
+    fn new(
+        id: String,
+        long_press_timeout: Duration,
+        hid_api: SharedHidApi,
+    ) -> Result<(DialDevice, DialSenders)> {
         let (events_tx, events_rx) = mpsc::channel();
         let (haptics_msg_tx, haptics_msg_rx) = mpsc::channel();
-
-        // TODO: interleave control events with regular events
-        // (once we figure out what control events actually do...)
+        let info = DeviceInfoHandle::default();
 
         std::thread::spawn({
-            let haptics_msg_tx = haptics_msg_tx.clone();
-            let mut worker = events::EventsWorker::new(
-                events::DialInputKind::MultiAxis,
-                events_tx,
-                haptics_msg_tx,
-            );
+            let mut worker = DialHapticsWorker::new(haptics_msg_rx, info.clone(), hid_api)?;
             move || {
-                worker.run().unwrap();
-                eprintln!("the events worker died!");
+                worker.run();
             }
         });
 
-        std::thread::spawn({
-            let mut worker = DialHapticsWorker::new(haptics_msg_rx)?;
-            move || {
-                if let Err(err) = worker.run() {
-                    eprintln!("Unexpected haptics worker error! {}", err);
-                }
-                eprintln!("the haptics worker died!");
-                // there's no coming back from this.
-                std::process::exit(0);
-            }
-        });
+        let senders = DialSenders {
+            events: events_tx.clone(),
+            haptics_msg: haptics_msg_tx.clone(),
+            info: info.clone(),
+        };
 
-        Ok(DialDevice {
+        let device = DialDevice {
+            id,
             long_press_timeout,
             events: events_rx,
-            haptics: DialHaptics::new(haptics_msg_tx)?,
+            events_tx,
+            haptics: DialHaptics::new(haptics_msg_tx, info.clone())?,
+            info,
+
+            long_press_deadline: None,
+            puck: PuckState::default(),
+        };
+
+        Ok((device, senders))
+    }
+
+    /// A stable identifier for the physical dial (typically its Bluetooth
+    /// address, e.g: `aa:bb:cc:dd:ee:ff`).
+    pub fn id(&self) -> &str {
+        &self.id
+    }
 
-            possible_long_press: false,
-        })
+    pub fn set_long_press_timeout(&mut self, timeout: Duration) {
+        self.long_press_timeout = timeout;
     }
 
-    /// Blocks until a new dial event comes occurs.
+    /// Returns a handle which can be used to wake up `next_event` from another
+    /// thread.
+    pub fn waker(&self) -> DialWaker {
+        DialWaker {
+            events: self.events_tx.clone(),
+        }
+    }
+
+    /// Blocks until a new dial event occurs, or until `timeout` elapses (in
+    /// which case `None` is returned). Returns early with `None` if woken up by
+    /// a [`DialWaker`].
     // TODO?: rewrite code using async/await?
     // TODO?: "cheat" by exposing an async interface to the current next_event impl
-    pub fn next_event(&mut self) -> Result<DialEvent> {
-        let evt = if self.possible_long_press {
-            self.events.recv_timeout(self.long_press_timeout)
-        } else {
-            self.events
+    pub fn next_event(&mut self, timeout: Option<Duration>) -> Result<Option<DialEvent>> {
+        let long_press_wait = self.long_press_deadline.map(|deadline| {
+            deadline
+                .checked_sub(now())
+                .unwrap_or_else(|| Duration::from_secs(0))
+        });
+
+        let wait = match (long_press_wait, timeout) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+
+        let evt = match wait {
+            Some(wait) => self.events.recv_timeout(wait),
+            None => self
+                .events
                 .recv()
-                .map_err(|_| mpsc::RecvTimeoutError::Disconnected)
+                .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
         };
 
         let event = match evt {
-            Ok(events::RawInputEvent::Event(_event_status, event)) => {
+            Ok(events::RawInputEvent::Event(profile, _event_status, event)) => {
                 // assert!(matches!(axis_status, ReadStatus::Success));
-                let event =
-                    DialEvent::from_raw_evt(event.clone()).ok_or(Error::UnexpectedEvt(event))?;
-
-                match event.kind {
-                    DialEventKind::ButtonPress => self.possible_long_press = true,
-                    DialEventKind::ButtonRelease => self.possible_long_press = false,
-                    _ => {}
-                }
-
+                let event = DialEvent::from_raw_evt(event.clone(), profile, &mut self.puck)
+                    .ok_or(Error::UnexpectedEvt(event))?;
+                self.update_long_press(&event);
                 event
             }
-            Ok(events::RawInputEvent::Connect) => {
-                DialEvent {
-                    time: Duration::from_secs(0), // this could be improved...
-                    kind: DialEventKind::Connect,
-                }
-            }
-            Ok(events::RawInputEvent::Disconnect) => {
-                DialEvent {
-                    time: Duration::from_secs(0), // this could be improved...
-                    kind: DialEventKind::Disconnect,
-                }
+            Ok(events::RawInputEvent::Decoded(event)) => {
+                self.update_long_press(&event);
+                event
             }
-            Err(mpsc::RecvTimeoutError::Timeout) => {
-                self.possible_long_press = false;
-                DialEvent {
-                    time: Duration::from_secs(0), // this could be improved...
-                    kind: DialEventKind::ButtonLongPress,
+            // synthetic events are stamped on arrival
+            Ok(events::RawInputEvent::Connect) => DialEvent {
+                time: now(),
+                kind: DialEventKind::Connect,
+            },
+            Ok(events::RawInputEvent::Disconnect) => DialEvent {
+                time: now(),
+                kind: DialEventKind::Disconnect,
+            },
+            Ok(events::RawInputEvent::Battery(level)) => DialEvent {
+                time: now(),
+                kind: DialEventKind::Battery(level),
+            },
+            Ok(events::RawInputEvent::Wake) => return Ok(None),
+            Err(mpsc::RecvTimeoutError::Timeout) => match self.long_press_deadline {
+                // i.e: when the button was pressed (or last touched), plus the
+                // timeout
+                Some(deadline) if deadline <= now() => {
+                    self.long_press_deadline = None;
+                    DialEvent {
+                        time: deadline,
+                        kind: DialEventKind::ButtonLongPress,
+                    }
                 }
-            }
+                _ => return Ok(None),
+            },
             Err(_e) => panic!("Could not recv event"),
         };
 
-        Ok(event)
+        Ok(Some(event))
+    }
+
+    /// Forget about the button being held (e.g: if its release might never be
+    /// reported).
+    pub fn cancel_long_press(&mut self) {
+        self.long_press_deadline = None;
+    }
+
+    fn update_long_press(&mut self, event: &DialEvent) {
+        // any activity while the button is held restarts the long press timeout
+        match event.kind {
+            DialEventKind::ButtonRelease => self.long_press_deadline = None,
+            DialEventKind::ButtonPress => {
+                self.long_press_deadline = Some(event.time + self.long_press_timeout)
+            }
+            // ...except for the dial shifting around on a touchscreen (or key
+            // repeats while the button is held)
+            DialEventKind::Ignored | DialEventKind::Contact(_) | DialEventKind::Position(_) => {}
+            _ => {
+                if self.long_press_deadline.is_some() {
+                    self.long_press_deadline = Some(event.time + self.long_press_timeout)
+                }
+            }
+        }
     }
 
     pub fn haptics(&self) -> &DialHaptics {
         &self.haptics
     }
+
+    pub fn info(&self) -> DeviceInfoHandle {
+        self.info.clone()
+    }
 }
 
 impl DialEvent {
-    fn from_raw_evt(evt: evdev_rs::InputEvent) -> Option<DialEvent> {
+    fn from_raw_evt(
+        evt: evdev_rs::InputEvent,
+        profile: &DeviceProfile,
+        puck: &mut PuckState,
+    ) -> Option<DialEvent> {
         use evdev_rs::enums::*;
 
         let evt_kind = match evt.event_type {
-            EventType::EV_SYN | EventType::EV_MSC => DialEventKind::Ignored,
-            EventType::EV_KEY => match evt.event_code {
-                EventCode::EV_KEY(EV_KEY::BTN_0) => match evt.value {
+            EventType::EV_SYN => match evt.event_code {
+                EventCode::EV_SYN(EV_SYN::SYN_REPORT) if puck.changed => {
+                    puck.changed = false;
+                    DialEventKind::Position(PuckPosition {
+                        x: puck.x,
+                        y: puck.y,
+                        width: puck.width,
+                    })
+                }
+                _ => DialEventKind::Ignored,
+            },
+            EventType::EV_MSC => DialEventKind::Ignored,
+            _ => match profile.binding(&evt.event_code) {
+                None => DialEventKind::Ignored,
+                Some(Binding::Button) => match evt.value {
                     0 => DialEventKind::ButtonRelease,
                     1 => DialEventKind::ButtonPress,
-                    _ => return None,
+                    // key repeats (e.g: from keyboard keys bound as buttons),
+                    // which don't affect the long press timeout
+                    _ => DialEventKind::Ignored,
                 },
-                _ => return None,
-            },
-            EventType::EV_REL => match evt.event_code {
-                EventCode::EV_REL(EV_REL::REL_DIAL) => DialEventKind::Dial(evt.value),
-                _ => return None,
+                Some(Binding::Dial) => DialEventKind::Dial(evt.value),
+                Some(Binding::DialStep(step)) => match evt.value {
+                    0 => DialEventKind::Ignored,
+                    // includes key repeats
+                    _ => DialEventKind::Dial(step),
+                },
+                Some(Binding::SystemKey(key)) => match evt.value {
+                    1 => DialEventKind::SystemKey(key),
+                    _ => DialEventKind::Ignored,
+                },
+                Some(Binding::Contact) => {
+                    // don't report a stale position after the dial is lifted
+                    puck.changed &= evt.value != 0;
+                    DialEventKind::Contact(evt.value != 0)
+                }
+                Some(axis @ Binding::PuckX)
+                | Some(axis @ Binding::PuckY)
+                | Some(axis @ Binding::PuckWidth) => {
+                    match axis {
+                        Binding::PuckX => puck.x = evt.value,
+                        Binding::PuckY => puck.y = evt.value,
+                        _ => puck.width = evt.value,
+                    }
+                    puck.changed = true;
+                    DialEventKind::Ignored
+                }
             },
-            _ => return None,
         };
 
         let evt = DialEvent {
@@ -172,3 +412,172 @@ impl DialEvent {
         Some(evt)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use evdev_rs::enums::{EventCode, EV_ABS, EV_KEY, EV_REL, EV_SYN};
+    use evdev_rs::{InputEvent, TimeVal};
+
+    fn profile(name: &str) -> &'static DeviceProfile {
+        profiles::PROFILES
+            .iter()
+            .find(|profile| profile.name == name)
+            .unwrap()
+    }
+
+    fn decode(profile: &DeviceProfile, code: &EventCode, value: i32) -> DialEventKind {
+        let evt = InputEvent::new(&TimeVal::new(1, 500), code, value);
+        DialEvent::from_raw_evt(evt, profile, &mut PuckState::default())
+            .unwrap()
+            .kind
+    }
+
+    #[test]
+    fn event_time() {
+        let evt = InputEvent::new(&TimeVal::new(1, 500), &EventCode::EV_KEY(EV_KEY::BTN_0), 1);
+        let evt = DialEvent::from_raw_evt(evt, profile("surface-dial"), &mut PuckState::default());
+        assert_eq!(evt.unwrap().time, Duration::from_micros(1_000_500));
+    }
+
+    #[test]
+    fn buttons() {
+        let buttons = [
+            ("surface-dial", EV_KEY::BTN_0),
+            ("powermate", EV_KEY::BTN_0),
+            ("rotary-encoder", EV_KEY::BTN_0),
+            ("rotary-encoder", EV_KEY::BTN_LEFT),
+            ("rotary-encoder", EV_KEY::KEY_ENTER),
+            ("rotary-encoder", EV_KEY::KEY_MUTE),
+        ];
+
+        for (name, key) in buttons.iter() {
+            let profile = profile(name);
+            let code = EventCode::EV_KEY(*key);
+            assert_eq!(decode(profile, &code, 1), DialEventKind::ButtonPress);
+            // i.e: a key repeat while held
+            assert_eq!(decode(profile, &code, 2), DialEventKind::Ignored);
+            assert_eq!(decode(profile, &code, 0), DialEventKind::ButtonRelease);
+        }
+    }
+
+    #[test]
+    fn dial() {
+        for name in ["surface-dial", "powermate", "rotary-encoder"].iter() {
+            let code = EventCode::EV_REL(EV_REL::REL_DIAL);
+            assert_eq!(decode(profile(name), &code, 3), DialEventKind::Dial(3));
+            assert_eq!(decode(profile(name), &code, -1), DialEventKind::Dial(-1));
+        }
+
+        let wheel = EventCode::EV_REL(EV_REL::REL_WHEEL);
+        assert_eq!(
+            decode(profile("rotary-encoder"), &wheel, -2),
+            DialEventKind::Dial(-2)
+        );
+    }
+
+    #[test]
+    fn dial_steps() {
+        let profile = profile("rotary-encoder");
+        let up = EventCode::EV_KEY(EV_KEY::KEY_VOLUMEUP);
+        let down = EventCode::EV_KEY(EV_KEY::KEY_VOLUMEDOWN);
+
+        assert_eq!(decode(profile, &up, 1), DialEventKind::Dial(1));
+        // key repeats keep turning
+        assert_eq!(decode(profile, &up, 2), DialEventKind::Dial(1));
+        assert_eq!(decode(profile, &up, 0), DialEventKind::Ignored);
+
+        assert_eq!(decode(profile, &down, 1), DialEventKind::Dial(-1));
+        assert_eq!(decode(profile, &down, 2), DialEventKind::Dial(-1));
+        assert_eq!(decode(profile, &down, 0), DialEventKind::Ignored);
+    }
+
+    #[test]
+    fn system_keys() {
+        let profile = profile("surface-dial-control");
+        let sleep = EventCode::EV_KEY(EV_KEY::KEY_SLEEP);
+        let wake_up = EventCode::EV_KEY(EV_KEY::KEY_WAKEUP);
+
+        assert_eq!(
+            decode(profile, &sleep, 1),
+            DialEventKind::SystemKey(SystemKey::Sleep)
+        );
+        assert_eq!(decode(profile, &sleep, 2), DialEventKind::Ignored);
+        assert_eq!(decode(profile, &sleep, 0), DialEventKind::Ignored);
+        assert_eq!(
+            decode(profile, &wake_up, 1),
+            DialEventKind::SystemKey(SystemKey::WakeUp)
+        );
+    }
+
+    #[test]
+    fn unbound_codes() {
+        let unbound = [
+            ("surface-dial", EventCode::EV_KEY(EV_KEY::KEY_ENTER)),
+            ("surface-dial-control", EventCode::EV_KEY(EV_KEY::BTN_0)),
+            ("powermate", EventCode::EV_REL(EV_REL::REL_WHEEL)),
+            ("powermate", EventCode::EV_KEY(EV_KEY::BTN_TOUCH)),
+            ("rotary-encoder", EventCode::EV_KEY(EV_KEY::KEY_A)),
+            ("rotary-encoder", EventCode::EV_ABS(EV_ABS::ABS_X)),
+        ];
+
+        for (name, code) in unbound.iter() {
+            for value in [0, 1, 2].iter() {
+                assert_eq!(decode(profile(name), code, *value), DialEventKind::Ignored);
+            }
+        }
+    }
+
+    #[test]
+    fn puck_position() {
+        let profile = profile("surface-dial");
+        let mut puck = PuckState::default();
+        let mut decode = |code: &EventCode, value| {
+            let evt = InputEvent::new(&TimeVal::new(1, 0), code, value);
+            DialEvent::from_raw_evt(evt, profile, &mut puck)
+                .unwrap()
+                .kind
+        };
+        let syn = EventCode::EV_SYN(EV_SYN::SYN_REPORT);
+
+        assert_eq!(
+            decode(&EventCode::EV_KEY(EV_KEY::BTN_TOUCH), 1),
+            DialEventKind::Contact(true)
+        );
+        assert_eq!(
+            decode(&EventCode::EV_ABS(EV_ABS::ABS_X), 100),
+            DialEventKind::Ignored
+        );
+        assert_eq!(
+            decode(&EventCode::EV_ABS(EV_ABS::ABS_Y), 200),
+            DialEventKind::Ignored
+        );
+        assert_eq!(
+            decode(&EventCode::EV_ABS(EV_ABS::ABS_TOOL_WIDTH), 58),
+            DialEventKind::Ignored
+        );
+        assert_eq!(
+            decode(&syn, 0),
+            DialEventKind::Position(PuckPosition {
+                x: 100,
+                y: 200,
+                width: 58
+            })
+        );
+        // only reported when it changes
+        assert_eq!(decode(&syn, 0), DialEventKind::Ignored);
+
+        // a position update in the same report as the dial being lifted isn't
+        // reported
+        assert_eq!(
+            decode(&EventCode::EV_ABS(EV_ABS::ABS_X), 150),
+            DialEventKind::Ignored
+        );
+        assert_eq!(
+            decode(&EventCode::EV_KEY(EV_KEY::BTN_TOUCH), 0),
+            DialEventKind::Contact(false)
+        );
+        assert_eq!(decode(&syn, 0), DialEventKind::Ignored);
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::haptics::{ManualTrigger, Waveform};
+
+/// A single manual trigger within a [`HapticPattern`].
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PatternStep {
+    /// When to fire the trigger, relative to the start of the pattern.
+    pub at: Duration,
+    pub trigger: ManualTrigger,
+}
+
+/// A named, timed sequence of manual haptic triggers.
+#[derive(Debug)]
+pub struct HapticPattern {
+    pub name: &'static str,
+    /// Must be sorted by `at`.
+    pub steps: &'static [PatternStep],
+}
+
+const fn step(at_ms: u64, waveform: Waveform) -> PatternStep {
+    PatternStep {
+        at: Duration::from_millis(at_ms),
+        trigger: ManualTrigger::new(waveform),
+    }
+}
+
+/// All the built-in patterns.
+pub const PATTERNS: &[HapticPattern] = &[
+    HapticPattern {
+        name: "tick",
+        steps: &[step(0, Waveform::Click)],
+    },
+    HapticPattern {
+        name: "double-tap",
+        steps: &[step(0, Waveform::Click), step(100, Waveform::Click)],
+    },
+    HapticPattern {
+        name: "heartbeat",
+        steps: &[
+            step(0, Waveform::Click),
+            step(150, Waveform::Click),
+            step(700, Waveform::Click),
+            step(850, Waveform::Click),
+        ],
+    },
+    HapticPattern {
+        name: "error",
+        steps: &[PatternStep {
+            at: Duration::from_millis(0),
+            trigger: ManualTrigger {
+                waveform: Waveform::Click,
+                repeat: 2,
+                retrigger_period: Duration::from_millis(60),
+            },
+        }],
+    },
+    // a short rattle. Continuous waveforms would keep buzzing until stopped,
+    // since manual triggers can't be given a cutoff time.
+    HapticPattern {
+        name: "limit-reached",
+        steps: &[PatternStep {
+            at: Duration::from_millis(0),
+            trigger: ManualTrigger {
+                waveform: Waveform::Click,
+                repeat: 4,
+                retrigger_period: Duration::from_millis(25),
+            },
+        }],
+    },
+];
+
+impl HapticPattern {
+    pub fn by_name(name: &str) -> Option<&'static HapticPattern> {
+        PATTERNS.iter().find(|p| p.name == name)
+    }
+}
+
+/// Plays back patterns in the haptics worker.
+///
+/// Only one pattern plays at a time: starting a new pattern cancels any
+/// remaining steps of the previous one.
+pub(super) struct Sequencer {
+    queue: VecDeque<(Instant, ManualTrigger)>,
+}
+
+impl Sequencer {
+    pub fn new() -> Sequencer {
+        Sequencer {
+            queue: VecDeque::new(),
+        }
+    }
+
+    pub fn play(&mut self, pattern: &HapticPattern, now: Instant) {
+        self.queue.clear();
+        self.queue.extend(
+            pattern
+                .steps
+                .iter()
+                .map(|step| (now + step.at, step.trigger)),
+        );
+    }
+
+    pub fn stop(&mut self) {
+        self.queue.clear();
+    }
+
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.queue.front().map(|(deadline, _)| *deadline)
+    }
+
+    /// Returns the next step that is due (if any).
+    pub fn pop_due(&mut self, now: Instant) -> Option<ManualTrigger> {
+        match self.queue.front() {
+            Some((deadline, _)) if *deadline <= now => self.queue.pop_front().map(|(_, t)| t),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn click() -> ManualTrigger {
+        ManualTrigger::new(Waveform::Click)
+    }
+
+    #[test]
+    fn by_name() {
+        for name in ["tick", "double-tap", "heartbeat", "error", "limit-reached"].iter() {
+            assert_eq!(HapticPattern::by_name(name).unwrap().name, *name);
+        }
+        assert!(HapticPattern::by_name("nope").is_none());
+        assert!(HapticPattern::by_name("").is_none());
+        assert!(HapticPattern::by_name("Tick").is_none());
+    }
+
+    #[test]
+    fn patterns_are_well_formed() {
+        for (i, pattern) in PATTERNS.iter().enumerate() {
+            assert!(
+                PATTERNS[i + 1..].iter().all(|p| p.name != pattern.name),
+                "duplicate pattern {}",
+                pattern.name
+            );
+            assert!(!pattern.steps.is_empty(), "{} is empty", pattern.name);
+            assert!(
+                pattern.steps.windows(2).all(|w| w[0].at <= w[1].at),
+                "{} isn't sorted",
+                pattern.name
+            );
+
+            for step in pattern.steps {
+                // i.e: nothing which plays until it's explicitly stopped
+                assert!(
+                    !matches!(
+                        step.trigger.waveform,
+                        Waveform::BuzzContinuous | Waveform::RumbleContinuous
+                    ),
+                    "{} plays a continuous waveform",
+                    pattern.name
+                );
+                assert!(step.trigger.retrigger_period <= Duration::from_secs(2));
+            }
+        }
+    }
+
+    #[test]
+    fn plays_steps_in_order() {
+        let t0 = Instant::now();
+        let mut seq = Sequencer::new();
+        assert_eq!(seq.next_deadline(), None);
+
+        seq.play(HapticPattern::by_name("heartbeat").unwrap(), t0);
+
+        let mut played = Vec::new();
+        while let Some(deadline) = seq.next_deadline() {
+            assert_eq!(seq.pop_due(deadline - Duration::from_millis(1)), None);
+            assert_eq!(seq.pop_due(deadline), Some(click()));
+            played.push(deadline - t0);
+        }
+
+        assert_eq!(
+            played,
+            [0, 150, 700, 850]
+                .iter()
+                .map(|ms| Duration::from_millis(*ms))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(seq.pop_due(t0 + Duration::from_secs(10)), None);
+    }
+
+    #[test]
+    fn pops_one_step_at_a_time() {
+        let t0 = Instant::now();
+        let mut seq = Sequencer::new();
+        seq.play(HapticPattern::by_name("double-tap").unwrap(), t0);
+
+        // e.g: the worker woke up late, and both steps are due
+        let late = t0 + Duration::from_millis(500);
+        assert_eq!(seq.pop_due(late), Some(click()));
+        assert_eq!(seq.next_deadline(), Some(t0 + Duration::from_millis(100)));
+        assert_eq!(seq.pop_due(late), Some(click()));
+        assert_eq!(seq.pop_due(late), None);
+        assert_eq!(seq.next_deadline(), None);
+    }
+
+    #[test]
+    fn play_cancels_previous_pattern() {
+        let t0 = Instant::now();
+        let mut seq = Sequencer::new();
+        seq.play(HapticPattern::by_name("heartbeat").unwrap(), t0);
+        assert_eq!(seq.pop_due(t0), Some(click()));
+
+        let t1 = t0 + Duration::from_millis(200);
+        let error = HapticPattern::by_name("error").unwrap();
+        seq.play(error, t1);
+
+        assert_eq!(seq.next_deadline(), Some(t1));
+        assert_eq!(seq.pop_due(t1), Some(error.steps[0].trigger));
+        // the rest of the heartbeat never plays
+        assert_eq!(seq.next_deadline(), None);
+        assert_eq!(seq.pop_due(t0 + Duration::from_secs(10)), None);
+    }
+
+    #[test]
+    fn stop() {
+        let t0 = Instant::now();
+        let mut seq = Sequencer::new();
+        seq.play(HapticPattern::by_name("double-tap").unwrap(), t0);
+
+        seq.stop();
+        assert_eq!(seq.next_deadline(), None);
+        assert_eq!(seq.pop_due(t0 + Duration::from_secs(1)), None);
+    }
+}
@@ -0,0 +1,273 @@
+//! Device profiles, which describe how to recognize a supported input device,
+//! and how to translate its evdev events into [`DialEventKind`]s.
+//!
+//! Besides the Surface Dial, any device which reports rotation (e.g: the
+//! Griffin PowerMate, or a macropad with a rotary encoder) can drive the same
+//! modes, albeit without haptic feedback.
+
+use evdev_rs::enums::{EventCode, EV_ABS, EV_KEY, EV_REL};
+
+use super::SystemKey;
+
+/// The Surface Dial's (Bluetooth) vendor / product ID.
+pub const SURFACE_DIAL_ID: (u16, u16) = (0x045e, 0x091b);
+
+/// A condition an input device must satisfy for a profile to apply.
+#[derive(Debug)]
+pub enum DeviceMatch {
+    /// The input device's name (as reported by `evtest`).
+    Name(&'static str),
+    /// The input device's vendor / product ID.
+    Id { vendor: u16, product: u16 },
+    /// A udev property, which can be attached to arbitrary devices using a
+    /// udev rule (e.g: `ENV{SURFACE_DIAL_PROFILE}="rotary-encoder"`).
+    Property(&'static str, &'static str),
+}
+
+/// What an evdev event code is translated into.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Binding {
+    /// Press (1) / release (0). Key repeats (2) are ignored.
+    Button,
+    /// Relative rotation.
+    Dial,
+    /// A key which is treated as a fixed amount of rotation when pressed (e.g:
+    /// encoders which report volume up / down keys).
+    DialStep(i32),
+    /// Only key presses are reported.
+    SystemKey(SystemKey),
+    /// Touchscreen contact.
+    Contact,
+    PuckX,
+    PuckY,
+    PuckWidth,
+}
+
+#[derive(Debug)]
+pub struct DeviceProfile {
+    pub name: &'static str,
+    /// Every condition must match.
+    pub matches: &'static [DeviceMatch],
+    /// Devices may expose several evdev nodes, whose events are interleaved
+    /// into a single stream. Connect / disconnect events, haptics, and battery
+    /// reporting are tied to the primary node.
+    pub primary: bool,
+    /// Event codes without a binding are ignored.
+    pub bindings: &'static [(EventCode, Binding)],
+    /// Whether the device supports the Surface Dial's haptics reports.
+    pub haptics: bool,
+    /// Whether the device's raw HID reports can be decoded by the hidraw
+    /// input backend.
+    pub raw_reports: bool,
+}
+
+impl DeviceProfile {
+    pub fn binding(&self, code: &EventCode) -> Option<Binding> {
+        self.bindings
+            .iter()
+            .find(|(c, _)| c == code)
+            .map(|(_, binding)| *binding)
+    }
+}
+
+/// All the built-in profiles (the first matching profile is used).
+pub const PROFILES: &[DeviceProfile] = &[
+    DeviceProfile {
+        name: "surface-dial",
+        matches: &[DeviceMatch::Name("Surface Dial System Multi Axis")],
+        primary: true,
+        bindings: &[
+            (EventCode::EV_KEY(EV_KEY::BTN_0), Binding::Button),
+            (EventCode::EV_REL(EV_REL::REL_DIAL), Binding::Dial),
+            (EventCode::EV_KEY(EV_KEY::BTN_TOUCH), Binding::Contact),
+            (EventCode::EV_ABS(EV_ABS::ABS_X), Binding::PuckX),
+            (EventCode::EV_ABS(EV_ABS::ABS_Y), Binding::PuckY),
+            (
+                EventCode::EV_ABS(EV_ABS::ABS_TOOL_WIDTH),
+                Binding::PuckWidth,
+            ),
+        ],
+        haptics: true,
+        raw_reports: true,
+    },
+    DeviceProfile {
+        name: "surface-dial-control",
+        matches: &[DeviceMatch::Name("Surface Dial System Control")],
+        primary: false,
+        bindings: &[
+            (
+                EventCode::EV_KEY(EV_KEY::KEY_SLEEP),
+                Binding::SystemKey(SystemKey::Sleep),
+            ),
+            (
+                EventCode::EV_KEY(EV_KEY::KEY_WAKEUP),
+                Binding::SystemKey(SystemKey::WakeUp),
+            ),
+        ],
+        haptics: false,
+        raw_reports: true,
+    },
+    DeviceProfile {
+        name: "powermate",
+        matches: &[DeviceMatch::Id {
+            vendor: 0x077d,
+            product: 0x0410,
+        }],
+        primary: true,
+        bindings: &[
+            (EventCode::EV_KEY(EV_KEY::BTN_0), Binding::Button),
+            (EventCode::EV_REL(EV_REL::REL_DIAL), Binding::Dial),
+        ],
+        haptics: false,
+        raw_reports: false,
+    },
+    DeviceProfile {
+        name: "rotary-encoder",
+        matches: &[DeviceMatch::Property(
+            "SURFACE_DIAL_PROFILE",
+            "rotary-encoder",
+        )],
+        primary: true,
+        bindings: &[
+            (EventCode::EV_REL(EV_REL::REL_DIAL), Binding::Dial),
+            (EventCode::EV_REL(EV_REL::REL_WHEEL), Binding::Dial),
+            (
+                EventCode::EV_KEY(EV_KEY::KEY_VOLUMEUP),
+                Binding::DialStep(1),
+            ),
+            (
+                EventCode::EV_KEY(EV_KEY::KEY_VOLUMEDOWN),
+                Binding::DialStep(-1),
+            ),
+            (EventCode::EV_KEY(EV_KEY::BTN_0), Binding::Button),
+            (EventCode::EV_KEY(EV_KEY::BTN_LEFT), Binding::Button),
+            (EventCode::EV_KEY(EV_KEY::KEY_ENTER), Binding::Button),
+            (EventCode::EV_KEY(EV_KEY::KEY_MUTE), Binding::Button),
+        ],
+        haptics: false,
+        raw_reports: false,
+    },
+];
+
+/// Returns the profile for one of a device's evdev nodes (if any).
+pub fn find(device: &udev::Device) -> Option<&'static DeviceProfile> {
+    // the evdev node is a child of the actual input device, which holds the
+    // name / IDs
+    let parent = device.parent()?;
+
+    PROFILES.iter().find(|profile| {
+        profile
+            .matches
+            .iter()
+            .all(|m| matches_device(m, device, &parent))
+    })
+}
+
+fn matches_device(m: &DeviceMatch, device: &udev::Device, parent: &udev::Device) -> bool {
+    let property = |key: &str| {
+        device
+            .property_value(key)
+            .or_else(|| parent.property_value(key))
+            .map(|val| val.to_string_lossy().into_owned())
+    };
+
+    match m {
+        DeviceMatch::Name(name) => {
+            property("NAME").is_some_and(|val| val.trim_matches('"') == *name)
+        }
+        // i.e: `PRODUCT=<bus>/<vendor>/<product>/<version>` (in hex)
+        DeviceMatch::Id { vendor, product } => {
+            let ids = match property("PRODUCT") {
+                Some(ids) => ids,
+                None => return false,
+            };
+            let ids = ids
+                .split('/')
+                .map(|id| u16::from_str_radix(id, 16).ok())
+                .collect::<Vec<_>>();
+            ids.get(1) == Some(&Some(*vendor)) && ids.get(2) == Some(&Some(*product))
+        }
+        DeviceMatch::Property(key, value) => property(key).as_deref() == Some(*value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(name: &str) -> &'static DeviceProfile {
+        PROFILES
+            .iter()
+            .find(|profile| profile.name == name)
+            .unwrap()
+    }
+
+    #[test]
+    fn bindings() {
+        let dial = profile("surface-dial");
+        assert_eq!(
+            dial.binding(&EventCode::EV_KEY(EV_KEY::BTN_0)),
+            Some(Binding::Button)
+        );
+        assert_eq!(
+            dial.binding(&EventCode::EV_REL(EV_REL::REL_DIAL)),
+            Some(Binding::Dial)
+        );
+        assert_eq!(
+            dial.binding(&EventCode::EV_ABS(EV_ABS::ABS_TOOL_WIDTH)),
+            Some(Binding::PuckWidth)
+        );
+
+        let encoder = profile("rotary-encoder");
+        assert_eq!(
+            encoder.binding(&EventCode::EV_KEY(EV_KEY::KEY_VOLUMEDOWN)),
+            Some(Binding::DialStep(-1))
+        );
+        assert_eq!(
+            encoder.binding(&EventCode::EV_KEY(EV_KEY::KEY_MUTE)),
+            Some(Binding::Button)
+        );
+    }
+
+    #[test]
+    fn unbound() {
+        assert_eq!(
+            profile("surface-dial").binding(&EventCode::EV_REL(EV_REL::REL_WHEEL)),
+            None
+        );
+        assert_eq!(
+            profile("powermate").binding(&EventCode::EV_KEY(EV_KEY::BTN_TOUCH)),
+            None
+        );
+        // the same code with a different type
+        assert_eq!(
+            profile("surface-dial").binding(&EventCode::EV_KEY(EV_KEY::BTN_1)),
+            None
+        );
+    }
+
+    #[test]
+    fn profile_names_unique() {
+        for (i, profile) in PROFILES.iter().enumerate() {
+            assert!(
+                PROFILES[i + 1..].iter().all(|p| p.name != profile.name),
+                "duplicate profile {}",
+                profile.name
+            );
+        }
+    }
+
+    #[test]
+    fn codes_bound_once() {
+        for profile in PROFILES {
+            for (i, (code, _)) in profile.bindings.iter().enumerate() {
+                assert!(
+                    profile.bindings[i + 1..].iter().all(|(c, _)| c != code),
+                    "{:?} bound twice in {}",
+                    code,
+                    profile.name
+                );
+            }
+        }
+    }
+}
@@ -3,34 +3,60 @@ use std::io;
 
 use evdev_rs::InputEvent;
 
+use crate::config::ConfigErrors;
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Debug)]
 pub enum Error {
-    ConfigFile(String),
+    Config(ConfigErrors),
+    StateFile(std::path::PathBuf, io::Error),
     OpenDevInputDir(io::Error),
     OpenEventFile(std::path::PathBuf, io::Error),
     HidError(hidapi::HidError),
+    ReportDescriptor(std::path::PathBuf, io::Error),
     MissingDial,
-    MultipleDials,
+    Udev(io::Error),
+    Dbus(dbus::Error),
+    InvalidSteps(u16),
+    UnknownHapticPattern(String),
     UnexpectedEvt(InputEvent),
     Evdev(io::Error),
     Notif(notify_rust::error::Error),
+    Overlay(String),
+    FeedbackCommand(String, io::Error),
     TermSig,
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Error::ConfigFile(e) => write!(f, "Could not open config file: {}", e),
+            Error::Config(e) => write!(f, "Invalid config:\n{}", e),
+            Error::StateFile(path, e) => write!(f, "Could not access state file {:?}: {}", path, e),
             Error::OpenDevInputDir(e) => write!(f, "Could not open /dev/input directory: {}", e),
             Error::OpenEventFile(path, e) => write!(f, "Could not open {:?}: {}", path, e),
             Error::HidError(e) => write!(f, "HID API Error: {}", e),
+            Error::ReportDescriptor(path, e) => {
+                write!(f, "Could not read report descriptor for {:?}: {}", path, e)
+            }
             Error::MissingDial => write!(f, "Could not find the Surface Dial"),
-            Error::MultipleDials => write!(f, "Found multiple dials"),
+            Error::Udev(e) => write!(f, "Could not watch for dials: {}", e),
+            Error::Dbus(e) => write!(f, "D-Bus error: {}", e),
+            Error::InvalidSteps(steps) => {
+                write!(
+                    f,
+                    "Invalid number of steps: {} (must be at most 3600)",
+                    steps
+                )
+            }
+            Error::UnknownHapticPattern(name) => write!(f, "Unknown haptic pattern {:?}", name),
             Error::UnexpectedEvt(evt) => write!(f, "Unexpected event: {:?}", evt),
             Error::Evdev(e) => write!(f, "Evdev error: {}", e),
             Error::Notif(e) => write!(f, "Notification error: {}", e),
+            Error::Overlay(e) => write!(f, "Overlay error: {}", e),
+            Error::FeedbackCommand(program, e) => {
+                write!(f, "Could not run feedback command {:?}: {}", program, e)
+            }
             Error::TermSig => write!(f, "Received termination signal (either SIGTERM or SIGINT)"),
         }
     }
@@ -142,146 +142,148 @@ macro_rules! input_event {
     };
 }
 
-fn kbd_syn_report() -> io::Result<()> {
-    (FAKE_INPUTS.keyboard.lock()).write_event(&input_event!(EV_SYN, SYN_REPORT, 0))
-}
+impl FakeInputs {
+    fn kbd_syn_report(&self) -> io::Result<()> {
+        (self.keyboard.lock()).write_event(&input_event!(EV_SYN, SYN_REPORT, 0))
+    }
 
-pub fn key_click(keys: &[EV_KEY]) -> io::Result<()> {
-    key_press(keys)?;
-    key_release(keys)?;
-    Ok(())
-}
+    pub fn key_click(&self, keys: &[EV_KEY]) -> io::Result<()> {
+        self.key_press(keys)?;
+        self.key_release(keys)?;
+        Ok(())
+    }
 
-pub fn key_press(keys: &[EV_KEY]) -> io::Result<()> {
-    let keyboard = FAKE_INPUTS.keyboard.lock();
+    pub fn key_press(&self, keys: &[EV_KEY]) -> io::Result<()> {
+        let keyboard = self.keyboard.lock();
 
-    for key in keys {
-        keyboard.write_event(&InputEvent {
-            time: TimeVal::new(0, 0),
-            event_code: EventCode::EV_KEY(*key),
-            event_type: EventType::EV_KEY,
-            value: 1,
-        })?;
+        for key in keys {
+            keyboard.write_event(&InputEvent {
+                time: TimeVal::new(0, 0),
+                event_code: EventCode::EV_KEY(*key),
+                event_type: EventType::EV_KEY,
+                value: 1,
+            })?;
+        }
+        self.kbd_syn_report()?;
+        Ok(())
     }
-    kbd_syn_report()?;
-    Ok(())
-}
 
-pub fn key_release(keys: &[EV_KEY]) -> io::Result<()> {
-    let keyboard = FAKE_INPUTS.keyboard.lock();
+    pub fn key_release(&self, keys: &[EV_KEY]) -> io::Result<()> {
+        let keyboard = self.keyboard.lock();
 
-    for key in keys.iter().clone() {
+        for key in keys.iter().clone() {
+            keyboard.write_event(&InputEvent {
+                time: TimeVal::new(0, 0),
+                event_code: EventCode::EV_KEY(*key),
+                event_type: EventType::EV_KEY,
+                value: 0,
+            })?;
+        }
+        self.kbd_syn_report()?;
+        Ok(())
+    }
+
+    pub fn scroll_step(&self, dir: ScrollStep) -> io::Result<()> {
+        let keyboard = self.keyboard.lock();
+
+        // copied from my razer blackwidow chroma mouse
         keyboard.write_event(&InputEvent {
             time: TimeVal::new(0, 0),
-            event_code: EventCode::EV_KEY(*key),
-            event_type: EventType::EV_KEY,
-            value: 0,
+            event_code: EventCode::EV_REL(EV_REL::REL_WHEEL),
+            event_type: EventType::EV_REL,
+            value: match dir {
+                ScrollStep::Down => -1,
+                ScrollStep::Up => 1,
+            },
         })?;
+        keyboard.write_event(&InputEvent {
+            time: TimeVal::new(0, 0),
+            event_code: EventCode::EV_REL(EV_REL::REL_WHEEL_HI_RES),
+            event_type: EventType::EV_REL,
+            value: match dir {
+                ScrollStep::Down => -120,
+                ScrollStep::Up => 120,
+            },
+        })?;
+        self.kbd_syn_report()?;
+        Ok(())
     }
-    kbd_syn_report()?;
-    Ok(())
-}
-
-pub fn scroll_step(dir: ScrollStep) -> io::Result<()> {
-    let keyboard = FAKE_INPUTS.keyboard.lock();
-
-    // copied from my razer blackwidow chroma mouse
-    keyboard.write_event(&InputEvent {
-        time: TimeVal::new(0, 0),
-        event_code: EventCode::EV_REL(EV_REL::REL_WHEEL),
-        event_type: EventType::EV_REL,
-        value: match dir {
-            ScrollStep::Down => -1,
-            ScrollStep::Up => 1,
-        },
-    })?;
-    keyboard.write_event(&InputEvent {
-        time: TimeVal::new(0, 0),
-        event_code: EventCode::EV_REL(EV_REL::REL_WHEEL_HI_RES),
-        event_type: EventType::EV_REL,
-        value: match dir {
-            ScrollStep::Down => -120,
-            ScrollStep::Up => 120,
-        },
-    })?;
-    kbd_syn_report()?;
-    Ok(())
-}
 
-fn touch_syn_report() -> io::Result<()> {
-    (FAKE_INPUTS.touchpad.lock()).write_event(&input_event!(EV_SYN, SYN_REPORT, 0))
-}
+    fn touch_syn_report(&self) -> io::Result<()> {
+        (self.touchpad.lock()).write_event(&input_event!(EV_SYN, SYN_REPORT, 0))
+    }
 
-pub fn scroll_mt_start() -> io::Result<()> {
-    let touchpad = FAKE_INPUTS.touchpad.lock();
+    pub fn scroll_mt_start(&self) -> io::Result<()> {
+        let touchpad = self.touchpad.lock();
 
-    {
-        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 0))?;
-        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_TRACKING_ID, 1))?;
-        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_POSITION_X, MT_BASELINE))?;
-        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_POSITION_Y, MT_BASELINE))?;
+        {
+            touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 0))?;
+            touchpad.write_event(&input_event!(EV_ABS, ABS_MT_TRACKING_ID, 1))?;
+            touchpad.write_event(&input_event!(EV_ABS, ABS_MT_POSITION_X, MT_BASELINE))?;
+            touchpad.write_event(&input_event!(EV_ABS, ABS_MT_POSITION_Y, MT_BASELINE))?;
 
-        touchpad.write_event(&input_event!(EV_KEY, BTN_TOUCH, 1))?;
-        touchpad.write_event(&input_event!(EV_KEY, BTN_TOOL_FINGER, 1))?;
+            touchpad.write_event(&input_event!(EV_KEY, BTN_TOUCH, 1))?;
+            touchpad.write_event(&input_event!(EV_KEY, BTN_TOOL_FINGER, 1))?;
 
-        touchpad.write_event(&input_event!(EV_ABS, ABS_X, MT_BASELINE))?;
-        touchpad.write_event(&input_event!(EV_ABS, ABS_Y, MT_BASELINE))?;
-    }
+            touchpad.write_event(&input_event!(EV_ABS, ABS_X, MT_BASELINE))?;
+            touchpad.write_event(&input_event!(EV_ABS, ABS_Y, MT_BASELINE))?;
+        }
 
-    touch_syn_report()?;
+        self.touch_syn_report()?;
 
-    {
-        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 1))?;
-        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_TRACKING_ID, 2))?;
-        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_POSITION_X, MT_BASELINE / 2))?;
-        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_POSITION_Y, MT_BASELINE))?;
+        {
+            touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 1))?;
+            touchpad.write_event(&input_event!(EV_ABS, ABS_MT_TRACKING_ID, 2))?;
+            touchpad.write_event(&input_event!(EV_ABS, ABS_MT_POSITION_X, MT_BASELINE / 2))?;
+            touchpad.write_event(&input_event!(EV_ABS, ABS_MT_POSITION_Y, MT_BASELINE))?;
 
-        touchpad.write_event(&input_event!(EV_KEY, BTN_TOOL_FINGER, 0))?;
-        touchpad.write_event(&input_event!(EV_KEY, BTN_TOOL_DOUBLETAP, 1))?;
-    }
+            touchpad.write_event(&input_event!(EV_KEY, BTN_TOOL_FINGER, 0))?;
+            touchpad.write_event(&input_event!(EV_KEY, BTN_TOOL_DOUBLETAP, 1))?;
+        }
 
-    touch_syn_report()?;
+        self.touch_syn_report()?;
 
-    Ok(())
-}
+        Ok(())
+    }
 
-pub fn scroll_mt_step(delta: i32) -> io::Result<()> {
-    let touchpad = FAKE_INPUTS.touchpad.lock();
+    pub fn scroll_mt_step(&self, delta: i32) -> io::Result<()> {
+        let touchpad = self.touchpad.lock();
 
-    touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 0))?;
-    touchpad.write_event(&input_event!(
-        EV_ABS,
-        ABS_MT_POSITION_Y,
-        MT_BASELINE + delta
-    ))?;
-    touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 1))?;
-    touchpad.write_event(&input_event!(
-        EV_ABS,
-        ABS_MT_POSITION_Y,
-        MT_BASELINE + delta
-    ))?;
+        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 0))?;
+        touchpad.write_event(&input_event!(
+            EV_ABS,
+            ABS_MT_POSITION_Y,
+            MT_BASELINE + delta
+        ))?;
+        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 1))?;
+        touchpad.write_event(&input_event!(
+            EV_ABS,
+            ABS_MT_POSITION_Y,
+            MT_BASELINE + delta
+        ))?;
 
-    touchpad.write_event(&input_event!(EV_ABS, ABS_Y, MT_BASELINE + delta))?;
+        touchpad.write_event(&input_event!(EV_ABS, ABS_Y, MT_BASELINE + delta))?;
 
-    touch_syn_report()?;
+        self.touch_syn_report()?;
 
-    Ok(())
-}
+        Ok(())
+    }
 
-pub fn scroll_mt_end() -> io::Result<()> {
-    let touchpad = FAKE_INPUTS.touchpad.lock();
+    pub fn scroll_mt_end(&self) -> io::Result<()> {
+        let touchpad = self.touchpad.lock();
 
-    touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 0))?;
-    touchpad.write_event(&input_event!(EV_ABS, ABS_MT_TRACKING_ID, -1))?;
-    touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 1))?;
-    touchpad.write_event(&input_event!(EV_ABS, ABS_MT_TRACKING_ID, -1))?;
+        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 0))?;
+        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_TRACKING_ID, -1))?;
+        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_SLOT, 1))?;
+        touchpad.write_event(&input_event!(EV_ABS, ABS_MT_TRACKING_ID, -1))?;
 
-    touchpad.write_event(&input_event!(EV_KEY, BTN_TOUCH, 0))?;
-    touchpad.write_event(&input_event!(EV_KEY, BTN_TOOL_DOUBLETAP, 0))?;
+        touchpad.write_event(&input_event!(EV_KEY, BTN_TOUCH, 0))?;
+        touchpad.write_event(&input_event!(EV_KEY, BTN_TOOL_DOUBLETAP, 0))?;
 
-    touch_syn_report()?;
+        self.touch_syn_report()?;
 
-    Ok(())
+        Ok(())
+    }
 }
 
 pub enum ScrollStep {
@@ -0,0 +1,137 @@
+use std::process::{Command, Stdio};
+use std::sync::{mpsc, Mutex};
+
+use super::{Feedback, Menu, MenuFeedback, MenuItem, Value};
+use crate::error::{Error, Result};
+
+/// Runs an external program for each piece of feedback, which is passed the
+/// kind of feedback followed by its details as arguments:
+///
+/// - `message <icon> <msg>`
+/// - `alert <icon> <msg>`
+/// - `value <icon> <label> <value> <min> <max>`
+/// - `menu-open <selected index> <item names...>`
+/// - `menu-select <selected index> <item name> <item icon>`
+/// - `menu-close`
+///
+/// The program is run on a background thread (it shouldn't hold up the dial),
+/// one invocation at a time, so each invocation sees the feedback in order.
+/// Values which have already been superseded by the time the program is free
+/// are skipped.
+pub struct CommandFeedback {
+    /// Arguments for each invocation of the program, in order.
+    jobs: Mutex<mpsc::Sender<Vec<String>>>,
+}
+
+impl CommandFeedback {
+    pub fn new(program: &str) -> CommandFeedback {
+        let (jobs, jobs_rx) = mpsc::channel::<Vec<String>>();
+
+        std::thread::spawn({
+            let program = program.to_string();
+            // exits once the backend is dropped (e.g: when the config is
+            // reloaded), after working through any remaining jobs
+            move || {
+                let mut pending = None;
+                while let Some(mut args) = pending.take().or_else(|| jobs_rx.recv().ok()) {
+                    // if values are coming in faster than the program can
+                    // handle them, skip straight to the most recent one
+                    while args[0] == "value" {
+                        match jobs_rx.try_recv() {
+                            Ok(next) if next[0] == "value" => args = next,
+                            Ok(next) => {
+                                pending = Some(next);
+                                break;
+                            }
+                            Err(_) => break,
+                        }
+                    }
+                    run(&program, &args);
+                }
+            }
+        });
+
+        CommandFeedback {
+            jobs: Mutex::new(jobs),
+        }
+    }
+
+    fn send(&self, args: &[&str]) -> Result<()> {
+        queue(&self.jobs.lock().unwrap(), args);
+        Ok(())
+    }
+}
+
+fn queue(jobs: &mpsc::Sender<Vec<String>>, args: &[&str]) {
+    let _ = jobs.send(args.iter().map(|arg| arg.to_string()).collect());
+}
+
+/// Runs the program to completion.
+fn run(program: &str, args: &[String]) {
+    let res = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| Error::FeedbackCommand(program.to_string(), e));
+
+    match res {
+        Ok(status) if !status.success() => {
+            eprintln!("feedback command {:?} exited with {}", program, status)
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("{}", e),
+    }
+}
+
+impl Feedback for CommandFeedback {
+    fn message(&self, msg: &str, icon: &str) -> Result<()> {
+        self.send(&["message", icon, msg])
+    }
+
+    fn alert(&self, msg: &str, icon: &str) -> Result<()> {
+        self.send(&["alert", icon, msg])
+    }
+
+    fn value(&self, value: &Value) -> Result<()> {
+        self.send(&[
+            "value",
+            value.icon,
+            value.label,
+            &value.value.to_string(),
+            &value.min.to_string(),
+            &value.max.to_string(),
+        ])
+    }
+
+    fn open_menu(&self, menu: Menu) -> Result<Box<dyn MenuFeedback>> {
+        let selected = menu.selected.to_string();
+        let mut args = vec!["menu-open", &selected];
+        args.extend(menu.items.iter().map(|item| item.name.as_str()));
+        self.send(&args)?;
+
+        Ok(Box::new(CommandMenu {
+            jobs: self.jobs.lock().unwrap().clone(),
+            items: menu.items,
+        }))
+    }
+}
+
+struct CommandMenu {
+    jobs: mpsc::Sender<Vec<String>>,
+    items: Vec<MenuItem>,
+}
+
+impl MenuFeedback for CommandMenu {
+    fn select(&mut self, selected: usize) -> Result<()> {
+        let item = &self.items[selected];
+        queue(
+            &self.jobs,
+            &["menu-select", &selected.to_string(), &item.name, &item.icon],
+        );
+        Ok(())
+    }
+
+    fn close(self: Box<Self>) {
+        queue(&self.jobs, &["menu-close"]);
+    }
+}
@@ -0,0 +1,44 @@
+use super::{Feedback, Menu, MenuFeedback, MenuItem, Value};
+use crate::error::Result;
+
+/// Only logs to stderr (e.g: for headless setups, or desktops without a
+/// notification daemon).
+pub struct LogFeedback;
+
+impl Feedback for LogFeedback {
+    fn message(&self, msg: &str, _icon: &str) -> Result<()> {
+        eprintln!("{}", msg);
+        Ok(())
+    }
+
+    fn alert(&self, msg: &str, _icon: &str) -> Result<()> {
+        eprintln!("{}", msg);
+        Ok(())
+    }
+
+    fn value(&self, value: &Value) -> Result<()> {
+        eprintln!("{}: {}%", value.label, value.percent());
+        Ok(())
+    }
+
+    fn open_menu(&self, menu: Menu) -> Result<Box<dyn MenuFeedback>> {
+        eprintln!(
+            "entered meta mode (from mode: {})",
+            menu.items[menu.selected].name
+        );
+        Ok(Box::new(LogMenu { items: menu.items }))
+    }
+}
+
+struct LogMenu {
+    items: Vec<MenuItem>,
+}
+
+impl MenuFeedback for LogMenu {
+    fn select(&mut self, selected: usize) -> Result<()> {
+        eprintln!("new mode: {}", self.items[selected].name);
+        Ok(())
+    }
+
+    fn close(self: Box<Self>) {}
+}
@@ -0,0 +1,131 @@
+//! Visual feedback (messages, warnings, and the meta-menu), which can be
+//! routed through one of several backends.
+
+use std::sync::{Arc, RwLock};
+
+use crate::config::Config;
+use crate::error::Result;
+
+mod command;
+mod log;
+mod notification;
+mod overlay;
+
+pub use command::CommandFeedback;
+pub use log::LogFeedback;
+pub use notification::NotificationFeedback;
+pub use overlay::OverlayFeedback;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FeedbackBackend {
+    /// FreeDesktop notifications.
+    Notification,
+    /// Only log to stderr (e.g: for headless setups).
+    Log,
+    /// An on-screen wheel for the meta-menu, with everything else logged.
+    Overlay,
+    /// Hand everything off to an external program.
+    Command,
+}
+
+impl FeedbackBackend {
+    pub const NAMES: &'static [&'static str] = &["notification", "log", "overlay", "command"];
+
+    pub fn from_name(name: &str) -> Option<FeedbackBackend> {
+        match name {
+            "notification" => Some(FeedbackBackend::Notification),
+            "log" => Some(FeedbackBackend::Log),
+            "overlay" => Some(FeedbackBackend::Overlay),
+            "command" => Some(FeedbackBackend::Command),
+            _ => None,
+        }
+    }
+}
+
+/// An entry in the meta-menu.
+#[derive(Debug, Clone)]
+pub struct MenuItem {
+    pub name: String,
+    /// A file:// url, or a standard FreeDesktop icon name.
+    pub icon: String,
+}
+
+/// The meta-menu, as it's first opened.
+#[derive(Debug, Clone)]
+pub struct Menu {
+    pub items: Vec<MenuItem>,
+    /// Index of the currently highlighted item (i.e: the current mode).
+    pub selected: usize,
+    pub battery: Option<u8>,
+}
+
+/// A value published by a mode (e.g: the current volume), within
+/// `min..=max`.
+#[derive(Debug, Clone)]
+pub struct Value<'a> {
+    pub label: &'a str,
+    pub icon: &'a str,
+    pub value: i32,
+    pub min: i32,
+    pub max: i32,
+}
+
+impl Value<'_> {
+    /// The value's position within its range, as a percentage.
+    pub fn percent(&self) -> u8 {
+        if self.max <= self.min {
+            return 100;
+        }
+        let pos = (self.value - self.min) as f64 / (self.max - self.min) as f64;
+        (pos * 100.0).round().clamp(0.0, 100.0) as u8
+    }
+}
+
+pub trait Feedback: Send + Sync {
+    /// Briefly show a message (e.g: in response to an action).
+    fn message(&self, msg: &str, icon: &str) -> Result<()>;
+
+    /// Show a message which shouldn't go unnoticed (e.g: a warning).
+    fn alert(&self, msg: &str, icon: &str) -> Result<()>;
+
+    /// Show a value as an on-screen display (e.g: a progress bar). Repeated
+    /// calls should update the existing display, instead of stacking up.
+    fn value(&self, value: &Value) -> Result<()>;
+
+    /// Open the meta-menu, which stays open until the returned handle is
+    /// closed.
+    fn open_menu(&self, menu: Menu) -> Result<Box<dyn MenuFeedback>>;
+}
+
+/// Handle to an open meta-menu.
+pub trait MenuFeedback {
+    /// Highlight a different item.
+    fn select(&mut self, selected: usize) -> Result<()>;
+
+    fn close(self: Box<Self>);
+}
+
+lazy_static::lazy_static! {
+    static ref BACKEND: RwLock<Arc<dyn Feedback>> = RwLock::new(Arc::new(NotificationFeedback::new()));
+}
+
+/// The feedback backend selected in the most recently loaded config
+/// (defaulting to notifications).
+pub fn backend() -> Arc<dyn Feedback> {
+    BACKEND.read().unwrap().clone()
+}
+
+/// Switch to the feedback backend selected in `cfg`.
+pub fn configure(cfg: &Config) {
+    let backend: Arc<dyn Feedback> = match cfg.feedback {
+        FeedbackBackend::Notification => Arc::new(NotificationFeedback::new()),
+        FeedbackBackend::Log => Arc::new(LogFeedback),
+        FeedbackBackend::Overlay => Arc::new(OverlayFeedback),
+        FeedbackBackend::Command => match &cfg.feedback_command {
+            Some(program) => Arc::new(CommandFeedback::new(program)),
+            // rejected when parsing the config
+            None => Arc::new(LogFeedback),
+        },
+    };
+    *BACKEND.write().unwrap() = backend;
+}
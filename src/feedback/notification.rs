@@ -0,0 +1,163 @@
+use std::sync::{mpsc, Mutex};
+
+use notify_rust::{Hint, Notification, NotificationHandle, Timeout};
+
+use super::{Feedback, Menu, MenuFeedback, MenuItem, Value};
+use crate::error::{Error, Result};
+
+/// FreeDesktop notifications.
+pub struct NotificationFeedback {
+    /// Values are shown by a background thread, so that modes publishing a
+    /// value on every tick of the dial aren't held up by D-Bus round trips.
+    values: Mutex<mpsc::Sender<ValueUpdate>>,
+}
+
+/// An owned copy of the parts of a [`Value`] which are shown.
+struct ValueUpdate {
+    label: String,
+    icon: String,
+    percent: u8,
+}
+
+impl NotificationFeedback {
+    pub fn new() -> NotificationFeedback {
+        let (values, values_rx) = mpsc::channel::<ValueUpdate>();
+
+        // exits once the backend is dropped (e.g: when the config is reloaded)
+        std::thread::spawn(move || {
+            // ID of the most recent value notification, which is replaced by
+            // the next one
+            let mut value_id = None;
+            while let Ok(mut value) = values_rx.recv() {
+                // if values are coming in faster than they can be shown, skip
+                // straight to the most recent one
+                while let Ok(newer) = values_rx.try_recv() {
+                    value = newer;
+                }
+                match show_value(&value, value_id) {
+                    Ok(id) => value_id = Some(id),
+                    Err(e) => eprintln!("could not show value: {}", e),
+                }
+            }
+        });
+
+        NotificationFeedback {
+            values: Mutex::new(values),
+        }
+    }
+}
+
+/// Returns the ID of the shown notification.
+fn show_value(value: &ValueUpdate, replaces: Option<u32>) -> Result<u32> {
+    let mut notification = Notification::new();
+    notification
+        .hint(Hint::Transient(true))
+        .hint(Hint::Category("device".into()))
+        // rendered as a progress bar by most notification daemons
+        .hint(Hint::CustomInt("value".into(), value.percent as i32))
+        // some daemons (e.g: notify-osd, dunst) replace notifications with
+        // the same synchronous tag, regardless of their ID
+        .hint(Hint::Custom(
+            "x-canonical-private-synchronous".into(),
+            "surface-dial-value".into(),
+        ))
+        .timeout(Timeout::Milliseconds(1000))
+        .summary(&value.label)
+        .icon(&value.icon);
+    if let Some(id) = replaces {
+        notification.id(id);
+    }
+
+    let handle = notification.show().map_err(Error::Notif)?;
+    Ok(handle.id())
+}
+
+impl Feedback for NotificationFeedback {
+    fn message(&self, msg: &str, icon: &str) -> Result<()> {
+        eprintln!("sending notification: {}", msg);
+        Notification::new()
+            .hint(Hint::Transient(true))
+            .hint(Hint::Category("device".into()))
+            .timeout(Timeout::Milliseconds(100))
+            .summary("Surface Dial")
+            .body(msg)
+            .icon(icon)
+            .show()
+            .map_err(Error::Notif)?;
+        Ok(())
+    }
+
+    fn alert(&self, msg: &str, icon: &str) -> Result<()> {
+        eprintln!("sending notification: {}", msg);
+        Notification::new()
+            .hint(Hint::Category("device".into()))
+            .summary("Surface Dial")
+            .body(msg)
+            .icon(icon)
+            .show()
+            .map_err(Error::Notif)?;
+        Ok(())
+    }
+
+    fn value(&self, value: &Value) -> Result<()> {
+        let _ = self.values.lock().unwrap().send(ValueUpdate {
+            label: value.label.to_string(),
+            icon: value.icon.to_string(),
+            percent: value.percent(),
+        });
+        Ok(())
+    }
+
+    fn open_menu(&self, menu: Menu) -> Result<Box<dyn MenuFeedback>> {
+        let body = menu_body(
+            &format!(
+                "Entered Meta Mode (From Mode: {})",
+                menu.items[menu.selected].name
+            ),
+            menu.battery,
+        );
+        let handle = Notification::new()
+            .hint(Hint::Resident(true))
+            .hint(Hint::Category("device".into()))
+            .timeout(Timeout::Never)
+            .summary("Surface Dial")
+            .body(&body)
+            .icon("emblem-system")
+            .show()
+            .map_err(Error::Notif)?;
+
+        Ok(Box::new(NotificationMenu {
+            handle,
+            items: menu.items,
+            battery: menu.battery,
+        }))
+    }
+}
+
+/// Appends the battery level (if known) to the notification body.
+fn menu_body(msg: &str, battery: Option<u8>) -> String {
+    match battery {
+        Some(level) => format!("{}\nBattery: {}%", msg, level),
+        None => msg.to_string(),
+    }
+}
+
+struct NotificationMenu {
+    handle: NotificationHandle,
+    items: Vec<MenuItem>,
+    battery: Option<u8>,
+}
+
+impl MenuFeedback for NotificationMenu {
+    fn select(&mut self, selected: usize) -> Result<()> {
+        let item = &self.items[selected];
+        let body = menu_body(&format!("New Mode: {}", item.name), self.battery);
+        self.handle.body(&body).icon(&item.icon);
+        self.handle.update();
+        Ok(())
+    }
+
+    fn close(self: Box<Self>) {
+        self.handle.close();
+    }
+}
@@ -0,0 +1,574 @@
+//! An on-screen wheel for the meta-menu.
+//!
+//! The wheel is rendered in software into a plain pixel buffer, and uploaded
+//! to an override-redirect X11 window (shaped into a circle). This works on
+//! any X server (including XWayland), without requiring a GPU or a
+//! compositor.
+
+use std::fs::File;
+use std::path::PathBuf;
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+
+use x11rb::connection::{Connection, RequestConnection};
+use x11rb::protocol::shape::{self, ConnectionExt as _};
+use x11rb::protocol::xproto::*;
+use x11rb::protocol::Event;
+use x11rb::rust_connection::RustConnection;
+
+use super::{Feedback, LogFeedback, Menu, MenuFeedback, MenuItem, Value};
+use crate::error::{Error, Result};
+
+/// Width / height of the wheel (in pixels).
+const SIZE: u16 = 320;
+const OUTER_RADIUS: f32 = 156.0;
+const INNER_RADIUS: f32 = 64.0;
+const BORDER_WIDTH: f32 = 2.0;
+const ICON_SIZE: u32 = 48;
+
+const BACKGROUND: u32 = 0x2b2b2b;
+const SEGMENT: u32 = 0x3c3c3c;
+const SELECTED: u32 = 0x3584e4;
+const BORDER: u32 = 0x1e1e1e;
+const TEXT: u32 = 0xffffff;
+
+/// How often X events are checked for while idle.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+enum OverlayMsg {
+    Show {
+        items: Vec<MenuItem>,
+        selected: usize,
+    },
+    Select(usize),
+    Hide,
+}
+
+/// Shows the meta-menu as an on-screen wheel. Since the wheel can't display
+/// arbitrary messages (or values), those are only logged.
+pub struct OverlayFeedback;
+
+impl Feedback for OverlayFeedback {
+    fn message(&self, msg: &str, icon: &str) -> Result<()> {
+        LogFeedback.message(msg, icon)
+    }
+
+    fn alert(&self, msg: &str, icon: &str) -> Result<()> {
+        LogFeedback.alert(msg, icon)
+    }
+
+    fn value(&self, value: &Value) -> Result<()> {
+        LogFeedback.value(value)
+    }
+
+    fn open_menu(&self, menu: Menu) -> Result<Box<dyn MenuFeedback>> {
+        OVERLAY.show(menu.items, menu.selected);
+        Ok(Box::new(OverlayMenu))
+    }
+}
+
+struct OverlayMenu;
+
+impl MenuFeedback for OverlayMenu {
+    fn select(&mut self, selected: usize) -> Result<()> {
+        OVERLAY.select(selected);
+        Ok(())
+    }
+
+    fn close(self: Box<Self>) {
+        OVERLAY.hide();
+    }
+}
+
+/// Handle to the overlay's render thread.
+struct Overlay {
+    msgs: Mutex<mpsc::Sender<OverlayMsg>>,
+}
+
+lazy_static::lazy_static! {
+    /// The render thread (and X11 connection) is only set up once the overlay
+    /// is first used.
+    static ref OVERLAY: Overlay = {
+        let (msgs, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            if let Err(e) = render_loop(rx) {
+                eprintln!("the wheel overlay died! {}", e);
+            }
+        });
+        Overlay {
+            msgs: Mutex::new(msgs),
+        }
+    };
+}
+
+impl Overlay {
+    /// Show the wheel, with `selected` highlighted.
+    fn show(&self, items: Vec<MenuItem>, selected: usize) {
+        self.send(OverlayMsg::Show { items, selected })
+    }
+
+    /// Highlight a different item.
+    fn select(&self, selected: usize) {
+        self.send(OverlayMsg::Select(selected))
+    }
+
+    fn hide(&self) {
+        self.send(OverlayMsg::Hide)
+    }
+
+    fn send(&self, msg: OverlayMsg) {
+        // if the render thread died, the error has already been logged
+        let _ = self.msgs.lock().unwrap().send(msg);
+    }
+}
+
+fn render_loop(msgs: mpsc::Receiver<OverlayMsg>) -> Result<()> {
+    let mut wheel = Wheel::new()?;
+
+    loop {
+        match msgs.recv_timeout(POLL_INTERVAL) {
+            Ok(OverlayMsg::Show { items, selected }) => {
+                wheel.items = items
+                    .into_iter()
+                    .map(|item| {
+                        let icon = find_icon(&item.icon).and_then(load_icon);
+                        (item, icon)
+                    })
+                    .collect();
+                wheel.selected = selected;
+                wheel.show()?;
+            }
+            Ok(OverlayMsg::Select(selected)) => {
+                wheel.selected = selected;
+                if wheel.visible {
+                    wheel.draw()?;
+                }
+            }
+            Ok(OverlayMsg::Hide) => wheel.hide()?,
+            Err(mpsc::RecvTimeoutError::Timeout) => {}
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        while let Some(event) = wheel.conn.poll_for_event().map_err(x11_error)? {
+            if let Event::Expose(e) = event {
+                // only redraw once the final expose event in a series arrives
+                if e.count == 0 && wheel.visible {
+                    wheel.draw()?;
+                }
+            }
+        }
+    }
+}
+
+fn x11_error(e: impl std::fmt::Display) -> Error {
+    Error::Overlay(e.to_string())
+}
+
+/// An RGBA image.
+struct Icon {
+    width: u32,
+    height: u32,
+    pixels: Vec<[u8; 4]>,
+}
+
+struct Wheel {
+    conn: RustConnection,
+    window: Window,
+    gc: Gcontext,
+    font: Font,
+    depth: u8,
+    /// Whether pixels should be sent most significant byte first.
+    big_endian: bool,
+
+    visible: bool,
+    items: Vec<(MenuItem, Option<Icon>)>,
+    selected: usize,
+}
+
+impl Wheel {
+    fn new() -> Result<Wheel> {
+        let (conn, screen_num) = RustConnection::connect(None).map_err(x11_error)?;
+        let screen = &conn.setup().roots[screen_num];
+        let depth = screen.root_depth;
+        let big_endian = conn.setup().image_byte_order == ImageOrder::MSB_FIRST;
+
+        let window = conn.generate_id().map_err(x11_error)?;
+        let gc = conn.generate_id().map_err(x11_error)?;
+        let font = conn.generate_id().map_err(x11_error)?;
+
+        // centered on the (first) screen
+        let x = (screen.width_in_pixels.saturating_sub(SIZE) / 2) as i16;
+        let y = (screen.height_in_pixels.saturating_sub(SIZE) / 2) as i16;
+
+        conn.create_window(
+            depth,
+            window,
+            screen.root,
+            x,
+            y,
+            SIZE,
+            SIZE,
+            0,
+            WindowClass::INPUT_OUTPUT,
+            screen.root_visual,
+            &CreateWindowAux::new()
+                // i.e: not managed (or decorated) by the window manager
+                .override_redirect(1)
+                .background_pixel(BACKGROUND)
+                .event_mask(EventMask::EXPOSURE),
+        )
+        .map_err(x11_error)?;
+
+        // cut the window down to a circle
+        let center = SIZE as f32 / 2.0;
+        let rects = (0..SIZE)
+            .filter_map(|row| {
+                let dy = row as f32 + 0.5 - center;
+                let half_width = (OUTER_RADIUS * OUTER_RADIUS - dy * dy).sqrt();
+                if half_width.is_nan() {
+                    return None;
+                }
+                Some(Rectangle {
+                    x: (center - half_width).round() as i16,
+                    y: row as i16,
+                    width: (half_width * 2.0).round() as u16,
+                    height: 1,
+                })
+            })
+            .collect::<Vec<_>>();
+        conn.shape_rectangles(
+            shape::SO::SET,
+            shape::SK::BOUNDING,
+            ClipOrdering::Y_SORTED,
+            window,
+            0,
+            0,
+            &rects,
+        )
+        .map_err(x11_error)?;
+
+        // the core "fixed" font is always available
+        conn.open_font(font, b"fixed").map_err(x11_error)?;
+        conn.create_gc(
+            gc,
+            window,
+            &CreateGCAux::new()
+                .foreground(TEXT)
+                .background(BACKGROUND)
+                .font(font),
+        )
+        .map_err(x11_error)?;
+        conn.flush().map_err(x11_error)?;
+
+        Ok(Wheel {
+            conn,
+            window,
+            gc,
+            font,
+            depth,
+            big_endian,
+
+            visible: false,
+            items: Vec::new(),
+            selected: 0,
+        })
+    }
+
+    fn show(&mut self) -> Result<()> {
+        if !self.visible {
+            self.conn.map_window(self.window).map_err(x11_error)?;
+            self.visible = true;
+        }
+        self.conn
+            .configure_window(
+                self.window,
+                &ConfigureWindowAux::new().stack_mode(StackMode::ABOVE),
+            )
+            .map_err(x11_error)?;
+        self.draw()
+    }
+
+    fn hide(&mut self) -> Result<()> {
+        if self.visible {
+            self.conn.unmap_window(self.window).map_err(x11_error)?;
+            self.conn.flush().map_err(x11_error)?;
+            self.visible = false;
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self) -> Result<()> {
+        let pixels = render(&self.items, self.selected);
+
+        // split the image into chunks of rows, as to not exceed the server's
+        // maximum request length
+        let row_bytes = SIZE as usize * 4;
+        let max_rows = ((self.conn.maximum_request_bytes() - 64) / row_bytes).max(1);
+        for (chunk_idx, rows) in pixels.chunks(max_rows * SIZE as usize).enumerate() {
+            let data = rows
+                .iter()
+                .flat_map(|&px| {
+                    if self.big_endian {
+                        px.to_be_bytes()
+                    } else {
+                        px.to_le_bytes()
+                    }
+                })
+                .collect::<Vec<_>>();
+            self.conn
+                .put_image(
+                    ImageFormat::Z_PIXMAP,
+                    self.window,
+                    self.gc,
+                    SIZE,
+                    (rows.len() / SIZE as usize) as u16,
+                    0,
+                    (chunk_idx * max_rows) as i16,
+                    0,
+                    self.depth,
+                    &data,
+                )
+                .map_err(x11_error)?;
+        }
+
+        self.draw_labels()?;
+        self.conn.flush().map_err(x11_error)?;
+        Ok(())
+    }
+
+    fn draw_labels(&self) -> Result<()> {
+        // items without an icon are labeled with their name instead
+        for (idx, (item, icon)) in self.items.iter().enumerate() {
+            if icon.is_none() {
+                let background = if idx == self.selected {
+                    SELECTED
+                } else {
+                    SEGMENT
+                };
+                let name = item.name.chars().take(8).collect::<String>();
+                let (x, y) = segment_center(idx, self.items.len());
+                self.draw_text(&name, x, y, background)?;
+            }
+        }
+
+        if let Some((item, _)) = self.items.get(self.selected) {
+            let center = SIZE as i32 / 2;
+            let name = item.name.chars().take(12).collect::<String>();
+            self.draw_text(&name, center, center, BACKGROUND)?;
+        }
+
+        Ok(())
+    }
+
+    /// Draw a line of text centered on (`x`, `y`).
+    fn draw_text(&self, text: &str, x: i32, y: i32, background: u32) -> Result<()> {
+        // the core font only supports latin-1
+        let text = text
+            .chars()
+            .map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' })
+            .collect::<Vec<_>>();
+        let chars = text
+            .iter()
+            .map(|&c| Char2b { byte1: 0, byte2: c })
+            .collect::<Vec<_>>();
+        let extents = self
+            .conn
+            .query_text_extents(self.font, &chars)
+            .map_err(x11_error)?
+            .reply()
+            .map_err(x11_error)?;
+
+        let height = extents.font_ascent as i32 + extents.font_descent as i32;
+        self.conn
+            .change_gc(self.gc, &ChangeGCAux::new().background(background))
+            .map_err(x11_error)?;
+        self.conn
+            .image_text8(
+                self.window,
+                self.gc,
+                (x - extents.overall_width / 2) as i16,
+                (y - height / 2 + extents.font_ascent as i32) as i16,
+                &text,
+            )
+            .map_err(x11_error)?;
+        Ok(())
+    }
+}
+
+/// Render the wheel (minus any text) into a `SIZE` x `SIZE` buffer of
+/// `0xRRGGBB` pixels.
+fn render(items: &[(MenuItem, Option<Icon>)], selected: usize) -> Vec<u32> {
+    let size = SIZE as usize;
+    let center = SIZE as f32 / 2.0;
+    let count = items.len().max(1);
+    let segment_angle = std::f32::consts::PI * 2.0 / count as f32;
+
+    let mut pixels = vec![BACKGROUND; size * size];
+    for (idx, px) in pixels.iter_mut().enumerate() {
+        let dx = (idx % size) as f32 + 0.5 - center;
+        let dy = (idx / size) as f32 + 0.5 - center;
+        let radius = (dx * dx + dy * dy).sqrt();
+        if !(INNER_RADIUS - BORDER_WIDTH..=OUTER_RADIUS).contains(&radius) {
+            continue;
+        }
+        if !(INNER_RADIUS..=OUTER_RADIUS - BORDER_WIDTH).contains(&radius) {
+            *px = BORDER;
+            continue;
+        }
+
+        // clockwise from 12 o'clock, with the first segment centered on
+        // 12 o'clock
+        let angle = (dx.atan2(-dy) + segment_angle / 2.0).rem_euclid(segment_angle * count as f32);
+        let segment = (angle / segment_angle) as usize % count;
+
+        let into_segment = angle - segment as f32 * segment_angle;
+        let edge_distance = into_segment.min(segment_angle - into_segment) * radius;
+        *px = if count > 1 && edge_distance < BORDER_WIDTH / 2.0 {
+            BORDER
+        } else if segment == selected {
+            SELECTED
+        } else {
+            SEGMENT
+        };
+    }
+
+    for (idx, (_, icon)) in items.iter().enumerate() {
+        if let Some(icon) = icon {
+            let (x, y) = segment_center(idx, items.len());
+            let half = ICON_SIZE as i32 / 2;
+            blit_icon(&mut pixels, icon, x - half, y - half);
+        }
+    }
+
+    pixels
+}
+
+/// Returns the center of an item's segment (in pixels), given the total
+/// number of items.
+fn segment_center(idx: usize, count: usize) -> (i32, i32) {
+    let center = SIZE as f32 / 2.0;
+    let radius = (INNER_RADIUS + OUTER_RADIUS) / 2.0;
+    let angle = std::f32::consts::PI * 2.0 * idx as f32 / count as f32;
+    (
+        (center + radius * angle.sin()).round() as i32,
+        (center - radius * angle.cos()).round() as i32,
+    )
+}
+
+/// Alpha-blend an icon onto the pixel buffer (scaled to `ICON_SIZE`), with
+/// its top-left corner at (`x`, `y`).
+fn blit_icon(pixels: &mut [u32], icon: &Icon, x: i32, y: i32) {
+    let size = SIZE as i32;
+    for dy in 0..ICON_SIZE {
+        for dx in 0..ICON_SIZE {
+            let (px, py) = (x + dx as i32, y + dy as i32);
+            if px < 0 || py < 0 || px >= size || py >= size {
+                continue;
+            }
+
+            // nearest-neighbour scaling is good enough at these sizes
+            let sx = dx * icon.width / ICON_SIZE;
+            let sy = dy * icon.height / ICON_SIZE;
+            let [r, g, b, a] = icon.pixels[(sy * icon.width + sx) as usize];
+
+            let dst = &mut pixels[(py * size + px) as usize];
+            let blend = |src: u8, shift: u32| {
+                let dst = (*dst >> shift) & 0xff;
+                (src as u32 * a as u32 + dst * (255 - a as u32)) / 255
+            };
+            *dst = blend(r, 16) << 16 | blend(g, 8) << 8 | blend(b, 0);
+        }
+    }
+}
+
+/// Look up a FreeDesktop icon (or file:// url), in roughly the size we need.
+///
+/// Only PNG icons from the hicolor / Adwaita themes are supported.
+fn find_icon(icon: &str) -> Option<PathBuf> {
+    if let Some(path) = icon.strip_prefix("file://") {
+        return Some(PathBuf::from(path));
+    }
+    if icon.is_empty() {
+        return None;
+    }
+
+    let mut data_dirs = std::env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".into())
+        .split(':')
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    if let Some(dirs) = directories::BaseDirs::new() {
+        data_dirs.insert(0, dirs.data_dir().to_owned());
+    }
+
+    let file_name = format!("{}.png", icon);
+    for data_dir in data_dirs.iter() {
+        for theme in ["Adwaita", "hicolor"].iter() {
+            for size in ["48x48", "64x64", "96x96", "128x128", "32x32"].iter() {
+                let size_dir = data_dir.join("icons").join(theme).join(size);
+                let contexts = match std::fs::read_dir(&size_dir) {
+                    Ok(contexts) => contexts,
+                    Err(_) => continue,
+                };
+                for context in contexts.filter_map(|e| e.ok()) {
+                    let path = context.path().join(&file_name);
+                    if path.exists() {
+                        return Some(path);
+                    }
+                }
+            }
+        }
+
+        let path = data_dir.join("pixmaps").join(&file_name);
+        if path.exists() {
+            return Some(path);
+        }
+    }
+
+    None
+}
+
+fn load_icon(path: PathBuf) -> Option<Icon> {
+    let mut decoder = png::Decoder::new(File::open(&path).ok()?);
+    // normalize everything to 8-bit (grayscale / rgb), with an optional alpha
+    // channel
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let (info, mut reader) = match decoder.read_info() {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("could not load icon {:?}: {}", path, e);
+            return None;
+        }
+    };
+
+    let mut buf = vec![0; info.buffer_size()];
+    if let Err(e) = reader.next_frame(&mut buf) {
+        eprintln!("could not load icon {:?}: {}", path, e);
+        return None;
+    }
+
+    let pixels = match reader.output_color_type().0 {
+        png::ColorType::RGBA => buf
+            .chunks_exact(4)
+            .map(|c| [c[0], c[1], c[2], c[3]])
+            .collect(),
+        png::ColorType::RGB => buf
+            .chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .map(|c| [c[0], c[0], c[0], c[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().map(|&c| [c, c, c, 255]).collect(),
+        png::ColorType::Indexed => return None,
+    };
+
+    Some(Icon {
+        width: info.width,
+        height: info.height,
+        pixels,
+    })
+}
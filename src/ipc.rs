@@ -0,0 +1,206 @@
+//! A tiny IPC interface, used to query the running daemon from the command
+//! line.
+//!
+//! The daemon listens on a Unix socket at
+//! `$XDG_RUNTIME_DIR/surface-dial-daemon.sock`. Clients send a single
+//! newline-terminated command, and the daemon replies with some
+//! human-readable text before closing the connection.
+//!
+//! Supported commands:
+//!
+//! - `status`: one line per dial, e.g: `aa:bb:cc:dd:ee:ff connected battery=80%`
+//!   (along with the dial's signal strength, if BlueZ knows it)
+//! - `device-info`: detailed info about each dial's underlying devices (device
+//!   nodes, firmware version, report layout, haptic capabilities, etc...)
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, prelude::*, BufReader};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::bluez::Bluez;
+use crate::dial_device::DeviceInfoHandle;
+
+/// What the daemon knows about a particular dial.
+#[derive(Debug, Default, Clone)]
+pub struct DialStatus {
+    pub connected: bool,
+    /// As a percentage.
+    pub battery: Option<u8>,
+    pub device: Option<DeviceInfoHandle>,
+}
+
+/// The status of every dial the daemon has seen, keyed by dial ID.
+pub type StatusMap = Arc<Mutex<BTreeMap<String, DialStatus>>>;
+
+pub fn socket_path() -> io::Result<PathBuf> {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir).join("surface-dial-daemon.sock")),
+        _ => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "XDG_RUNTIME_DIR is not set",
+        )),
+    }
+}
+
+/// Serve IPC requests.
+///
+/// Blocks forever (unless an error occurs).
+pub fn serve(status: StatusMap) -> io::Result<()> {
+    let path = socket_path()?;
+
+    // clean up after a previous instance of the daemon
+    match fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let listener = UnixListener::bind(&path)?;
+    for stream in listener.incoming() {
+        // a misbehaving client shouldn't bring down the server
+        if let Err(e) = stream.and_then(|stream| handle_client(stream, &status)) {
+            eprintln!("IPC error: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_client(stream: UnixStream, status: &StatusMap) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+
+    let mut command = String::new();
+    BufReader::new(&stream).read_line(&mut command)?;
+
+    let reply = match command.trim() {
+        "status" => format_status(&status.lock().unwrap(), &read_rssi()),
+        "device-info" => format_device_info(&status.lock().unwrap()),
+        other => format!("error: unknown command {:?}\n", other),
+    };
+
+    (&stream).write_all(reply.as_bytes())
+}
+
+/// Signal strength (in dBm) of each dial BlueZ knows about, keyed by (lowercase)
+/// Bluetooth address.
+fn read_rssi() -> HashMap<String, i16> {
+    let devices = match Bluez::system().and_then(|bluez| bluez.devices()) {
+        Ok(devices) => devices,
+        Err(_) => return HashMap::new(),
+    };
+
+    devices
+        .into_iter()
+        .filter_map(|dev| Some((dev.address.to_lowercase(), dev.rssi?)))
+        .collect()
+}
+
+fn format_status(status: &BTreeMap<String, DialStatus>, rssi: &HashMap<String, i16>) -> String {
+    if status.is_empty() {
+        return "no dials found\n".into();
+    }
+
+    let mut out = String::new();
+    for (id, dial) in status.iter() {
+        out += &format!(
+            "{} {} battery={}",
+            id,
+            if dial.connected {
+                "connected"
+            } else {
+                "disconnected"
+            },
+            match dial.battery {
+                Some(level) => format!("{}%", level),
+                None => "unknown".into(),
+            }
+        );
+        if let Some(rssi) = rssi.get(id) {
+            out += &format!(" rssi={}dBm", rssi);
+        }
+        out += "\n";
+    }
+    out
+}
+
+fn format_device_info(status: &BTreeMap<String, DialStatus>) -> String {
+    if status.is_empty() {
+        return "no dials found\n".into();
+    }
+
+    let or_unknown = |val: Option<String>| val.unwrap_or_else(|| "unknown".into());
+
+    let mut out = String::new();
+    for (id, dial) in status.iter() {
+        let info = dial.device.as_ref().map(|d| d.get()).unwrap_or_default();
+
+        out += &format!("{}:\n", id);
+        out += &format!("  connected: {}\n", dial.connected);
+        out += &format!(
+            "  profile: {}\n",
+            or_unknown(info.profile.map(String::from))
+        );
+        out += &format!(
+            "  evdev: {}\n",
+            or_unknown(info.evdev.map(|p| p.display().to_string()))
+        );
+        out += &format!(
+            "  hidraw: {}\n",
+            or_unknown(info.hidraw.map(|p| p.display().to_string()))
+        );
+        out += &format!(
+            "  version: {}\n",
+            or_unknown(info.version.map(|v| format!("{:#06x}", v)))
+        );
+
+        if !info.reports.is_empty() {
+            out += "  reports:\n";
+            for report in info.reports.iter() {
+                out += &format!("    {}\n", report);
+            }
+        }
+
+        if let Some(haptics) = &info.haptics {
+            out += "  haptics:\n";
+            out += &format!(
+                "    auto trigger control: {:02x}:{:04x}\n",
+                haptics.auto_trigger_control >> 16,
+                haptics.auto_trigger_control & 0xffff
+            );
+            for waveform in haptics.waveforms.iter() {
+                let duration = waveform
+                    .duration
+                    .map(|d| format!(" ({}ms)", d.as_millis()))
+                    .unwrap_or_default();
+                out += &format!(
+                    "    waveform {}: {:?}{}\n",
+                    waveform.ordinal, waveform.waveform, duration
+                );
+            }
+        }
+
+        if !info.haptic_report.is_empty() {
+            let bytes = info
+                .haptic_report
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>();
+            out += &format!("  feature report 2: {}\n", bytes.join(" "));
+        }
+    }
+    out
+}
+
+/// Send a command to the running daemon, returning its reply.
+pub fn request(command: &str) -> io::Result<String> {
+    let mut stream = UnixStream::connect(socket_path()?)?;
+    stream.write_all(format!("{}\n", command).as_bytes())?;
+
+    let mut reply = String::new();
+    stream.read_to_string(&mut reply)?;
+    Ok(reply)
+}
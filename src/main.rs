@@ -1,23 +1,45 @@
 #![deny(unsafe_code)]
 #![allow(clippy::collapsible_if, clippy::new_without_default)]
 
-pub mod common;
+mod bluez;
 mod config;
 pub mod controller;
 mod dial_device;
 mod error;
 mod fake_input;
+mod feedback;
+mod ipc;
+mod power;
 
-use std::sync::mpsc;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::Duration;
 
-use crate::controller::DialController;
-use crate::dial_device::DialDevice;
+use crate::bluez::Bluez;
+use crate::controller::{ControllerHandle, ControllerMsg, DialController, StateStore};
+use crate::dial_device::DialManager;
 use crate::error::{Error, Result};
 
-use notify_rust::{Hint, Notification, Timeout};
 use signal_hook::{iterator::Signals, SIGINT, SIGTERM};
 
 fn main() {
+    let args = std::env::args().collect::<Vec<_>>();
+    match args.get(1).map(String::as_str) {
+        None => {}
+        Some("check-config") => std::process::exit(check_config(args.get(2).map(Path::new))),
+        Some("status") => std::process::exit(ipc_command("status")),
+        Some("device-info") => std::process::exit(ipc_command("device-info")),
+        Some("bluetooth") => std::process::exit(bluetooth_command(&args[2..])),
+        Some(other) => {
+            eprintln!("unknown command {:?}", other);
+            eprintln!(
+                "usage: {} [check-config [path] | status | device-info | bluetooth [list | pair | connect [address]]]",
+                args[0]
+            );
+            std::process::exit(2);
+        }
+    }
+
     let (terminate_tx, terminate_rx) = mpsc::channel::<Result<()>>();
 
     std::thread::spawn({
@@ -31,10 +53,16 @@ fn main() {
         }
     });
 
+    // shared between every dial's controller, and loaded up front so that any
+    // pending changes can be flushed on the way out
+    let state = StateStore::from_disk().map(|state| Arc::new(Mutex::new(state)));
+    let exit_state = state.as_ref().ok().cloned();
+
     std::thread::spawn({
         let terminate_tx = terminate_tx;
         move || {
-            let _ = terminate_tx.send(controller_main());
+            let res = state.and_then(|state| controller_main(state, terminate_tx.clone()));
+            let _ = terminate_tx.send(res);
         }
     });
 
@@ -56,41 +84,291 @@ fn main() {
     };
 
     if !silent {
-        Notification::new()
-            .hint(Hint::Transient(true))
-            .hint(Hint::Category("device".into()))
-            .timeout(Timeout::Milliseconds(100))
-            .summary("Surface Dial")
-            .body(&msg)
-            .icon(icon)
-            .show()
-            .unwrap();
+        if let Err(e) = feedback::backend().message(&msg, icon) {
+            eprintln!("{}", e);
+        }
+    }
+
+    // controllers batch up state changes before writing them to disk
+    if let Some(Ok(mut state)) = exit_state.as_ref().map(|state| state.lock()) {
+        if state.is_dirty() {
+            if let Err(e) = state.to_disk() {
+                eprintln!("could not save mode state: {}", e);
+            }
+        }
     }
 
     // cleaning up threads is hard...
     std::process::exit(1);
 }
 
-fn controller_main() -> Result<()> {
+fn controller_main(
+    state: Arc<Mutex<StateStore>>,
+    terminate_tx: mpsc::Sender<Result<()>>,
+) -> Result<()> {
     println!("Started");
 
     let cfg = config::Config::from_disk()?;
+    feedback::configure(&cfg);
+
+    let manager = DialManager::new(cfg.long_press_timeout, cfg.input_backend, cfg.grab_input);
+
+    // notified whenever the system resumes from suspend
+    let bluetooth_resumed = if cfg.bluetooth_reconnect {
+        let (resumed_tx, resumed) = mpsc::channel();
+        std::thread::spawn(move || {
+            let res = Bluez::system().and_then(|bluez| bluez.reconnect_loop(resumed));
+            if let Err(e) = res {
+                eprintln!("the bluetooth reconnect worker died! {}", e);
+            }
+        });
+        Some(resumed_tx)
+    } else {
+        None
+    };
+
+    let status = ipc::StatusMap::default();
+
+    std::thread::spawn({
+        let status = status.clone();
+        move || {
+            if let Err(e) = ipc::serve(status) {
+                eprintln!("the IPC server died! {}", e);
+            }
+        }
+    });
+
+    // a handle to each dial's controller, and the most recently loaded config
+    // (for dials which have yet to connect).
+    //
+    // NOTE: always lock `handles` before `cfg`
+    let handles = Arc::new(Mutex::new(Vec::<ControllerHandle>::new()));
+    let cfg = Arc::new(Mutex::new(cfg));
+
+    std::thread::spawn({
+        let handles = handles.clone();
+        let cfg = cfg.clone();
+        move || {
+            let res = config::watch(|new_cfg| match new_cfg {
+                Ok(new_cfg) => {
+                    feedback::configure(&new_cfg);
+                    let handles = handles.lock().unwrap();
+                    for handle in handles.iter() {
+                        handle.send(ControllerMsg::ReloadConfig(new_cfg.clone()));
+                    }
+                    *cfg.lock().unwrap() = new_cfg;
+                }
+                Err(e) => {
+                    // keep on trucking with the existing config
+                    eprintln!("{}", e);
+                    let _ = feedback::backend().alert(&format!("Error: {}", e), "dialog-error");
+                }
+            });
 
-    let dial = DialDevice::new(std::time::Duration::from_millis(750))?;
-
-    let mut controller = DialController::new(
-        dial,
-        cfg.last_mode,
-        vec![
-            Box::new(controller::controls::Scroll::new()),
-            Box::new(controller::controls::ScrollMT::new()),
-            Box::new(controller::controls::Zoom::new()),
-            Box::new(controller::controls::Volume::new()),
-            Box::new(controller::controls::Media::new()),
-            Box::new(controller::controls::MediaWithVolume::new()),
-            Box::new(controller::controls::Paddle::new()),
-        ],
-    );
-
-    controller.run()
+            if let Err(e) = res {
+                eprintln!("the config watcher died! {}", e);
+            }
+        }
+    });
+
+    std::thread::spawn({
+        let handles = handles.clone();
+        move || {
+            let res = dbus::blocking::Connection::new_system()
+                .map_err(Error::Dbus)
+                .and_then(|conn| {
+                    power::watch_sleep(&conn, |event| {
+                        eprintln!("{:?}", event);
+                        let handles = handles.lock().unwrap().clone();
+                        on_sleep_event(event, &handles);
+                        if let (power::SleepEvent::Resume, Some(resumed)) =
+                            (event, &bluetooth_resumed)
+                        {
+                            let _ = resumed.send(());
+                        }
+                    })
+                });
+
+            if let Err(e) = res {
+                eprintln!("the suspend/resume watcher died! {}", e);
+            }
+        }
+    });
+
+    // each dial gets its own controller (running on its own thread)
+    loop {
+        let dial = manager.next_dial()?;
+
+        std::thread::spawn({
+            let terminate_tx = terminate_tx.clone();
+            let handles = handles.clone();
+            let cfg = cfg.clone();
+            let state = state.clone();
+            let status = status.clone();
+            move || {
+                let controller = {
+                    let mut handles = handles.lock().unwrap();
+                    let cfg = cfg.lock().unwrap().clone();
+                    DialController::new(dial, cfg, state, status)
+                        .inspect(|controller| handles.push(controller.handle()))
+                };
+
+                let _ = terminate_tx.send(controller.and_then(|mut c| c.run()));
+            }
+        });
+    }
+}
+
+/// Forward suspend / resume events to every controller.
+///
+/// Before suspending, waits (briefly) for each controller to release any
+/// inputs it's holding down.
+fn on_sleep_event(event: power::SleepEvent, handles: &[ControllerHandle]) {
+    match event {
+        power::SleepEvent::Suspend => {
+            let (done_tx, done) = mpsc::channel();
+            for handle in handles {
+                handle.send(ControllerMsg::Suspend(done_tx.clone()));
+            }
+            drop(done_tx);
+
+            for _ in handles {
+                if done.recv_timeout(Duration::from_secs(1)).is_err() {
+                    break;
+                }
+            }
+        }
+        power::SleepEvent::Resume => {
+            for handle in handles {
+                handle.send(ControllerMsg::Resume);
+            }
+        }
+    }
+}
+
+/// Validate a config file (defaulting to the one the daemon would load),
+/// printing any errors. Returns the process exit code.
+fn check_config(path: Option<&Path>) -> i32 {
+    let default_path;
+    let path = match path {
+        Some(path) => path,
+        None => match config::config_file_path() {
+            Ok(path) => {
+                default_path = path;
+                &default_path
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        },
+    };
+
+    if !path.exists() {
+        println!(
+            "{}: no such file (the default config will be used)",
+            path.display()
+        );
+        return 0;
+    }
+
+    match config::Config::from_file(path) {
+        Ok(cfg) => {
+            let ids = cfg.modes.iter().map(|m| m.id.as_str()).collect::<Vec<_>>();
+            println!("{}: OK (modes: {})", path.display(), ids.join(", "));
+            0
+        }
+        Err(Error::Config(errors)) => {
+            eprintln!("{}", errors);
+            1
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Manage dials through BlueZ (doesn't require the daemon to be running).
+/// Returns the process exit code.
+fn bluetooth_command(args: &[String]) -> i32 {
+    let res = Bluez::system().and_then(|bluez| {
+        match args.first().map(String::as_str) {
+            None | Some("list") => {
+                let dials = bluez.devices()?.into_iter().filter(|d| d.is_dial());
+                let mut found = false;
+                for dial in dials {
+                    found = true;
+                    println!(
+                        "{} {} {}{}",
+                        dial.address,
+                        if dial.paired { "paired" } else { "unpaired" },
+                        if dial.connected {
+                            "connected"
+                        } else {
+                            "disconnected"
+                        },
+                        match dial.rssi {
+                            Some(rssi) => format!(" rssi={}dBm", rssi),
+                            None => "".into(),
+                        }
+                    );
+                }
+                if !found {
+                    println!("no dials found (use `bluetooth pair` to pair a new dial)");
+                }
+            }
+            Some("pair") => {
+                println!("hold down the dial's pairing button (next to the batteries)...");
+                let dial = bluez.discover(Duration::from_secs(60))?;
+                println!("found {}, pairing...", dial.address);
+                bluez.pair(&dial)?;
+                println!("paired with {}", dial.address);
+            }
+            Some("connect") => match args.get(1) {
+                Some(address) => {
+                    let dial = bluez.device(address)?.ok_or(Error::MissingDial)?;
+                    bluez.connect(&dial)?;
+                    println!("connected to {}", dial.address);
+                }
+                None => {
+                    let reconnected = bluez.reconnect_dials()?;
+                    if reconnected.is_empty() {
+                        println!("no disconnected dials found");
+                    }
+                    for address in reconnected {
+                        println!("connected to {}", address);
+                    }
+                }
+            },
+            Some(other) => {
+                eprintln!("unknown bluetooth command {:?}", other);
+                return Ok(2);
+            }
+        }
+        Ok(0)
+    });
+
+    match res {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// Send a command to the running daemon, printing its reply. Returns the
+/// process exit code.
+fn ipc_command(command: &str) -> i32 {
+    match ipc::request(command) {
+        Ok(reply) => {
+            print!("{}", reply);
+            0
+        }
+        Err(e) => {
+            eprintln!("could not connect to the daemon: {}", e);
+            1
+        }
+    }
 }
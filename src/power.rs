@@ -0,0 +1,205 @@
+//! System suspend / resume notifications, via logind's `PrepareForSleep`
+//! signal.
+
+use std::sync::mpsc;
+use std::time::Duration;
+
+use dbus::arg::OwnedFd;
+use dbus::blocking::Connection;
+use dbus::message::MatchRule;
+
+use crate::error::{Error, Result};
+
+const LOGIND: &str = "org.freedesktop.login1";
+const LOGIND_PATH: &str = "/org/freedesktop/login1";
+const LOGIND_MANAGER: &str = "org.freedesktop.login1.Manager";
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SleepEvent {
+    /// The system is about to suspend.
+    Suspend,
+    /// The system has just resumed.
+    Resume,
+}
+
+/// Invoke `f` whenever the system is about to suspend, or has just resumed.
+///
+/// A "delay" inhibitor lock is held while the system is awake, so the system
+/// won't suspend until `f` has returned (or logind's `InhibitDelayMaxSec`
+/// elapses).
+///
+/// `conn` is typically the system bus, but any connection will do (e.g: a
+/// private bus with a stand-in logind). Blocks forever (unless an error
+/// occurs).
+pub fn watch_sleep(conn: &Connection, mut f: impl FnMut(SleepEvent)) -> Result<()> {
+    let (sleeping_tx, sleeping) = mpsc::channel();
+    conn.add_match(
+        MatchRule::new_signal(LOGIND_MANAGER, "PrepareForSleep"),
+        move |(sleeping,): (bool,), _, _| {
+            let _ = sleeping_tx.send(sleeping);
+            true
+        },
+    )
+    .map_err(Error::Dbus)?;
+
+    let mut sleep = SleepState::new(inhibit(conn));
+    loop {
+        conn.process(Duration::from_secs(60)).map_err(Error::Dbus)?;
+
+        while let Ok(sleeping) = sleeping.try_recv() {
+            sleep.prepare_for_sleep(sleeping, || inhibit(conn), &mut f);
+        }
+    }
+}
+
+/// Keeps the inhibitor lock (`L`) in step with the system's sleep state.
+struct SleepState<L> {
+    asleep: bool,
+    inhibitor: Option<L>,
+}
+
+impl<L> SleepState<L> {
+    fn new(inhibitor: Option<L>) -> SleepState<L> {
+        SleepState {
+            asleep: false,
+            inhibitor,
+        }
+    }
+
+    /// Handle a `PrepareForSleep` signal. Signals which don't change anything
+    /// (e.g: a resume without a matching suspend) are ignored.
+    fn prepare_for_sleep(
+        &mut self,
+        sleeping: bool,
+        inhibit: impl FnOnce() -> Option<L>,
+        f: &mut impl FnMut(SleepEvent),
+    ) {
+        if sleeping == self.asleep {
+            return;
+        }
+        self.asleep = sleeping;
+
+        if sleeping {
+            f(SleepEvent::Suspend);
+            // closing the lock lets the system go to sleep
+            drop(self.inhibitor.take());
+        } else {
+            // taken first, so that the lock is already held if the system
+            // goes straight back to sleep
+            self.inhibitor = inhibit();
+            f(SleepEvent::Resume);
+        }
+    }
+}
+
+/// Take a "delay" inhibitor lock, which is held until the returned fd is
+/// closed.
+///
+/// Not fatal if this fails: the system just won't wait for the daemon before
+/// going to sleep.
+fn inhibit(conn: &Connection) -> Option<OwnedFd> {
+    let res = conn
+        .with_proxy(LOGIND, LOGIND_PATH, Duration::from_secs(2))
+        .method_call(
+            LOGIND_MANAGER,
+            "Inhibit",
+            (
+                "sleep",
+                "surface-dial-daemon",
+                "Release held inputs",
+                "delay",
+            ),
+        );
+
+    match res {
+        Ok((fd,)) => Some(fd),
+        Err(e) => {
+            eprintln!("could not take an inhibitor lock: {}", e);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    type Log = Rc<RefCell<Vec<&'static str>>>;
+
+    /// A stand-in inhibitor lock, which logs when it's released.
+    struct Lock(Log);
+
+    impl Drop for Lock {
+        fn drop(&mut self) {
+            self.0.borrow_mut().push("release");
+        }
+    }
+
+    /// Runs a sequence of `PrepareForSleep` signals, logging when the lock is
+    /// taken / released, and when events are reported.
+    fn run(signals: &[bool]) -> Vec<&'static str> {
+        let log = Log::default();
+        let mut sleep = SleepState::new(Some(Lock(log.clone())));
+        let mut f = |event| {
+            log.borrow_mut().push(match event {
+                SleepEvent::Suspend => "suspend",
+                SleepEvent::Resume => "resume",
+            })
+        };
+
+        for &sleeping in signals {
+            sleep.prepare_for_sleep(
+                sleeping,
+                || {
+                    log.borrow_mut().push("inhibit");
+                    Some(Lock(log.clone()))
+                },
+                &mut f,
+            );
+        }
+
+        let log = log.borrow().clone();
+        log
+    }
+
+    #[test]
+    fn suspend_then_resume() {
+        assert_eq!(
+            run(&[true, false]),
+            ["suspend", "release", "inhibit", "resume"]
+        );
+    }
+
+    #[test]
+    fn repeated_cycles() {
+        assert_eq!(
+            run(&[true, false, true, false]),
+            [
+                "suspend", "release", "inhibit", "resume", "suspend", "release", "inhibit",
+                "resume"
+            ]
+        );
+    }
+
+    #[test]
+    fn redundant_signals_ignored() {
+        assert_eq!(run(&[false]), [] as [&str; 0]);
+        assert_eq!(
+            run(&[true, true, false, false]),
+            ["suspend", "release", "inhibit", "resume"]
+        );
+    }
+
+    #[test]
+    fn without_inhibitor() {
+        let mut sleep = SleepState::<()>::new(None);
+        let mut events = Vec::new();
+        sleep.prepare_for_sleep(true, || None, &mut |event| events.push(event));
+        sleep.prepare_for_sleep(false, || None, &mut |event| events.push(event));
+
+        assert_eq!(events, [SleepEvent::Suspend, SleepEvent::Resume]);
+    }
+}